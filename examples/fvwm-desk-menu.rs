@@ -1,7 +1,7 @@
 use xdg_desktop::dirs::xdg_data_dirs;
-use xdg_desktop::icon::IconIndex;
+use xdg_desktop::icon::{current_theme_name, IconIndex};
 use xdg_desktop::menu::{MenuPrinter, MenuItem, MenuItemDetail, MenuIndex};
-use std::{env, path::Path, process::Command, fs};
+use std::{env, path::Path, fs};
 use std::io;
 
 struct FvwmMenuPrinter<'a> {
@@ -13,6 +13,27 @@ struct FvwmMenuPrinter<'a> {
     menu_stack: Vec<String>,
 }
 
+#[cfg(feature = "raster")]
+fn scale_icon(src: &Path, size: usize, dest: &Path) -> Result<(), io::Error> {
+    xdg_desktop::icon::scale_to_png(src, size, dest)
+}
+
+// Call imagemagick convert to scale the image.
+#[cfg(not(feature = "raster"))]
+fn scale_icon(src: &Path, size: usize, dest: &Path) -> Result<(), io::Error> {
+    let status = std::process::Command::new("convert")
+	.arg("-background").arg("none")
+	.arg("-resize").arg(format!("{}x{}", size, size))
+	.arg(src)
+	.arg(dest)
+	.status()?;
+    if !status.success() {
+	Err(io::Error::other("convert failed"))
+    } else {
+	Ok(())
+    }
+}
+
 impl<'a> FvwmMenuPrinter<'a> {
     fn new<'b, PathIterator>(icon_theme: String, paths: PathIterator, desire_icon_size: usize, menu_index: &'a MenuIndex) -> Self
     where PathIterator: Iterator<Item = &'b Path> {
@@ -33,7 +54,7 @@ impl<'a> FvwmMenuPrinter<'a> {
     fn ensure_all_icons(&self) {
 	for item in &self.menu_index.items {
 	    if let Err(err) = self.ensure_icon(&item.icon) {
-		eprintln!("Error when converting icons {} {}", &item.icon, err.to_string());
+		eprintln!("Error when converting icons {} {}", &item.icon, err);
 	    }
 	}
     }
@@ -57,7 +78,6 @@ impl<'a> FvwmMenuPrinter<'a> {
 	    }
 	}
 
-	// Call imagemagick convert to scale the image.
 	let icon = &icons[idx as usize];
 	let output_filename = format!("{}/.fvwm/icons/{}/{}.png", env::var("HOME").unwrap(), self.desire_icon_size, &icon.name);
 
@@ -70,22 +90,11 @@ impl<'a> FvwmMenuPrinter<'a> {
 	    }
 	}
 
-	let result = Command::new("convert")
-	    .arg("-resize").arg(format!("{}x{}", self.desire_icon_size, self.desire_icon_size))
-	    .arg(icon.path.to_str().unwrap())
-	    .arg(&output_filename)
-	    .spawn();
-	if !result?.wait()?.success() {
-	    Err(io::Error::new(io::ErrorKind::Other, "convert failed"))
-	} else {
-	    Ok(())
-	}
+	scale_icon(&icon.path, self.desire_icon_size, Path::new(&output_filename))
     }
 
     fn resolve_icon(&self, name: &str) -> Option<String> {
-	let Some(icons) = self.icon_index.index.get(name) else {
-	    return None;
-	};
+	let icons = self.icon_index.index.get(name)?;
 	for icon in icons {
 	    let Some(pixel_size) = icon.pixel_size() else {
 		return Some(format!("{}:{}x{}", icon.path.to_str().unwrap(), self.desire_icon_size, self.desire_icon_size));
@@ -94,7 +103,7 @@ impl<'a> FvwmMenuPrinter<'a> {
 		return Some(String::from(icon.path.to_str().unwrap()));
 	    }
 	}
-	return Some(format!("{}/.fvwm/icons/{}/{}.png", env::var("HOME").unwrap(), self.desire_icon_size, &name));
+	Some(format!("{}/.fvwm/icons/{}/{}.png", env::var("HOME").unwrap(), self.desire_icon_size, &name))
     }
 
     fn print_wmclass_icons(&self) {
@@ -145,12 +154,15 @@ impl<'a> MenuPrinter for FvwmMenuPrinter<'a> {
 }
 
 fn main() {
-    let icon_theme = env::args().nth(1).unwrap().to_string();
+    let Some(icon_theme) = env::args().nth(1).or_else(current_theme_name) else {
+	eprintln!("Cannot detect the current icon theme");
+	return;
+    };
     let mut index = MenuIndex::new_default();
 
     index.scan();
     let paths = xdg_data_dirs();
-    let mut printer = FvwmMenuPrinter::new(icon_theme, paths.iter().map(|s| Path::new(s)), 64, &index);
+    let mut printer = FvwmMenuPrinter::new(icon_theme, paths.iter().map(Path::new), 64, &index);
     printer.ensure_all_icons();
 
     index.print(&mut printer);
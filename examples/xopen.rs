@@ -1,16 +1,35 @@
-use std::{collections::BTreeMap, env, io::stdin, iter, path::{Path, PathBuf}, process::Command};
-use glob::Pattern;
-use xdg_desktop::{menu::MenuIndex, mime_glob::mime_glob_foreach};
+use std::{collections::BTreeMap, env, io::{stdin, Read, Write}, path::{Path, PathBuf}};
+use xdg_desktop::{dirs, mime_db::MimeDatabase, mime_type::MimeType, open::{Launch, Opener}, recent::RecentFiles};
 
 fn show_usage() {
-    println!("{} [-s -u] file1 [file2 file3 ...]\n\n", env::args().nth(0).unwrap());
+    println!("{} [-s -u] file1 [file2 file3 ...]\n\n", env::args().next().unwrap());
+    println!(" -: Open data piped on stdin.\n");
     println!(" -s: Select which app to open.\n");
     println!(" -u: Save the select app as the default when using with -s.\n");
 }
 
+// Applications need a file, so stdin is saved to one. It is left behind since the
+// application opens it after we exit.
+fn save_stdin() -> Option<(PathBuf, String)> {
+    let mut data = vec![];
+    if stdin().read_to_end(&mut data).is_err() {
+        eprintln!("Cannot read stdin");
+        return None;
+    }
+    let mime = MimeDatabase::shared()?.guess_reader(data.as_slice()).ok()?.mime;
+    match dirs::create_runtime_file("xopen-stdin-").and_then(|(path, mut file)| file.write_all(&data).map(|_| path)) {
+        Ok(path) => Some((path, mime)),
+        Err(err) => {
+            eprintln!("Cannot save stdin: {}", err);
+            None
+        }
+    }
+}
+
 fn main() {
     let mut select_app = false;
     let mut save_selection = false;
+    let mut stdin_file: Option<(PathBuf, String)> = None;
     let paths: Vec<PathBuf> = env::args().skip(1).filter_map(|pstr| {
         if pstr == "-s" {
             select_app = true;
@@ -18,6 +37,9 @@ fn main() {
         } else if pstr == "-u" {
             save_selection = true;
             return None;
+        } else if pstr == "-" {
+            stdin_file = save_stdin();
+            return stdin_file.as_ref().map(|(path, _)| path.clone());
         }
         let path = Path::new(&pstr);
         let pathbuf = if path.is_symlink() {
@@ -30,7 +52,7 @@ fn main() {
             path.to_path_buf()
         };
         let path = Path::new(&pathbuf);
-        if !path.exists() || !path.is_file() {
+        if !path.exists() {
             eprintln!("Path {} does not exist", path.display());
             return None;
         }
@@ -43,51 +65,32 @@ fn main() {
         return;
     }
 
-    let mut mimes: Vec<String> = Vec::with_capacity(paths.len());
-    let mut nr_matches = 0;
-    mimes.extend(iter::repeat(String::new()).take(paths.len()));
-    mime_glob_foreach(|_, m, pattern| {
-        let ptn = Pattern::new(pattern).unwrap();
-
-        for i in 0..paths.len() {
-            if !mimes[i].is_empty() {
-                continue;
-            }
-
-            let filename = paths[i].file_name().unwrap().to_str().unwrap();
-            if ptn.matches(filename) {
-                mimes[i] = m.clone();
-                nr_matches += 1;
-            }
-        }
-
-        nr_matches < paths.len()
-    }).expect("Cannot find mime type for file");
-
-    let mut index = MenuIndex::new_default();
-    index.scan();
+    let mimes: Vec<Option<String>> = paths.iter().map(|path| match &stdin_file {
+        Some((stdin_path, mime)) if stdin_path == path => Some(mime.clone()),
+        _ => Opener::mime_type(path),
+    }).collect();
 
-    let mut assoc_map: BTreeMap<usize, Vec<&PathBuf>> = BTreeMap::new();
+    let mut opener = Opener::new();
+    let mut assoc_map: BTreeMap<usize, Vec<PathBuf>> = BTreeMap::new();
+    let mut recent = RecentFiles::load().inspect_err(|err| eprintln!("Cannot read recent files: {}", err)).ok();
 
     for i in 0..mimes.len() {
-        let mime = mimes[i].as_str();
-        if mime.is_empty() {
+        let Some(mime) = mimes[i].as_deref().and_then(MimeType::parse) else {
             println!("Cannot find MIME type for {}", &paths[i].display());
             continue;
-        }
-        let Some(assoc) = index.mime_assoc_index.get(mime) else {
+        };
+        let Some(assoc) = opener.index.mime_assoc_index.get(&mime) else {
             println!("Cannot find any associate app for {}", &paths[i].display());
             continue;
         };
         let idx;
-        if !select_app && assoc.default.is_some() {
-            let default_idx = assoc.default.unwrap();
-            println!("Using Default: {}", &index.items[default_idx].name);
+        if let (false, Some(default_idx)) = (select_app, assoc.default) {
+            println!("Using Default: {}", &opener.index.items[default_idx].name);
             idx = default_idx;
         } else {
             println!("No default app for {}. Select from the following apps:", mime);
             for j in 0..assoc.all.len() {
-                println!("{}. {}", j, &index.items[assoc.all[j]].name);
+                println!("{}. {}", j, &opener.index.items[assoc.all[j]].name);
             }
             let mut user_input = String::new();
             if stdin().read_line(&mut user_input).is_err() {
@@ -103,30 +106,31 @@ fn main() {
             }
             idx = assoc.all[sel];
             if save_selection {
-                index.change_default_assoc(mime, idx);
+                opener.index.change_default_assoc(&mime, idx);
             }
         }
-        if assoc_map.get_mut(&idx).map(|v| {v.push(&paths[i]);}).is_none() {
-            assoc_map.insert(idx, vec![&paths[i]]);
+        let is_stdin = stdin_file.as_ref().is_some_and(|(path, _)| *path == paths[i]);
+        if let (false, Some(recent), Some(entry)) = (is_stdin, recent.as_mut(), opener.index.items[idx].detail_entry()) {
+            recent.record(&paths[i], mime.as_str(), &opener.index.items[idx].basename, &entry.exec);
         }
+        assoc_map.entry(idx).or_default().push(paths[i].clone());
     }
 
-    let cmds = assoc_map.iter().map(|(idx, v)| {
-        let item = &index.items[*idx];
-        item.detail_entry().unwrap().exec_with_filenames(v)
-    }).flatten().collect::<Vec<String>>();
+    let launches: Vec<Launch> = assoc_map.iter().filter_map(|(idx, paths)| opener.launch_with(*idx, paths)).collect();
 
     println!("Will execute the following command(s):");
-    for cmd in &cmds {
-        println!("{}", cmd);
-        let Ok(_) = Command::new("/bin/sh").arg("-c").arg(cmd).spawn() else {
+    for launch in &launches {
+        for cmd in &launch.commands {
+            println!("{}", cmd);
+        }
+        if launch.spawn().is_err() {
             eprintln!("Fail to execute command");
-            continue;
-        };
+        }
     }
     if save_selection {
-        index.write_default_assoc().unwrap();
+        opener.index.write_default_assoc().unwrap();
+    }
+    if let Some(Err(err)) = recent.as_mut().map(RecentFiles::save) {
+        eprintln!("Cannot save recent files: {}", err);
     }
-
-    return;
 }
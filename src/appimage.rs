@@ -0,0 +1,199 @@
+use std::fs::{self, File};
+use std::io::{Read, Result, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::SystemTime;
+
+use log::warn;
+
+use crate::desktop_entry::{escape_value, DesktopEntryBuilder};
+use crate::dirs::BaseDirs;
+
+// The AppImages directly in `dir`, by name.
+pub fn find_appimages(dir: &Path) -> Vec<PathBuf> {
+    let Ok(read_dir) = dir.read_dir() else {
+        return vec![];
+    };
+    let mut paths: Vec<PathBuf> = read_dir.flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("appimage")))
+        .collect();
+    paths.sort();
+
+    paths
+}
+
+// The end of the ELF runtime of a type 2 AppImage, where its squashfs image starts: the end
+// of the section header table, the last part of the runtime.
+fn squashfs_offset(path: &Path) -> Option<u64> {
+    let mut header = [0u8; 64];
+    File::open(path).ok()?.read_exact(&mut header).ok()?;
+    if &header[..4] != b"\x7fELF" {
+        return None;
+    }
+    let is_le = header[5] == 1;
+    let u16_at = |at: usize| {
+        let bytes = [header[at], header[at + 1]];
+        (if is_le { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) }) as u64
+    };
+    let u32_at = |at: usize| {
+        let bytes = header[at..at + 4].try_into().unwrap();
+        (if is_le { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) }) as u64
+    };
+    let u64_at = |at: usize| {
+        let bytes = header[at..at + 8].try_into().unwrap();
+        if is_le { u64::from_le_bytes(bytes) } else { u64::from_be_bytes(bytes) }
+    };
+    let (sh_off, sh_entsize, sh_num) = match header[4] {
+        1 => (u32_at(0x20), u16_at(0x2e), u16_at(0x30)),
+        2 => (u64_at(0x28), u16_at(0x3a), u16_at(0x3c)),
+        _ => return None,
+    };
+
+    Some(sh_off + sh_entsize * sh_num)
+}
+
+// Whether the squashfs image of the AppImage starts at `offset`.
+fn has_squashfs_at(path: &Path, offset: u64) -> bool {
+    let mut magic = [0u8; 4];
+    File::open(path).and_then(|mut file| {
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut magic)
+    }).is_ok() && &magic == b"hsqs"
+}
+
+// Unpacks the top level desktop files and icons of the AppImage into `dest` with
+// unsquashfs, without running the AppImage itself.
+fn extract_metadata(path: &Path, dest: &Path) -> bool {
+    let Some(offset) = squashfs_offset(path).filter(|offset| has_squashfs_at(path, *offset)) else {
+        return false;
+    };
+    let _ = fs::remove_dir_all(dest);
+
+    Command::new("unsquashfs")
+        .arg("-offset").arg(offset.to_string())
+        .args(["-no-progress", "-no-xattrs", "-force", "-dest"]).arg(dest)
+        .arg(path)
+        .args(["*.desktop", "*.png", "*.svg", "*.xpm", ".DirIcon"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+// `path` quoted as an Exec argument, before the string value escapes.
+fn quote_exec_arg(path: &Path) -> String {
+    let mut quoted = String::from("\"");
+    for ch in path.to_string_lossy().chars() {
+        if matches!(ch, '"' | '`' | '$' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(ch);
+    }
+    quoted.push('"');
+
+    quoted
+}
+
+// The embedded desktop file with its programs pointed at the AppImage: Exec lines run it
+// with their original arguments, TryExec goes, and Icon becomes the extracted file.
+fn rewrite_desktop_file(content: &str, appimage: &Path, icon: Option<&Path>) -> String {
+    let exec = escape_value(&quote_exec_arg(appimage));
+    let mut lines = vec![];
+    for line in content.lines() {
+        let key = line.split('=').next().unwrap_or("").trim();
+        let value = line.split_once('=').map(|(_, value)| value.trim()).unwrap_or("");
+        match (key, icon) {
+            ("Exec", _) => match value.split_once(' ') {
+                Some((_, args)) => lines.push(format!("Exec={} {}", exec, args)),
+                None => lines.push(format!("Exec={}", exec)),
+            },
+            ("TryExec", _) => {}
+            ("Icon", Some(icon)) => lines.push(format!("Icon={}", escape_value(&icon.to_string_lossy()))),
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    lines.join("\n") + "\n"
+}
+
+// The icon the embedded desktop file names, or .DirIcon.
+fn extracted_icon(dir: &Path, content: &str) -> Option<PathBuf> {
+    let name = content.lines()
+        .filter_map(|line| line.split_once('='))
+        .find(|(key, _)| key.trim() == "Icon")
+        .map(|(_, value)| value.trim().to_string());
+    let candidates = name.iter()
+        .flat_map(|name| ["png", "svg", "xpm"].map(|ext| dir.join(format!("{}.{}", name, ext))))
+        .chain([dir.join(".DirIcon")]);
+
+    candidates.into_iter().find(|path| path.is_file())
+}
+
+// "appimage-Krita-5.2.2-x86_64" for Krita-5.2.2-x86_64.AppImage.
+fn desktop_id(appimage: &Path) -> String {
+    let stem = appimage.file_stem().unwrap_or_default().to_string_lossy();
+    let stem: String = stem.chars()
+        .map(|ch| if ch.is_ascii_alphanumeric() || "-_.".contains(ch) { ch } else { '_' })
+        .collect();
+
+    format!("appimage-{}", stem)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+// A desktop file for the AppImage under the cache dir, made from the one it embeds, or a
+// minimal one named after the file when it has none or unsquashfs is missing. It is made
+// again when the AppImage is newer.
+pub fn desktop_file_for(base_dirs: &BaseDirs, appimage: &Path) -> Result<PathBuf> {
+    let appimage = std::path::absolute(appimage)?;
+    let cache_dir = base_dirs.cache_home().join("appimages");
+    let id = desktop_id(&appimage);
+    let desktop_path = cache_dir.join(format!("{}.desktop", id));
+    if modified(&desktop_path).is_some_and(|made| modified(&appimage).is_some_and(|changed| changed <= made)) {
+        return Ok(desktop_path);
+    }
+    fs::create_dir_all(&cache_dir)?;
+
+    let extract_dir = cache_dir.join(&id);
+    let embedded = extract_metadata(&appimage, &extract_dir).then(|| {
+        let mut desktop_files: Vec<PathBuf> = extract_dir.read_dir().ok()?.flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "desktop"))
+            .collect();
+        desktop_files.sort();
+        fs::read_to_string(desktop_files.first()?).ok()
+    }).flatten();
+    let content = match embedded {
+        Some(content) => rewrite_desktop_file(&content, &appimage, extracted_icon(&extract_dir, &content).as_deref()),
+        None => {
+            let name = appimage.file_stem().unwrap_or_default().to_string_lossy();
+            let mut builder = DesktopEntryBuilder::new(&name).exec(&quote_exec_arg(&appimage));
+            if let Some(icon) = extracted_icon(&extract_dir, "") {
+                builder = builder.icon(&icon.to_string_lossy());
+            }
+            builder.to_desktop_file()
+        }
+    };
+
+    let tmp = cache_dir.join(format!(".{}.desktop.{}", id, std::process::id()));
+    fs::write(&tmp, content)?;
+    fs::rename(&tmp, &desktop_path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })?;
+
+    Ok(desktop_path)
+}
+
+// Desktop files for the AppImages in `dirs`, e.g. ~/Applications, to scan along with the
+// applications dirs.
+pub fn desktop_files_for(base_dirs: &BaseDirs, dirs: &[PathBuf]) -> Vec<PathBuf> {
+    dirs.iter()
+        .flat_map(|dir| find_appimages(dir))
+        .filter_map(|appimage| desktop_file_for(base_dirs, &appimage)
+            .inspect_err(|err| warn!("Cannot make a desktop file for {}: {}", appimage.display(), err))
+            .ok())
+        .collect()
+}
@@ -0,0 +1,205 @@
+use std::collections::HashSet;
+use std::env;
+use std::fs::{self, File};
+use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Child;
+
+use log::warn;
+
+use crate::desktop_parser::{DesktopFile, DesktopParserCallback};
+use crate::dirs::BaseDirs;
+use crate::scope;
+use crate::session;
+use crate::terminal;
+
+// An autostart .desktop file that applies to the current desktop.
+#[derive(Clone, Debug)]
+pub struct AutostartEntry {
+    // The file name, which shadows files of the same name in lower precedence dirs.
+    pub id: String,
+    pub path: PathBuf,
+    pub name: String,
+    pub exec: String,
+    pub working_dir: Option<PathBuf>,
+    pub terminal: bool,
+}
+
+impl AutostartEntry {
+    // Exec without field codes, as there are no files to open.
+    pub fn command_line(&self) -> String {
+        strip_field_codes(&self.exec)
+    }
+
+    // Entries with Terminal=true run in the default terminal, if there is one.
+    pub fn launch(&self) -> io::Result<Child> {
+        let mut command_line = self.command_line();
+        if self.terminal {
+            if let Some(terminal) = terminal::default() {
+                command_line = terminal.wrap(&command_line);
+            }
+        }
+        let mut cmd = scope::shell_command(&self.id, &command_line);
+        if let Some(dir) = self.working_dir.as_ref().filter(|dir| dir.is_dir()) {
+            cmd.current_dir(dir);
+        }
+
+        cmd.spawn()
+    }
+}
+
+// Drops the %f, %U, ... codes of an Exec value and unescapes %%.
+pub(crate) fn strip_field_codes(exec: &str) -> String {
+    let mut cmd = String::new();
+    let mut chars = exec.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '%' {
+            cmd.push(ch);
+        } else if let Some('%') = chars.next() {
+            cmd.push('%');
+        }
+    }
+
+    cmd.trim().to_string()
+}
+
+#[derive(Default)]
+struct AutostartParser {
+    in_entry: bool,
+    key: String,
+    app_type: String,
+    name: String,
+    exec: String,
+    try_exec: String,
+    working_dir: String,
+    terminal: bool,
+    hidden: bool,
+    only_show_in: Vec<String>,
+    not_show_in: Vec<String>,
+}
+
+fn decode_list(value: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(value).split(';').filter(|s| !s.is_empty()).map(str::to_string).collect()
+}
+
+impl DesktopParserCallback for AutostartParser {
+    fn on_section(&mut self, name: &[u8]) -> bool {
+        self.in_entry = name == b"Desktop Entry";
+
+        true
+    }
+    fn on_key(&mut self, key: &[u8]) -> bool {
+        self.key = String::from_utf8_lossy(key).trim().to_string();
+
+        true
+    }
+    fn on_value(&mut self, value: &[u8]) -> bool {
+        if !self.in_entry {
+            return true;
+        }
+        let value = value.trim_ascii();
+        match self.key.as_str() {
+            "Type" => self.app_type = String::from_utf8_lossy(value).into_owned(),
+            "Name" => self.name = String::from_utf8_lossy(value).into_owned(),
+            "Exec" => self.exec = String::from_utf8_lossy(value).into_owned(),
+            "TryExec" => self.try_exec = String::from_utf8_lossy(value).into_owned(),
+            "Path" => self.working_dir = String::from_utf8_lossy(value).into_owned(),
+            "Terminal" => self.terminal = value.eq_ignore_ascii_case(b"true"),
+            "Hidden" => self.hidden = value.eq_ignore_ascii_case(b"true"),
+            "OnlyShowIn" => self.only_show_in = decode_list(value),
+            "NotShowIn" => self.not_show_in = decode_list(value),
+            _ => {}
+        }
+
+        true
+    }
+}
+
+// The desktops named by $XDG_CURRENT_DESKTOP, e.g. ["GNOME", "Unity"].
+pub fn current_desktops() -> Vec<String> {
+    session::current().current_desktops
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+// Without execute bits, any file will do.
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|meta| meta.is_file())
+}
+
+// Whether TryExec names an executable, either by absolute path or on $PATH.
+pub(crate) fn can_exec(program: &str) -> bool {
+    if program.contains('/') {
+        return is_executable(Path::new(program));
+    }
+
+    env::var_os("PATH").is_some_and(|paths| env::split_paths(&paths).any(|dir| is_executable(&dir.join(program))))
+}
+
+fn read_entry(id: &str, path: &Path, desktops: &[String]) -> Option<AutostartEntry> {
+    let mut parser = AutostartParser::default();
+    DesktopFile::new(File::open(path).ok()?).ok()?.parse(&mut parser);
+
+    let shown_in = |list: &[String]| list.iter().any(|name| desktops.contains(name));
+    if parser.hidden || parser.exec.is_empty() || (!parser.app_type.is_empty() && parser.app_type != "Application") {
+        return None;
+    }
+    if (!parser.only_show_in.is_empty() && !shown_in(&parser.only_show_in)) || shown_in(&parser.not_show_in) {
+        return None;
+    }
+    if !parser.try_exec.is_empty() && !can_exec(&parser.try_exec) {
+        return None;
+    }
+
+    Some(AutostartEntry {
+        id: id.to_string(),
+        path: path.to_path_buf(),
+        name: parser.name,
+        exec: parser.exec,
+        working_dir: (!parser.working_dir.is_empty()).then(|| PathBuf::from(parser.working_dir)),
+        terminal: parser.terminal,
+    })
+}
+
+// The entries of the autostart dirs under `base_dirs` to start on `desktops`. A file shadows
+// those of the same name in lower precedence dirs, even when it is hidden, which is how
+// users disable system autostart entries.
+pub fn entries_for(base_dirs: &BaseDirs, desktops: &[String]) -> Vec<AutostartEntry> {
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut result = vec![];
+    for dir in base_dirs.config_dirs_in_precedence() {
+        let Ok(read_dir) = dir.join("autostart").read_dir() else {
+            continue;
+        };
+        let mut files: Vec<(String, PathBuf)> = read_dir.flatten()
+            .filter_map(|ent| Some((ent.file_name().into_string().ok()?, ent.path())))
+            .filter(|(id, _)| id.ends_with(".desktop"))
+            .collect();
+        files.sort();
+        for (id, path) in files {
+            if seen.insert(id.clone()) {
+                result.extend(read_entry(&id, &path, desktops));
+            }
+        }
+    }
+
+    result
+}
+
+// What to start at login on the current desktop.
+pub fn entries() -> Vec<AutostartEntry> {
+    entries_for(&BaseDirs::new(), &current_desktops())
+}
+
+// Starts every entry, reporting those that fail to spawn.
+pub fn launch_all(entries: &[AutostartEntry]) -> Vec<Child> {
+    entries.iter().filter_map(|entry| {
+        entry.launch().inspect_err(|err| warn!("Cannot start {}: {}", entry.id, err)).ok()
+    }).collect()
+}
@@ -0,0 +1,23 @@
+use std::{env, process::exit};
+use xdg_desktop::cli::{self, CommandError};
+
+fn show_usage() -> ! {
+    eprintln!("Usage: validate-desktop [--no-warnings] FILE...");
+    eprintln!("Checks .desktop and .directory files against the Desktop Entry spec. Exits with 1");
+    eprintln!("if any file has errors.");
+    exit(2);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match cli::validate(&args.iter().map(String::as_str).collect::<Vec<&str>>()) {
+        Ok(()) => {}
+        Err(CommandError::Usage) => show_usage(),
+        Err(CommandError::Failed(message, code)) => {
+            if let Some(message) = message {
+                eprintln!("validate-desktop: {}", message);
+            }
+            exit(code);
+        }
+    }
+}
@@ -0,0 +1,332 @@
+use std::{collections::HashSet, env, io::{stdin, Read, Write}, path::{Path, PathBuf}, process::exit};
+use xdg_desktop::cli::{self, CommandError, EXIT_FAILED, EXIT_NO_FILE, EXIT_SYNTAX};
+use xdg_desktop::icon::IconCollection;
+use xdg_desktop::menu::{MenuItem, MenuItemDetail, MenuPrinter};
+use xdg_desktop::mime_type::MimeType;
+use xdg_desktop::open::{Launch, Opener};
+use xdg_desktop::recent::RecentFiles;
+use xdg_desktop::{dirs, terminal, uri};
+#[cfg(feature = "dbus")]
+use xdg_desktop::notify::{self, Urgency};
+
+const DEFAULT_ICON_SIZE: usize = 48;
+
+fn show_usage() -> ! {
+    eprintln!("Usage:");
+    eprintln!("  xdg-desktop menu [--format text|fvwm|openbox]");
+    eprintln!("  xdg-desktop open [-s] [-u] FILE|URL|-...");
+    eprintln!("  xdg-desktop mime query filetype FILE");
+    eprintln!("  xdg-desktop mime query default MIMETYPE");
+    eprintln!("  xdg-desktop mime default APPLICATION.desktop MIMETYPE...");
+    eprintln!("  xdg-desktop icon lookup NAME [SIZE]");
+    eprintln!("  xdg-desktop icon render NAME SIZE OUTPUT.png");
+    eprintln!("  xdg-desktop validate [--no-warnings] FILE...");
+    exit(EXIT_SYNTAX);
+}
+
+fn fail(message: String, code: i32) -> ! {
+    eprintln!("xdg-desktop: {}", message);
+    exit(code);
+}
+
+fn run(result: Result<(), CommandError>) {
+    match result {
+        Ok(()) => {}
+        Err(CommandError::Usage) => show_usage(),
+        Err(CommandError::Failed(Some(message), code)) => fail(message, code),
+        Err(CommandError::Failed(None, code)) => exit(code),
+    }
+}
+
+fn menu_command(item: &MenuItem) -> Option<String> {
+    let entry = item.detail_entry()?;
+    let exec = entry.exec_without_args();
+
+    Some(match entry.is_terminal.then(terminal::default).flatten() {
+        Some(terminal) => terminal.wrap(&exec),
+        None => exec,
+    })
+}
+
+// An indented tree, for reading.
+#[derive(Default)]
+struct TextPrinter {
+    level: usize,
+}
+
+impl MenuPrinter for TextPrinter {
+    fn print(&mut self, item: &MenuItem) {
+        if item.hidden || self.level == 0 {
+            return;
+        }
+        let indent = "  ".repeat(self.level - 1);
+        match &item.detail {
+            MenuItemDetail::Directory => println!("{}{}/", indent, item.name),
+            _ => println!("{}{}\t{}", indent, item.name, menu_command(item).unwrap_or_default()),
+        }
+    }
+
+    fn enter_menu(&mut self, _item: &MenuItem) {
+        self.level += 1;
+    }
+
+    fn leave_menu(&mut self, _item: &MenuItem) {
+        self.level -= 1;
+    }
+}
+
+// FVWM menu commands, each menu after its submenus.
+#[derive(Default)]
+struct FvwmPrinter {
+    menu_stack: Vec<String>,
+}
+
+impl MenuPrinter for FvwmPrinter {
+    fn print(&mut self, item: &MenuItem) {
+        let (Some(menu), false) = (self.menu_stack.last_mut(), item.hidden) else {
+            return;
+        };
+        let name = item.name.replace('&', "&&");
+        match &item.detail {
+            MenuItemDetail::Directory => menu.push_str(&format!("+ \"{}\" Popup \"{}\"\n", name, item.name)),
+            _ => menu.push_str(&format!("+ \"{}\" Exec exec {}\n", name, menu_command(item).unwrap_or_default())),
+        }
+    }
+
+    fn enter_menu(&mut self, item: &MenuItem) {
+        let name = &item.name;
+        self.menu_stack.push(format!("DestroyMenu \"{}\"\nAddToMenu \"{}\" \"{}\" Title\n", name, name, name));
+    }
+
+    fn leave_menu(&mut self, _item: &MenuItem) {
+        println!("{}", self.menu_stack.pop().unwrap());
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// An Openbox pipe menu.
+#[derive(Default)]
+struct OpenboxPrinter {
+    level: usize,
+}
+
+impl MenuPrinter for OpenboxPrinter {
+    fn print(&mut self, item: &MenuItem) {
+        let Some(command) = menu_command(item).filter(|_| !item.hidden) else {
+            return;
+        };
+        let indent = "  ".repeat(self.level);
+        println!("{}<item label=\"{}\"><action name=\"Execute\"><command>{}</command></action></item>",
+                 indent, escape_xml(&item.name), escape_xml(&command));
+    }
+
+    fn enter_menu(&mut self, item: &MenuItem) {
+        if self.level == 0 {
+            println!("<openbox_pipe_menu>");
+        } else {
+            println!("{}<menu id=\"xdg-desktop-{}\" label=\"{}\">", "  ".repeat(self.level), escape_xml(&item.basename), escape_xml(&item.name));
+        }
+        self.level += 1;
+    }
+
+    fn leave_menu(&mut self, _item: &MenuItem) {
+        self.level -= 1;
+        if self.level == 0 {
+            println!("</openbox_pipe_menu>");
+        } else {
+            println!("{}</menu>", "  ".repeat(self.level));
+        }
+    }
+}
+
+fn menu(args: &[&str]) {
+    let index = cli::scanned_index();
+    match args {
+        [] | ["--format", "text"] => index.print(&mut TextPrinter::default()),
+        ["--format", "fvwm"] => index.print(&mut FvwmPrinter::default()),
+        ["--format", "openbox"] => index.print(&mut OpenboxPrinter::default()),
+        _ => show_usage(),
+    }
+}
+
+// Applications need a file, so stdin is saved to one. It is left behind since the
+// application opens it after we exit.
+fn save_stdin() -> PathBuf {
+    let mut data = vec![];
+    if let Err(err) = stdin().read_to_end(&mut data) {
+        fail(format!("cannot read stdin: {}", err), EXIT_FAILED);
+    }
+    let saved = dirs::create_runtime_file("xdg-desktop-stdin-").and_then(|(path, mut file)| file.write_all(&data).map(|_| path));
+
+    saved.unwrap_or_else(|err| fail(format!("cannot save stdin: {}", err), EXIT_FAILED))
+}
+
+// Asks which of the applications for `mime` to use.
+fn select_app(opener: &Opener, mime: &MimeType) -> usize {
+    let Some(assoc) = opener.index.mime_assoc_index.get(mime).filter(|assoc| !assoc.all.is_empty()) else {
+        fail(format!("no application can open {}", mime), EXIT_FAILED);
+    };
+    println!("Select an application for {}:", mime);
+    for (i, item) in assoc.all.iter().enumerate() {
+        println!("{}. {}", i, opener.index.items[*item].name);
+    }
+    let mut user_input = String::new();
+    let selected = stdin().read_line(&mut user_input).ok()
+        .and_then(|_| user_input.trim().parse::<usize>().ok())
+        .and_then(|sel| assoc.all.get(sel).copied());
+
+    selected.unwrap_or_else(|| fail(format!("invalid selection {}", user_input.trim()), EXIT_SYNTAX))
+}
+
+// Failures to open also show as notifications, as open is often run from a key binding
+// with no terminal to print to.
+#[cfg(feature = "dbus")]
+fn notify_failure(message: &str) {
+    let _ = notify::send("Cannot open", message, Some("dialog-error"), Urgency::Normal);
+}
+
+#[cfg(not(feature = "dbus"))]
+fn notify_failure(_message: &str) {}
+
+fn open_failed(message: String, code: i32) -> ! {
+    notify_failure(&message);
+    fail(message, code);
+}
+
+// Opens files with their default applications, or ones chosen with -s and saved as the
+// default with -u, and URLs with their scheme handlers. "-" opens what is piped on stdin.
+fn open(args: &[&str]) {
+    let mut select = false;
+    let mut save_selection = false;
+    let mut stdin_path = None;
+    let mut paths = vec![];
+    let mut urls = vec![];
+    for arg in args {
+        match *arg {
+            "-s" => select = true,
+            "-u" => save_selection = true,
+            "-" => {
+                let path = save_stdin();
+                paths.push(path.clone());
+                stdin_path = Some(path);
+            }
+            _ if Path::new(arg).exists() => paths.push(PathBuf::from(arg)),
+            _ if uri::url_scheme(arg).is_some() => urls.push(*arg),
+            _ => open_failed(format!("file '{}' does not exist", arg), EXIT_NO_FILE),
+        }
+    }
+    if paths.is_empty() && urls.is_empty() {
+        show_usage();
+    }
+
+    let mut opener = Opener::new();
+    // The chosen applications become the defaults of their types, which plan then picks.
+    // They are only saved with -u.
+    if select {
+        let mut selected: HashSet<MimeType> = HashSet::new();
+        for path in &paths {
+            let Some(mime) = Opener::mime_type(path).as_deref().and_then(MimeType::parse) else {
+                open_failed(format!("cannot determine the type of '{}'", path.display()), EXIT_FAILED);
+            };
+            if !selected.contains(&mime) {
+                let item = select_app(&opener, &mime);
+                opener.index.change_default_assoc(&mime, item);
+                selected.insert(mime);
+            }
+        }
+    }
+    let mut launches = vec![];
+    for url in urls {
+        launches.push(opener.plan_url(url).unwrap_or_else(|err| open_failed(err.to_string(), EXIT_FAILED)));
+    }
+    launches.extend(opener.plan(&paths).unwrap_or_else(|err| open_failed(err.to_string(), EXIT_FAILED)));
+
+    let mut failed = false;
+    for launch in &launches {
+        if let Err(err) = launch.spawn() {
+            let message = format!("cannot run {}: {}", launch.commands.join("; "), err);
+            eprintln!("xdg-desktop: {}", message);
+            notify_failure(&message);
+            failed = true;
+        }
+    }
+    if save_selection {
+        if let Err(err) = opener.index.write_default_assoc() {
+            open_failed(format!("cannot write mimeapps.list: {}", err), EXIT_FAILED);
+        }
+    }
+    record_recent(&opener, &launches, stdin_path.as_deref());
+    if failed {
+        exit(EXIT_FAILED);
+    }
+}
+
+// Adds the opened files, except the saved stdin, to the recent files.
+fn record_recent(opener: &Opener, launches: &[Launch], stdin_path: Option<&Path>) {
+    let mut recent = match RecentFiles::load() {
+        Ok(recent) => recent,
+        Err(err) => return eprintln!("xdg-desktop: cannot read recent files: {}", err),
+    };
+    for launch in launches {
+        let item = &opener.index.items[launch.item];
+        let Some(entry) = item.detail_entry() else {
+            continue;
+        };
+        for path in launch.paths.iter().filter(|path| Some(path.as_path()) != stdin_path) {
+            if let Some(mime) = Opener::mime_type(path) {
+                recent.record(path, &mime, &item.basename, &entry.exec);
+            }
+        }
+    }
+    if let Err(err) = recent.save() {
+        eprintln!("xdg-desktop: cannot save recent files: {}", err);
+    }
+}
+
+fn parse_size(size: &str) -> usize {
+    size.parse().ok().filter(|size| *size > 0).unwrap_or_else(|| fail(format!("invalid size {}", size), EXIT_SYNTAX))
+}
+
+// Looks up icons of the current theme.
+fn icon(args: &[&str]) {
+    let (name, size, output) = match args {
+        ["lookup", name] => (*name, DEFAULT_ICON_SIZE, None),
+        ["lookup", name, size] => (*name, parse_size(size), None),
+        ["render", name, size, output] => (*name, parse_size(size), Some(Path::new(output))),
+        _ => show_usage(),
+    };
+    let icons = IconCollection::scan_current_theme();
+    let icon = icons.lookup(name, size).unwrap_or_else(|err| fail(err.to_string(), EXIT_FAILED));
+    match output {
+        Some(output) => render(&icon.path, size, output),
+        None => println!("{}", icon.path.display()),
+    }
+}
+
+#[cfg(feature = "raster")]
+fn render(src: &Path, size: usize, output: &Path) {
+    if let Err(err) = xdg_desktop::icon::scale_to_png(src, size, output) {
+        fail(format!("cannot render {} to {}: {}", src.display(), output.display(), err), EXIT_FAILED);
+    }
+}
+
+#[cfg(not(feature = "raster"))]
+fn render(_src: &Path, _size: usize, _output: &Path) {
+    fail(String::from("rendering needs the raster feature"), EXIT_FAILED);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["menu", rest @ ..] => menu(rest),
+        ["open", rest @ ..] => open(rest),
+        ["mime", rest @ ..] => run(cli::mime(rest)),
+        ["icon", rest @ ..] => icon(rest),
+        ["validate", rest @ ..] => run(cli::validate(rest)),
+        _ => show_usage(),
+    }
+}
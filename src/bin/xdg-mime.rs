@@ -0,0 +1,24 @@
+use std::{env, process::exit};
+use xdg_desktop::cli::{self, CommandError, EXIT_SYNTAX};
+
+fn show_usage() -> ! {
+    eprintln!("Usage:");
+    eprintln!("  xdg-mime query filetype FILE");
+    eprintln!("  xdg-mime query default MIMETYPE");
+    eprintln!("  xdg-mime default APPLICATION.desktop MIMETYPE...");
+    exit(EXIT_SYNTAX);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match cli::mime(&args.iter().map(String::as_str).collect::<Vec<&str>>()) {
+        Ok(()) => {}
+        Err(CommandError::Usage) => show_usage(),
+        Err(CommandError::Failed(message, code)) => {
+            if let Some(message) = message {
+                eprintln!("xdg-mime: {}", message);
+            }
+            exit(code);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::warn;
+use roxmltree::Node;
+
+use crate::dirs::BaseDirs;
+use crate::mime_info::parse_document;
+use crate::uri::{path_to_uri, uri_to_path};
+
+const BOOKMARK_NS: &str = "http://www.freedesktop.org/standards/desktop-bookmarks";
+
+// An entry of a "Places" menu.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Place {
+    pub uri: String,
+    pub label: String,
+    pub icon: Option<String>,
+}
+
+impl Place {
+    fn from_path(path: &Path, label: Option<&str>, icon: &str) -> Self {
+        let label = label.map(str::to_string).unwrap_or_else(|| default_label(path));
+        Self { uri: path_to_uri(path), label, icon: Some(icon.to_string()) }
+    }
+
+    // The local directory, None for remote places like sftp:// URIs.
+    pub fn path(&self) -> Option<PathBuf> {
+        uri_to_path(&self.uri)
+    }
+}
+
+fn default_label(path: &Path) -> String {
+    path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_else(|| path.display().to_string())
+}
+
+// "URI [label]" lines, as GTK writes them to gtk-3.0/bookmarks.
+fn parse_gtk_bookmarks(content: &str) -> Vec<Place> {
+    content.lines().filter(|line| !line.trim().is_empty()).map(|line| {
+        let (uri, label) = line.trim().split_once(' ').map_or((line.trim(), None), |(uri, label)| (uri, Some(label.trim())));
+        let label = label.map(str::to_string)
+            .unwrap_or_else(|| uri_to_path(uri).map(|path| default_label(&path)).unwrap_or_else(|| uri.to_string()));
+        Place { uri: uri.to_string(), label, icon: None }
+    }).collect()
+}
+
+// GTK bookmarks, from $XDG_CONFIG_HOME/gtk-3.0/bookmarks or else the older ~/.gtk-bookmarks.
+pub fn gtk_bookmarks_for(base_dirs: &BaseDirs) -> Vec<Place> {
+    [base_dirs.config_home().join("gtk-3.0/bookmarks"), base_dirs.home().join(".gtk-bookmarks")].iter()
+        .find_map(|path| fs::read_to_string(path).ok())
+        .map(|content| parse_gtk_bookmarks(&content))
+        .unwrap_or_default()
+}
+
+pub fn gtk_bookmarks() -> Vec<Place> {
+    gtk_bookmarks_for(&BaseDirs::new())
+}
+
+fn parse_kde_place(node: Node) -> Option<Place> {
+    let uri = node.attribute("href")?.to_string();
+    let mut label = None;
+    let mut icon = None;
+    let mut hidden = false;
+    for child in node.children().filter(Node::is_element) {
+        match child.tag_name().name() {
+            "title" => label = child.text().map(str::to_string),
+            "info" => {
+                for field in child.descendants().filter(Node::is_element) {
+                    let tag = field.tag_name();
+                    match (tag.namespace(), tag.name()) {
+                        (Some(BOOKMARK_NS), "icon") => icon = field.attribute("name").map(str::to_string),
+                        (_, "IsHidden") => hidden = field.text() == Some("true"),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if hidden {
+        return None;
+    }
+    let label = label.unwrap_or_else(|| uri_to_path(&uri).map(|path| default_label(&path)).unwrap_or_else(|| uri.clone()));
+
+    Some(Place { uri, label, icon })
+}
+
+// The places KDE shows, from $XDG_DATA_HOME/user-places.xbel, without the hidden ones.
+pub fn kde_places_for(base_dirs: &BaseDirs) -> Vec<Place> {
+    let Ok(text) = fs::read_to_string(base_dirs.data_home().join("user-places.xbel")) else {
+        return vec![];
+    };
+    let doc = match parse_document(&text) {
+        Ok(doc) => doc,
+        Err(err) => {
+            warn!("Cannot parse user-places.xbel: {}", err);
+            return vec![];
+        }
+    };
+
+    doc.root_element().children().filter(|node| node.has_tag_name("bookmark")).filter_map(parse_kde_place).collect()
+}
+
+pub fn kde_places() -> Vec<Place> {
+    kde_places_for(&BaseDirs::new())
+}
+
+// Home and the user directories that exist, followed by the GTK and KDE bookmarks. Places
+// listed more than once keep their first position.
+pub fn places_for(base_dirs: &BaseDirs) -> Vec<Place> {
+    let user_dirs = base_dirs.user_dirs();
+    let mut result = vec![Place::from_path(base_dirs.home(), Some("Home"), "user-home")];
+    let standard = [
+        (&user_dirs.desktop, "user-desktop"),
+        (&user_dirs.documents, "folder-documents"),
+        (&user_dirs.download, "folder-download"),
+        (&user_dirs.music, "folder-music"),
+        (&user_dirs.pictures, "folder-pictures"),
+        (&user_dirs.videos, "folder-videos"),
+    ];
+    for (dir, icon) in standard {
+        if let Some(dir) = dir.as_ref().filter(|dir| dir.is_dir()) {
+            result.push(Place::from_path(dir, None, icon));
+        }
+    }
+    for place in gtk_bookmarks_for(base_dirs).into_iter().chain(kde_places_for(base_dirs)) {
+        if !result.iter().any(|known| known.uri == place.uri) {
+            result.push(place);
+        }
+    }
+
+    result
+}
+
+pub fn places() -> Vec<Place> {
+    places_for(&BaseDirs::new())
+}
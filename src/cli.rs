@@ -0,0 +1,108 @@
+use std::io::ErrorKind;
+use std::path::Path;
+
+use crate::desktop_parser;
+use crate::menu::MenuIndex;
+use crate::mime_type::MimeType;
+use crate::open::Opener;
+
+// The commands shared by the xdg-desktop binary and the standalone xdg-mime and
+// validate-desktop ones, which only differ in their usage text and message prefix.
+
+// The exit codes of xdg-utils.
+pub const EXIT_SYNTAX: i32 = 1;
+pub const EXIT_NO_FILE: i32 = 2;
+pub const EXIT_FAILED: i32 = 4;
+
+#[derive(Debug)]
+pub enum CommandError {
+    // The arguments are wrong; the binary prints its usage.
+    Usage,
+    // Print the message, if any, and exit with the code.
+    Failed(Option<String>, i32),
+}
+
+fn failed(message: String, code: i32) -> CommandError {
+    CommandError::Failed(Some(message), code)
+}
+
+pub fn scanned_index() -> MenuIndex {
+    let mut index = MenuIndex::new_default();
+    index.scan();
+
+    index
+}
+
+pub fn parse_mime(mime: &str) -> Result<MimeType, CommandError> {
+    MimeType::parse(mime).ok_or_else(|| failed(format!("invalid MIME type {}", mime), EXIT_SYNTAX))
+}
+
+// As xdg-mime: "query filetype FILE", "query default MIMETYPE" and
+// "default APPLICATION.desktop MIMETYPE...".
+pub fn mime(args: &[&str]) -> Result<(), CommandError> {
+    match args {
+        ["query", "filetype", file] => {
+            let path = Path::new(file);
+            if path.symlink_metadata().is_err() {
+                return Err(failed(format!("file '{}' does not exist", file), EXIT_NO_FILE));
+            }
+            let mime = Opener::mime_type(path).ok_or_else(|| failed(format!("cannot determine the type of '{}'", file), EXIT_FAILED))?;
+            println!("{}", mime);
+        }
+        ["query", "default", mime] => {
+            // Like xdg-mime, print nothing when there is no default.
+            if let Some(filename) = scanned_index().default_desktop_file(&parse_mime(mime)?) {
+                println!("{}", filename);
+            }
+        }
+        ["default", app, mimes @ ..] if !mimes.is_empty() && app.ends_with(".desktop") => {
+            let mimes = mimes.iter().map(|mime| parse_mime(mime)).collect::<Result<Vec<MimeType>, CommandError>>()?;
+            scanned_index().set_default_app(app, &mimes).map_err(|err| match err.kind() {
+                ErrorKind::NotFound => failed(err.to_string(), EXIT_NO_FILE),
+                _ => failed(format!("cannot write mimeapps.list: {}", err), EXIT_FAILED),
+            })?;
+        }
+        _ => return Err(CommandError::Usage),
+    }
+
+    Ok(())
+}
+
+// Prints the issues of `path` as "file:line: severity: message". Returns whether it is valid.
+fn validate_file(path: &Path, warnings: bool) -> bool {
+    match desktop_parser::report(path, warnings) {
+        Ok((valid, lines)) => {
+            for line in lines {
+                println!("{}", line);
+            }
+            valid
+        }
+        Err(err) => {
+            eprintln!("{}: error: cannot read: {}", path.display(), err);
+            false
+        }
+    }
+}
+
+// As validate-desktop: "[--no-warnings] FILE...". Fails with 1 if any file has errors.
+pub fn validate(args: &[&str]) -> Result<(), CommandError> {
+    let mut warnings = true;
+    let mut files = vec![];
+    for arg in args {
+        match *arg {
+            "--no-warnings" => warnings = false,
+            "-h" | "--help" => return Err(CommandError::Usage),
+            _ => files.push(Path::new(arg)),
+        }
+    }
+    if files.is_empty() {
+        return Err(CommandError::Usage);
+    }
+
+    // Check every file, even after one fails.
+    let failed = files.iter().filter(|file| !validate_file(file, warnings)).count();
+    match failed {
+        0 => Ok(()),
+        _ => Err(CommandError::Failed(None, 1)),
+    }
+}
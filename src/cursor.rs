@@ -0,0 +1,157 @@
+use std::fs::File;
+use std::io::{Read, Result};
+use std::path::{Path, PathBuf};
+
+use crate::dirs;
+use crate::icon::{icon_base_dirs, read_theme_inherits};
+
+const XCURSOR_MAGIC: &[u8; 4] = b"Xcur";
+const XCURSOR_IMAGE_TYPE: u32 = 0xfffd0002;
+
+pub struct Cursor {
+    pub name: String,
+    pub theme: String,
+    pub path: PathBuf,
+    // Nominal sizes stored in the Xcursor file, ascending.
+    pub sizes: Vec<u32>,
+}
+
+impl Cursor {
+    pub fn nearest_size(&self, size: u32) -> Option<u32> {
+        self.sizes.iter().copied().min_by_key(|s| s.abs_diff(size))
+    }
+}
+
+fn read_u32(buf: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(buf.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+pub fn xcursor_sizes(path: &Path) -> Result<Vec<u32>> {
+    let mut header = [0u8; 16];
+    let mut file = File::open(path)?;
+    file.read_exact(&mut header)?;
+    if &header[..4] != XCURSOR_MAGIC {
+        return Ok(vec![]);
+    }
+    let header_size = read_u32(&header, 4).unwrap() as usize;
+    let ntoc = read_u32(&header, 12).unwrap() as usize;
+
+    let mut rest = vec![];
+    file.read_to_end(&mut rest)?;
+    let toc_start = header_size.saturating_sub(header.len());
+    // A bogus count would have us loop for billions of entries past the end.
+    let ntoc = ntoc.min(rest.len().saturating_sub(toc_start) / 12);
+    let mut sizes: Vec<u32> = (0..ntoc).filter_map(|i| {
+        let entry = toc_start + i * 12;
+        if read_u32(&rest, entry)? == XCURSOR_IMAGE_TYPE { read_u32(&rest, entry + 4) } else { None }
+    }).collect();
+    sizes.sort();
+    sizes.dedup();
+
+    Ok(sizes)
+}
+
+pub struct CursorTheme {
+    pub name: String,
+    pub inherits: Vec<String>,
+    cursor_dirs: Vec<PathBuf>,
+}
+
+impl CursorTheme {
+    pub fn new<'a, PathIterator>(name: &str, base_dirs: PathIterator) -> Option<Self>
+    where PathIterator: Iterator<Item = &'a Path> {
+        let theme_dirs: Vec<PathBuf> = base_dirs.map(|p| p.join(name)).filter(|p| p.is_dir()).collect();
+        let cursor_dirs: Vec<PathBuf> = theme_dirs.iter().map(|p| p.join("cursors")).filter(|p| p.is_dir()).collect();
+        let inherits = theme_dirs.iter().rev()
+            .flat_map(|p| [p.join("index.theme"), p.join("cursor.theme")])
+            .find(|p| p.is_file())
+            .map(|p| read_theme_inherits(&p))
+            .unwrap_or_default();
+        if cursor_dirs.is_empty() && inherits.is_empty() {
+            return None;
+        }
+
+        Some(Self { name: name.to_string(), inherits, cursor_dirs })
+    }
+
+    pub fn find_cursor(&self, name: &str) -> Option<Cursor> {
+        let path = self.cursor_dirs.iter().rev().map(|p| p.join(name)).find(|p| p.is_file())?;
+        let sizes = xcursor_sizes(&path).unwrap_or_default();
+
+        Some(Cursor { name: name.to_string(), theme: self.name.clone(), path, sizes })
+    }
+
+    pub fn has_cursors(&self) -> bool {
+        !self.cursor_dirs.is_empty()
+    }
+}
+
+pub struct CursorCollection {
+    pub themes: Vec<CursorTheme>,
+}
+
+impl CursorCollection {
+    pub fn new_default(theme: &str) -> Self {
+        Self::with_base_dirs(theme, &dirs::BaseDirs::new())
+    }
+
+    pub fn with_base_dirs(theme: &str, base_dirs: &dirs::BaseDirs) -> Self {
+        let paths = dirs::existing_only(base_dirs.data_dirs_scan_order());
+        Self::load(theme, icon_base_dirs(paths.iter().map(PathBuf::as_path), base_dirs.home()))
+    }
+
+    pub fn new<'a, PathIterator>(theme: &str, paths: PathIterator) -> Self
+    where PathIterator: Iterator<Item = &'a Path> {
+        Self::load(theme, icon_base_dirs(paths, dirs::BaseDirs::new().home()))
+    }
+
+    fn load(theme: &str, base_dirs: Vec<PathBuf>) -> Self {
+        let mut themes: Vec<CursorTheme> = vec![];
+        let mut pending = vec![theme.to_string()];
+        while let Some(name) = pending.pop() {
+            if themes.iter().any(|th| th.name == name) {
+                continue;
+            }
+            let Some(th) = CursorTheme::new(&name, base_dirs.iter().map(|p| p.as_path())) else {
+                continue;
+            };
+            pending.extend(th.inherits.iter().rev().cloned());
+            themes.push(th);
+        }
+
+        Self { themes }
+    }
+
+    // Names of all themes providing a cursors directory.
+    pub fn list_themes<'a, PathIterator>(paths: PathIterator) -> Vec<String>
+    where PathIterator: Iterator<Item = &'a Path> {
+        let mut result: Vec<String> = vec![];
+        for base_dir in icon_base_dirs(paths, dirs::BaseDirs::new().home()) {
+            let Ok(dir) = base_dir.read_dir() else {
+                continue;
+            };
+            for ent in dir.flatten() {
+                let Ok(name) = ent.file_name().into_string() else {
+                    continue;
+                };
+                if ent.path().join("cursors").is_dir() && !result.contains(&name) {
+                    result.push(name);
+                }
+            }
+        }
+        result.sort();
+
+        result
+    }
+
+    pub fn find_cursor(&self, name: &str) -> Option<Cursor> {
+        self.themes.iter().find_map(|th| th.find_cursor(name))
+    }
+
+    // Like find_cursor, but keeps looking through inherited themes for a file providing the size.
+    pub fn find_cursor_sized(&self, name: &str, size: u32) -> Option<Cursor> {
+        let cursors: Vec<Cursor> = self.themes.iter().filter_map(|th| th.find_cursor(name)).collect();
+        let exact = cursors.iter().position(|c| c.sizes.contains(&size));
+        cursors.into_iter().nth(exact.unwrap_or(0))
+    }
+}
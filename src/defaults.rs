@@ -0,0 +1,77 @@
+use std::io::{Error, ErrorKind, Result};
+
+use crate::menu::MenuIndex;
+use crate::mime_type::MimeType;
+use crate::uri::url_scheme;
+
+// What xdg-settings changes for each setting. The first type is the one queried.
+const WEB_BROWSER_TYPES: [&str; 3] = ["x-scheme-handler/http", "x-scheme-handler/https", "text/html"];
+const MAIL_CLIENT_TYPES: [&str; 1] = ["x-scheme-handler/mailto"];
+
+fn scanned_index() -> MenuIndex {
+    let mut index = MenuIndex::new_default();
+    index.scan();
+
+    index
+}
+
+// The desktop file set as the default for the first of `mimes`, e.g. "firefox.desktop".
+pub fn default_for(index: &MenuIndex, mimes: &[&str]) -> Option<String> {
+    let mime = MimeType::parse(mimes.first()?)?;
+    let idx = index.mime_assoc_index.get(&mime)?.default?;
+
+    Some(index.items[idx].basename.clone() + ".desktop")
+}
+
+// Makes `desktop_file` the default for all of `mimes` in the user's mimeapps.list.
+pub fn set_default_for(index: &mut MenuIndex, mimes: &[&str], desktop_file: &str) -> Result<()> {
+    let idx = index.find_desktop_file(desktop_file)
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("Cannot find {}", desktop_file)))?;
+    for mime in mimes.iter().filter_map(|mime| MimeType::parse(mime)) {
+        index.change_default_assoc(&mime, idx);
+    }
+
+    index.write_default_assoc()
+}
+
+pub fn web_browser() -> Option<String> {
+    default_for(&scanned_index(), &WEB_BROWSER_TYPES)
+}
+
+// Also makes it the default for HTML files, as xdg-settings does.
+pub fn set_web_browser(desktop_file: &str) -> Result<()> {
+    set_default_for(&mut scanned_index(), &WEB_BROWSER_TYPES, desktop_file)
+}
+
+pub fn mail_client() -> Option<String> {
+    default_for(&scanned_index(), &MAIL_CLIENT_TYPES)
+}
+
+pub fn set_mail_client(desktop_file: &str) -> Result<()> {
+    set_default_for(&mut scanned_index(), &MAIL_CLIENT_TYPES, desktop_file)
+}
+
+// "x-scheme-handler/https" for "https" or "HTTPS:".
+fn scheme_handler_type(scheme: &str) -> Result<String> {
+    let scheme = url_scheme(&format!("{}:", scheme.trim_end_matches(':')))
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("{} is not a URL scheme", scheme)))?;
+
+    Ok(format!("x-scheme-handler/{}", scheme))
+}
+
+// The desktop file handling `scheme` URLs, e.g. "thunderbird.desktop" for "mailto".
+pub fn scheme_handler_for(index: &MenuIndex, scheme: &str) -> Option<String> {
+    default_for(index, &[&scheme_handler_type(scheme).ok()?])
+}
+
+pub fn set_scheme_handler_for(index: &mut MenuIndex, scheme: &str, desktop_file: &str) -> Result<()> {
+    set_default_for(index, &[&scheme_handler_type(scheme)?], desktop_file)
+}
+
+pub fn default_scheme_handler(scheme: &str) -> Option<String> {
+    scheme_handler_for(&scanned_index(), scheme)
+}
+
+pub fn set_default_scheme_handler(scheme: &str, desktop_file: &str) -> Result<()> {
+    set_scheme_handler_for(&mut scanned_index(), scheme, desktop_file)
+}
@@ -0,0 +1,398 @@
+use std::fs::{self, File};
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::desktop_parser::{DesktopFile, DesktopParserCallback};
+use crate::dirs::BaseDirs;
+use crate::menu::{AssocType, MenuIndex};
+use crate::mime_type::MimeType;
+
+// Builds an Application .desktop file and installs it for the current user:
+//
+//   DesktopEntryBuilder::new("My App").exec("myapp %F").icon("myapp").categories(&["Utility"])
+//       .install("org.example.MyApp")
+#[derive(Clone, Debug, Default)]
+pub struct DesktopEntryBuilder {
+    name: String,
+    generic_name: Option<String>,
+    comment: Option<String>,
+    exec: Option<String>,
+    try_exec: Option<String>,
+    working_dir: Option<String>,
+    icon: Option<String>,
+    terminal: bool,
+    no_display: bool,
+    startup_wm_class: Option<String>,
+    categories: Vec<String>,
+    mime_types: Vec<String>,
+    keywords: Vec<String>,
+    extra: Vec<(String, String)>,
+    refresh_database: bool,
+}
+
+// Escapes for string values, where a newline would end the entry.
+pub(crate) fn escape_value(value: &str) -> String {
+    let mut result = String::new();
+    for ch in value.chars() {
+        match ch {
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\t' => result.push_str("\\t"),
+            '\r' => result.push_str("\\r"),
+            _ => result.push(ch),
+        }
+    }
+    // Leading spaces would be taken as part of the separator.
+    match result.strip_prefix(' ') {
+        Some(rest) => format!("\\s{}", rest),
+        None => result,
+    }
+}
+
+fn escape_list(values: &[String]) -> String {
+    values.iter().map(|value| escape_value(value).replace(';', "\\;") + ";").collect()
+}
+
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() && key.bytes().all(|ch| ch.is_ascii_alphanumeric() || ch == b'-')
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+// "org.example.App" or "org.example.App.desktop" as a file name in an applications dir.
+fn desktop_filename(desktop_id: &str) -> Result<String> {
+    let filename = if desktop_id.ends_with(".desktop") { desktop_id.to_string() } else { format!("{}.desktop", desktop_id) };
+    if filename.contains('/') || filename == ".desktop" {
+        return Err(invalid(format!("Invalid desktop id {}", desktop_id)));
+    }
+
+    Ok(filename)
+}
+
+impl DesktopEntryBuilder {
+    pub fn new(name: &str) -> Self {
+        Self { name: name.to_string(), ..Default::default() }
+    }
+
+    pub fn generic_name(mut self, generic_name: &str) -> Self {
+        self.generic_name = Some(generic_name.to_string());
+        self
+    }
+
+    pub fn comment(mut self, comment: &str) -> Self {
+        self.comment = Some(comment.to_string());
+        self
+    }
+
+    // The command line, with field codes such as %f or %U for the files it opens.
+    pub fn exec(mut self, exec: &str) -> Self {
+        self.exec = Some(exec.to_string());
+        self
+    }
+
+    pub fn try_exec(mut self, try_exec: &str) -> Self {
+        self.try_exec = Some(try_exec.to_string());
+        self
+    }
+
+    pub fn working_dir(mut self, dir: &Path) -> Self {
+        self.working_dir = Some(dir.to_string_lossy().into_owned());
+        self
+    }
+
+    // An icon name from the theme, or an absolute path.
+    pub fn icon(mut self, icon: &str) -> Self {
+        self.icon = Some(icon.to_string());
+        self
+    }
+
+    pub fn terminal(mut self, terminal: bool) -> Self {
+        self.terminal = terminal;
+        self
+    }
+
+    pub fn no_display(mut self, no_display: bool) -> Self {
+        self.no_display = no_display;
+        self
+    }
+
+    pub fn startup_wm_class(mut self, wm_class: &str) -> Self {
+        self.startup_wm_class = Some(wm_class.to_string());
+        self
+    }
+
+    pub fn categories(mut self, categories: &[&str]) -> Self {
+        self.categories = categories.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    pub fn mime_types(mut self, mime_types: &[&str]) -> Self {
+        self.mime_types = mime_types.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    pub fn keywords(mut self, keywords: &[&str]) -> Self {
+        self.keywords = keywords.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    // Any other key, typically an X- extension.
+    pub fn key(mut self, key: &str, value: &str) -> Self {
+        self.extra.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    // Runs update-desktop-database after installing, so the MIME types take effect in
+    // mimeinfo.cache.
+    pub fn refresh_database(mut self, refresh: bool) -> Self {
+        self.refresh_database = refresh;
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.name.trim().is_empty() {
+            return Err(invalid(String::from("Name is empty")));
+        }
+        let Some(exec) = self.exec.as_ref().filter(|exec| !exec.trim().is_empty()) else {
+            return Err(invalid(String::from("Exec is missing")));
+        };
+        let mut chars = exec.chars();
+        while let Some(ch) = chars.next() {
+            if ch == '%' && !matches!(chars.next(), Some('f' | 'F' | 'u' | 'U' | 'i' | 'c' | 'k' | '%')) {
+                return Err(invalid(format!("Invalid field code in Exec {}", exec)));
+            }
+        }
+        if let Some(category) = self.categories.iter().find(|category| category.is_empty() || category.contains(';')) {
+            return Err(invalid(format!("Invalid category {:?}", category)));
+        }
+        if let Some(mime) = self.mime_types.iter().find(|mime| MimeType::parse(mime).is_none()) {
+            return Err(invalid(format!("Invalid MIME type {}", mime)));
+        }
+        for (key, _) in &self.extra {
+            if !is_valid_key(key) {
+                return Err(invalid(format!("Invalid key {}", key)));
+            }
+        }
+
+        Ok(())
+    }
+
+    // The file contents. Call validate() first; install() does.
+    pub fn to_desktop_file(&self) -> String {
+        let mut lines = vec![String::from("[Desktop Entry]"), String::from("Type=Application")];
+        let mut push = |key: &str, value: String| lines.push(format!("{}={}", key, value));
+        push("Name", escape_value(&self.name));
+        let strings = [
+            ("GenericName", &self.generic_name),
+            ("Comment", &self.comment),
+            ("Icon", &self.icon),
+            ("TryExec", &self.try_exec),
+            ("Exec", &self.exec),
+            ("Path", &self.working_dir),
+            ("StartupWMClass", &self.startup_wm_class),
+        ];
+        for (key, value) in strings {
+            if let Some(value) = value {
+                push(key, escape_value(value));
+            }
+        }
+        if self.terminal {
+            push("Terminal", String::from("true"));
+        }
+        if self.no_display {
+            push("NoDisplay", String::from("true"));
+        }
+        for (key, values) in [("Categories", &self.categories), ("MimeType", &self.mime_types), ("Keywords", &self.keywords)] {
+            if !values.is_empty() {
+                push(key, escape_list(values));
+            }
+        }
+        for (key, value) in &self.extra {
+            push(key, escape_value(value));
+        }
+
+        lines.join("\n") + "\n"
+    }
+
+    // Writes <desktop_id>.desktop to the applications dir under data_home, replacing an
+    // existing file by a rename. Returns its path.
+    pub fn install_for(&self, base_dirs: &BaseDirs, desktop_id: &str) -> Result<PathBuf> {
+        self.validate()?;
+        let filename = desktop_filename(desktop_id)?;
+        let dir = base_dirs.data_home().join("applications");
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(&filename);
+        let tmp = dir.join(format!(".{}.{}", filename, std::process::id()));
+        fs::write(&tmp, self.to_desktop_file())?;
+        fs::rename(&tmp, &path).inspect_err(|_| {
+            let _ = fs::remove_file(&tmp);
+        })?;
+
+        // The entry works without the cache, so a missing tool is not an error.
+        if self.refresh_database {
+            let _ = Command::new("update-desktop-database").arg(&dir).status();
+        }
+
+        Ok(path)
+    }
+
+    pub fn install(&self, desktop_id: &str) -> Result<PathBuf> {
+        self.install_for(&BaseDirs::new(), desktop_id)
+    }
+}
+
+// What remove_desktop_entry changed.
+#[derive(Clone, Debug, Default)]
+pub struct RemovedEntry {
+    // The user's own copy, now deleted.
+    pub deleted: Option<PathBuf>,
+    // The Hidden=true file written over a system-wide entry of the same id.
+    pub masked: Option<PathBuf>,
+    // Types that no longer have the entry as default or added association in the user's
+    // mimeapps.list.
+    pub unassociated: Vec<MimeType>,
+}
+
+// Deletes the entry from the applications dir under data_home. An entry installed in one of
+// the data_dirs is masked there instead, so it no longer shows up either way. Fails with
+// NotFound if no such entry exists.
+pub fn remove_desktop_entry_for(base_dirs: &BaseDirs, desktop_id: &str) -> Result<RemovedEntry> {
+    let filename = desktop_filename(desktop_id)?;
+    let mut removed = RemovedEntry::default();
+    let user_dir = base_dirs.data_home().join("applications");
+    let user_path = user_dir.join(&filename);
+    if user_path.is_file() {
+        fs::remove_file(&user_path)?;
+        removed.deleted = Some(user_path.clone());
+    }
+
+    let system_entry = base_dirs.data_dirs_in_precedence().iter()
+        .filter(|dir| *dir != base_dirs.data_home())
+        .any(|dir| dir.join("applications").join(&filename).is_file());
+    if system_entry {
+        fs::create_dir_all(&user_dir)?;
+        let name = filename.trim_end_matches(".desktop");
+        fs::write(&user_path, format!("[Desktop Entry]\nType=Application\nName={}\nHidden=true\n", escape_value(name)))?;
+        removed.masked = Some(user_path);
+    }
+    if removed.deleted.is_none() && removed.masked.is_none() {
+        return Err(Error::new(ErrorKind::NotFound, format!("Cannot find {}", filename)));
+    }
+
+    let mut index = MenuIndex::with_base_dirs(None, base_dirs.clone());
+    index.scan();
+    index.local_assocs.retain(|assoc| {
+        let related = assoc.filename == filename && assoc.assoc_type != AssocType::Remove;
+        if related {
+            removed.unassociated.push(assoc.mime.clone());
+        }
+        !related
+    });
+    if !removed.unassociated.is_empty() {
+        index.write_default_assoc()?;
+    }
+
+    Ok(removed)
+}
+
+pub fn remove_desktop_entry(desktop_id: &str) -> Result<RemovedEntry> {
+    remove_desktop_entry_for(&BaseDirs::new(), desktop_id)
+}
+
+// The groups of a desktop file in order, each with its keys and raw values in order.
+type Groups = Vec<(String, Vec<(String, String)>)>;
+
+#[derive(Default)]
+struct GroupsParser {
+    groups: Groups,
+    key: String,
+}
+
+impl DesktopParserCallback for GroupsParser {
+    fn on_section(&mut self, name: &[u8]) -> bool {
+        self.groups.push((String::from_utf8_lossy(name).into_owned(), vec![]));
+
+        true
+    }
+    fn on_key(&mut self, key: &[u8]) -> bool {
+        self.key = String::from_utf8_lossy(key).trim().to_string();
+
+        true
+    }
+    fn on_value(&mut self, value: &[u8]) -> bool {
+        if let Some((_, keys)) = self.groups.last_mut() {
+            keys.push((self.key.clone(), String::from_utf8_lossy(value.trim_ascii()).into_owned()));
+        }
+
+        true
+    }
+}
+
+fn read_groups(path: &Path) -> Result<Groups> {
+    let mut parser = GroupsParser::default();
+    DesktopFile::new(File::open(path)?)?.parse(&mut parser);
+
+    Ok(parser.groups)
+}
+
+// A user override for the entry at `system` turning it into `modified`. A desktop file in
+// the user's applications dir replaces the system one rather than being merged with it, so
+// every group and key of `modified` is written, and a comment at the top names the system
+// entry and the keys that differ from it.
+pub fn override_content(system: &Path, modified: &Path) -> Result<String> {
+    let system_groups = read_groups(system)?;
+    let modified = read_groups(modified)?;
+    let changed = |group: &str, key: &str, value: &str| {
+        let original = system_groups.iter().find(|(name, _)| name == group).map(|(_, keys)| keys.as_slice()).unwrap_or(&[]);
+        !original.iter().any(|(name, old)| name == key && old == value)
+    };
+    let changed_keys: Vec<String> = modified.iter()
+        .flat_map(|(group, keys)| keys.iter().filter(|(key, value)| changed(group, key, value)).map(move |(key, _)| format!("{}/{}", group, key)))
+        .collect();
+
+    let mut lines = vec![format!("# Override of {}", system.display())];
+    lines.push(match changed_keys.is_empty() {
+        true => String::from("# Changed: nothing"),
+        false => format!("# Changed: {}", changed_keys.join(", ")),
+    });
+    for (i, (group, keys)) in modified.iter().enumerate() {
+        if i > 0 {
+            lines.push(String::new());
+        }
+        lines.push(format!("[{}]", group));
+        lines.extend(keys.iter().map(|(key, value)| format!("{}={}", key, value)));
+    }
+
+    Ok(lines.join("\n") + "\n")
+}
+
+// Writes the override of `desktop_id` made from `modified` to the applications dir under
+// data_home, replacing an existing one by a rename. The system entry is the one of highest
+// precedence in the other data dirs. Returns the path written.
+pub fn write_override_for(base_dirs: &BaseDirs, desktop_id: &str, modified: &Path) -> Result<PathBuf> {
+    let filename = desktop_filename(desktop_id)?;
+    let system = base_dirs.data_dirs_in_precedence().iter()
+        .filter(|dir| *dir != base_dirs.data_home())
+        .map(|dir| dir.join("applications").join(&filename))
+        .find(|path| path.is_file())
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("Cannot find a system entry for {}", filename)))?;
+    let content = override_content(&system, modified)?;
+
+    let dir = base_dirs.data_home().join("applications");
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(&filename);
+    let tmp = dir.join(format!(".{}.{}", filename, std::process::id()));
+    fs::write(&tmp, content)?;
+    fs::rename(&tmp, &path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })?;
+
+    Ok(path)
+}
+
+pub fn write_override(desktop_id: &str, modified: &Path) -> Result<PathBuf> {
+    write_override_for(&BaseDirs::new(), desktop_id, modified)
+}
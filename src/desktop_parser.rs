@@ -1,6 +1,9 @@
 use memmap::{MmapOptions, Mmap};
 use std::fs::File;
 use std::io::Result;
+use std::path::Path;
+
+use crate::mime_type::MimeType;
 
 pub struct DesktopFile {
     pub file: File,
@@ -13,37 +16,37 @@ pub trait DesktopParserCallback {
     fn on_value(&mut self, value: &[u8]) -> bool;
 }
 
-fn skip_whitespace<'a>(slice: &'a[u8]) -> &'a [u8] {
+fn skip_whitespace(slice: &[u8]) -> &[u8] {
     if let Some(pos) = slice.iter().position(|ch| { *ch != b' '}) {
-	return &slice[pos..];
+	&slice[pos..]
     } else {
-	return &slice[..];
+	slice
     }
 }
 
-fn find_next_char<'a>(x: u8, slice: &'a [u8]) -> Option<(&'a [u8], usize)> {
+fn find_next_char(x: u8, slice: &[u8]) -> Option<(&[u8], usize)> {
     let mut last:u8 = 0;
     let pos = slice.iter().position(|ch| {
 	last = *ch;
-	return *ch == b'\n' || *ch == x;
-    });
-    if pos.is_some() && last == x {
-	return Some((&slice[pos.unwrap()..], pos.unwrap()));
+	*ch == b'\n' || *ch == x
+    })?;
+    if last == x {
+	Some((&slice[pos..], pos))
     } else {
-	return None;
+	None
     }
 }
 
 impl DesktopFile {
     pub fn new(file: File) -> Result<Self> {
 	let mmap_region = unsafe { MmapOptions::new().map(&file)? };
-	return Ok(Self {
+	Ok(Self {
 	    file, mmap_region,
-	});
+	})
     }
     pub fn parse(&self, callback: &mut impl DesktopParserCallback) -> bool {
 	let mut slice = self.mmap_region.iter().as_slice();
-	while slice.len() > 0 {
+	while !slice.is_empty() {
 	    slice = skip_whitespace(slice);
 	    if slice[0] == b'\n' {
 		slice = &slice[1..];
@@ -63,7 +66,7 @@ impl DesktopFile {
                 }
 		slice = &next_slice[1..];
 		let Some((next_slice, pos)) = find_next_char(b'\n', slice) else {
-		    return callback.on_value(&slice);
+		    return callback.on_value(slice);
 		};
 		if !callback.on_value(&slice[..pos]) {
                     return false;
@@ -75,3 +78,297 @@ impl DesktopFile {
         true
     }
 }
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+// A spec violation found by DesktopFile::validate, at a 1-based line.
+#[derive(Clone, Debug)]
+pub struct Issue {
+    pub line: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+const BOOLEAN_KEYS: [&str; 7] = [
+    "NoDisplay", "Hidden", "DBusActivatable", "Terminal", "StartupNotify", "PrefersNonDefaultGPU", "SingleMainWindow",
+];
+const STRING_KEYS: [&str; 8] = ["Type", "Version", "Exec", "TryExec", "Path", "StartupWMClass", "URL", "Icon"];
+const LOCALE_STRING_KEYS: [&str; 3] = ["Name", "GenericName", "Comment"];
+const LIST_KEYS: [&str; 7] = ["OnlyShowIn", "NotShowIn", "Actions", "MimeType", "Categories", "Implements", "Keywords"];
+const LOCALIZED_KEYS: [&str; 5] = ["Name", "GenericName", "Comment", "Icon", "Keywords"];
+// Keys of older versions of the spec, still found in the wild.
+const DEPRECATED_KEYS: [&str; 11] = [
+    "Encoding", "MiniIcon", "TerminalOptions", "Protocols", "Extensions", "BinaryPattern", "MapNotify",
+    "SwallowTitle", "SwallowExec", "SortOrder", "FilePattern",
+];
+const ACTION_KEYS: [&str; 3] = ["Name", "Icon", "Exec"];
+
+// The key without its [locale] suffix, or None if the key is malformed.
+pub(crate) fn split_locale(key: &str) -> Option<(&str, Option<&str>)> {
+    let (base, locale) = match key.strip_suffix(']') {
+        Some(rest) => {
+            let (base, locale) = rest.split_once('[')?;
+            let valid_locale = !locale.is_empty()
+                && locale.bytes().all(|ch| ch.is_ascii_alphanumeric() || b"_@.-".contains(&ch));
+            if !valid_locale {
+                return None;
+            }
+            (base, Some(locale))
+        }
+        None => (key, None),
+    };
+    if base.is_empty() || !base.bytes().all(|ch| ch.is_ascii_alphanumeric() || ch == b'-') {
+        return None;
+    }
+
+    Some((base, locale))
+}
+
+// Only \s, \n, \t, \r and \\ are defined, and \; in lists.
+fn has_valid_escapes(value: &str, is_list: bool) -> bool {
+    let mut chars = value.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '\\' {
+            match chars.next() {
+                Some('s' | 'n' | 't' | 'r' | '\\') => {}
+                Some(';') if is_list => {}
+                _ => return false,
+            }
+        }
+    }
+
+    true
+}
+
+#[derive(Default)]
+struct Validator {
+    issues: Vec<Issue>,
+    is_directory: bool,
+    // The group being read, with its first line and the keys seen so far.
+    group: Option<String>,
+    group_line: usize,
+    keys: Vec<String>,
+    groups: Vec<String>,
+    // The unlocalized keys of [Desktop Entry], and where it starts.
+    entry: Vec<(String, String)>,
+    entry_line: usize,
+    action_groups: Vec<(usize, String)>,
+}
+
+impl Validator {
+    fn error(&mut self, line: usize, message: String) {
+        self.issues.push(Issue { line, severity: Severity::Error, message });
+    }
+
+    fn warning(&mut self, line: usize, message: String) {
+        self.issues.push(Issue { line, severity: Severity::Warning, message });
+    }
+
+    fn on_group(&mut self, line: usize, name: &str) {
+        self.finish_group();
+        if name.is_empty() || name.chars().any(|ch| ch.is_control() || ch == '[' || ch == ']') {
+            self.error(line, format!("invalid group name [{}]", name));
+        }
+        if self.groups.is_empty() && name != "Desktop Entry" {
+            self.error(line, format!("first group must be [Desktop Entry], not [{}]", name));
+        }
+        if self.groups.iter().any(|group| group == name) {
+            self.error(line, format!("duplicate group [{}]", name));
+        } else if let Some(action) = name.strip_prefix("Desktop Action ") {
+            self.action_groups.push((line, action.to_string()));
+        } else if name == "Desktop Entry" {
+            self.entry_line = line;
+        } else if !name.starts_with("X-") {
+            self.error(line, format!("unknown group [{}], extension groups must start with X-", name));
+        }
+        self.groups.push(name.to_string());
+        self.group = Some(name.to_string());
+        self.group_line = line;
+        self.keys.clear();
+    }
+
+    fn on_entry(&mut self, line: usize, key: &str, value: &str) {
+        let Some(group) = self.group.clone() else {
+            self.error(line, format!("key {} is not in a group", key));
+            return;
+        };
+        if self.keys.iter().any(|seen| seen == key) {
+            self.error(line, format!("duplicate key {} in [{}]", key, group));
+            return;
+        }
+        self.keys.push(key.to_string());
+        let Some((base, locale)) = split_locale(key) else {
+            self.error(line, format!("invalid key {}", key));
+            return;
+        };
+
+        let is_entry = group == "Desktop Entry";
+        if group.starts_with("X-") || base.starts_with("X-") {
+            return;
+        }
+        if !is_entry && !ACTION_KEYS.contains(&base) {
+            self.error(line, format!("key {} is not allowed in [{}]", base, group));
+            return;
+        }
+        let known = BOOLEAN_KEYS.contains(&base) || STRING_KEYS.contains(&base)
+            || LOCALE_STRING_KEYS.contains(&base) || LIST_KEYS.contains(&base);
+        if DEPRECATED_KEYS.contains(&base) {
+            self.warning(line, format!("key {} is deprecated", base));
+        } else if !known {
+            self.error(line, format!("unknown key {}, extension keys must start with X-", base));
+            return;
+        }
+        if locale.is_some() && !LOCALIZED_KEYS.contains(&base) {
+            self.error(line, format!("key {} cannot be localized", base));
+        }
+        if BOOLEAN_KEYS.contains(&base) && value != "true" && value != "false" {
+            self.error(line, format!("value {:?} of {} is not a boolean", value, base));
+        }
+        if !has_valid_escapes(value, LIST_KEYS.contains(&base)) {
+            self.error(line, format!("value of {} has an invalid escape sequence", key));
+        }
+        if base == "Exec" {
+            self.check_exec(line, value);
+        }
+        if base == "MimeType" {
+            for mime in value.split(';').filter(|mime| !mime.is_empty()) {
+                if let Err(err) = mime.parse::<MimeType>() {
+                    self.error(line, format!("{} in MimeType", err));
+                }
+            }
+        }
+        if is_entry && locale.is_none() {
+            self.entry.push((base.to_string(), value.to_string()));
+        }
+    }
+
+    fn check_exec(&mut self, line: usize, exec: &str) {
+        let mut chars = exec.chars();
+        while let Some(ch) = chars.next() {
+            if ch != '%' {
+                continue;
+            }
+            match chars.next() {
+                Some('f' | 'F' | 'u' | 'U' | 'i' | 'c' | 'k' | '%') => {}
+                Some(code @ ('d' | 'D' | 'n' | 'N' | 'v' | 'm')) => {
+                    self.warning(line, format!("field code %{} in Exec is deprecated", code));
+                }
+                Some(code) => self.error(line, format!("invalid field code %{} in Exec", code)),
+                None => self.error(line, String::from("Exec ends with a lone %")),
+            }
+        }
+    }
+
+    fn finish_group(&mut self) {
+        if let Some(group) = self.group.take() {
+            let line = self.group_line;
+            if group.starts_with("Desktop Action ") && !self.keys.iter().any(|key| key == "Name") {
+                self.error(line, format!("[{}] has no Name", group));
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        self.finish_group();
+        if !self.groups.iter().any(|group| group == "Desktop Entry") {
+            self.error(1, String::from("no [Desktop Entry] group"));
+            return;
+        }
+        let line = self.entry_line;
+        let value = |key: &str| self.entry.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone());
+        let entry_type = value("Type");
+        let has_exec = value("Exec").is_some();
+        let has_url = value("URL").is_some();
+        let dbus_activatable = value("DBusActivatable").as_deref() == Some("true");
+        let actions: Vec<String> = value("Actions").unwrap_or_default()
+            .split(';').filter(|s| !s.is_empty()).map(str::to_string).collect();
+        if value("Name").is_none() {
+            self.error(line, String::from("required key Name is missing"));
+        }
+        match entry_type.as_deref() {
+            None => self.error(line, String::from("required key Type is missing")),
+            Some("Application") if !has_exec && !dbus_activatable => {
+                self.error(line, String::from("Application has neither Exec nor DBusActivatable=true"));
+            }
+            Some("Link") if !has_url => self.error(line, String::from("Link has no URL")),
+            Some("Application" | "Link" | "Directory") => {}
+            Some(other) => self.error(line, format!("unknown Type {}", other)),
+        }
+        if self.is_directory && entry_type.as_deref().is_some_and(|t| t != "Directory") {
+            self.error(line, String::from(".directory file must have Type=Directory"));
+        }
+        for (action_line, action) in self.action_groups.clone() {
+            if !actions.contains(&action) {
+                self.warning(action_line, format!("action {} is not listed in Actions", action));
+            }
+        }
+        for action in actions {
+            if !self.action_groups.iter().any(|(_, name)| *name == action) {
+                self.error(line, format!("action {} in Actions has no [Desktop Action {}] group", action, action));
+            }
+        }
+    }
+}
+
+impl DesktopFile {
+    // Checks the file against the Desktop Entry spec instead of parsing it. `is_directory`
+    // is for .directory files, which must be of Type=Directory.
+    pub fn validate(&self, is_directory: bool) -> Vec<Issue> {
+        let mut validator = Validator { is_directory, ..Default::default() };
+        for (i, line) in self.mmap_region.split(|ch| *ch == b'\n').enumerate() {
+            let line_no = i + 1;
+            let Ok(line) = std::str::from_utf8(line) else {
+                validator.error(line_no, String::from("line is not valid UTF-8"));
+                continue;
+            };
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(rest) = line.strip_prefix('[') {
+                match rest.strip_suffix(']') {
+                    Some(name) => validator.on_group(line_no, name),
+                    None => validator.error(line_no, format!("malformed group header {}", line)),
+                }
+            } else if let Some((key, value)) = line.split_once('=') {
+                validator.on_entry(line_no, key.trim_end(), value.trim_start());
+            } else {
+                validator.error(line_no, format!("line is neither a group, a key nor a comment: {}", line));
+            }
+        }
+        validator.finish();
+
+        validator.issues.sort_by_key(|issue| issue.line);
+        validator.issues
+    }
+}
+
+// The issues of the desktop file at `path` as "file:line: severity: message" lines, the way
+// desktop-file-validate prints them, and whether the file is valid. A name not ending in
+// .desktop or .directory is an error too. Warnings are left out unless `warnings`.
+pub fn report(path: &Path, warnings: bool) -> Result<(bool, Vec<String>)> {
+    let file = DesktopFile::new(File::open(path)?)?;
+    let is_directory = path.extension().is_some_and(|ext| ext == "directory");
+    let mut valid = is_directory || path.extension().is_some_and(|ext| ext == "desktop");
+    let mut lines = vec![];
+    if !valid {
+        lines.push(format!("{}: error: file name does not end with .desktop or .directory", path.display()));
+    }
+    for issue in file.validate(is_directory) {
+        let severity = match issue.severity {
+            Severity::Error => {
+                valid = false;
+                "error"
+            }
+            Severity::Warning if warnings => "warning",
+            Severity::Warning => continue,
+        };
+        lines.push(format!("{}:{}: {}: {}", path.display(), issue.line, severity, issue.message));
+    }
+
+    Ok((valid, lines))
+}
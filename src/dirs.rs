@@ -1,35 +1,577 @@
-use std::{cmp::Ordering, env};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::{collections::HashMap, env, fs, io};
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
 
-pub fn xdg_data_dirs() -> Vec<String> {
-    let home_dir = env::var("HOME").unwrap_or("/root".to_string());
-    let dirs = env::var("XDG_DATA_DIRS").unwrap_or_else(|_| {
-        "/usr/share:/usr/local/share:".to_string() + home_dir.as_str() + "/.local/share"
-    });
-    let mut paths: Vec<&str> = dirs.split(':').collect();
-    let rank_path = |s: &str| -> i32 {
-        if s.starts_with("/usr") { -2 }
-        else if s.starts_with("/usr/local") { -1 }
-        else if s.starts_with(home_dir.as_str()) { 1 }
-        else { 0 }
+use log::warn;
+
+// Between the entries of $XDG_DATA_DIRS and $XDG_CONFIG_DIRS.
+const LIST_SEPARATOR: char = if cfg!(windows) { ';' } else { ':' };
+
+// Where BaseDirs reads HOME and the XDG variables from. Closures and maps work too, so
+// callers can supply fake values without touching the process environment.
+pub trait EnvProvider {
+    fn var(&self, name: &str) -> Option<String>;
+}
+
+// The process environment.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StdEnv;
+
+impl EnvProvider for StdEnv {
+    fn var(&self, name: &str) -> Option<String> {
+        env::var(name).ok()
+    }
+}
+
+impl<F: Fn(&str) -> Option<String>> EnvProvider for F {
+    fn var(&self, name: &str) -> Option<String> {
+        self(name)
+    }
+}
+
+impl EnvProvider for HashMap<String, String> {
+    fn var(&self, name: &str) -> Option<String> {
+        self.get(name).cloned()
+    }
+}
+
+// HOME and the XDG base dir variables, resolved once. Pass one to the *_with_base_dirs
+// constructors to load from a fixed environment instead of the process one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BaseDirs {
+    home: PathBuf,
+    data_home: PathBuf,
+    config_home: PathBuf,
+    cache_home: PathBuf,
+    state_home: PathBuf,
+    // System dirs, highest precedence first.
+    data_dirs: Vec<PathBuf>,
+    config_dirs: Vec<PathBuf>,
+    runtime_dir: Option<PathBuf>,
+}
+
+impl Default for BaseDirs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BaseDirs {
+    pub fn new() -> Self {
+        Self::from_env(StdEnv)
+    }
+
+    // Reads the variables from `env` rather than the process environment.
+    pub fn from_env(env: impl EnvProvider) -> Self {
+        let lookup = |name: &str| env.var(name);
+        let defaults = Self::defaults(&lookup);
+        // Relative values are invalid and must be ignored.
+        let absolute = |name: &str| lookup(name).filter(|dir| Path::new(dir).is_absolute()).map(PathBuf::from);
+        let list = |name: &str, default: Vec<PathBuf>| {
+            let Some(dirs) = lookup(name).filter(|dirs| !dirs.is_empty()) else {
+                return default;
+            };
+            let mut paths: Vec<PathBuf> = vec![];
+            for dir in dirs.split(LIST_SEPARATOR).map(Path::new).filter(|dir| dir.is_absolute()) {
+                let dir = dir.components().collect::<PathBuf>();
+                if !paths.contains(&dir) {
+                    paths.push(dir);
+                }
+            }
+            paths
+        };
+
+        Self {
+            data_home: absolute("XDG_DATA_HOME").unwrap_or(defaults.data_home),
+            config_home: absolute("XDG_CONFIG_HOME").unwrap_or(defaults.config_home),
+            cache_home: absolute("XDG_CACHE_HOME").unwrap_or(defaults.cache_home),
+            state_home: absolute("XDG_STATE_HOME").unwrap_or(defaults.state_home),
+            data_dirs: list("XDG_DATA_DIRS", defaults.data_dirs),
+            config_dirs: list("XDG_CONFIG_DIRS", defaults.config_dirs),
+            runtime_dir: absolute("XDG_RUNTIME_DIR"),
+            home: defaults.home,
+        }
+    }
+
+    // What the spec prescribes for unset variables.
+    #[cfg(not(all(feature = "portable-dirs", any(target_os = "macos", windows))))]
+    fn defaults(lookup: &impl Fn(&str) -> Option<String>) -> Self {
+        let home = PathBuf::from(lookup("HOME").unwrap_or("/root".to_string()));
+
+        Self {
+            data_home: home.join(".local/share"),
+            config_home: home.join(".config"),
+            cache_home: home.join(".cache"),
+            state_home: home.join(".local/state"),
+            data_dirs: vec![PathBuf::from("/usr/local/share"), PathBuf::from("/usr/share")],
+            config_dirs: vec![PathBuf::from("/etc/xdg")],
+            runtime_dir: None,
+            home,
+        }
+    }
+
+    // The native locations, so frontends can be built and run outside of a freedesktop
+    // session. Set XDG variables still take precedence.
+    #[cfg(all(feature = "portable-dirs", target_os = "macos"))]
+    fn defaults(lookup: &impl Fn(&str) -> Option<String>) -> Self {
+        let home = PathBuf::from(lookup("HOME").unwrap_or("/var/root".to_string()));
+        let support = home.join("Library/Application Support");
+
+        Self {
+            data_home: support.clone(),
+            config_home: support.clone(),
+            cache_home: home.join("Library/Caches"),
+            state_home: support,
+            data_dirs: vec![PathBuf::from("/Library/Application Support"), PathBuf::from("/opt/homebrew/share"), PathBuf::from("/usr/local/share")],
+            config_dirs: vec![PathBuf::from("/Library/Application Support")],
+            runtime_dir: None,
+            home,
+        }
+    }
+
+    #[cfg(all(feature = "portable-dirs", windows))]
+    fn defaults(lookup: &impl Fn(&str) -> Option<String>) -> Self {
+        let home = PathBuf::from(lookup("HOME").or_else(|| lookup("USERPROFILE")).unwrap_or("C:\\Users\\Default".to_string()));
+        let roaming = lookup("APPDATA").map(PathBuf::from).unwrap_or_else(|| home.join("AppData\\Roaming"));
+        let local = lookup("LOCALAPPDATA").map(PathBuf::from).unwrap_or_else(|| home.join("AppData\\Local"));
+        let program_data = PathBuf::from(lookup("ProgramData").unwrap_or("C:\\ProgramData".to_string()));
+
+        Self {
+            data_home: roaming.clone(),
+            config_home: roaming,
+            cache_home: local.clone(),
+            state_home: local,
+            data_dirs: vec![program_data.clone()],
+            config_dirs: vec![program_data],
+            runtime_dir: None,
+            home,
+        }
+    }
+
+    pub fn home(&self) -> &Path {
+        &self.home
+    }
+
+    pub fn data_home(&self) -> &Path {
+        &self.data_home
+    }
+
+    pub fn config_home(&self) -> &Path {
+        &self.config_home
+    }
+
+    pub fn cache_home(&self) -> &Path {
+        &self.cache_home
+    }
+
+    pub fn state_home(&self) -> &Path {
+        &self.state_home
+    }
+
+    pub fn config_dirs(&self) -> &[PathBuf] {
+        &self.config_dirs
+    }
+
+    // The spec's data dirs, highest precedence first: data_home followed by $XDG_DATA_DIRS.
+    pub fn data_dirs_in_precedence(&self) -> Vec<PathBuf> {
+        let mut paths = vec![self.data_home.clone()];
+        paths.extend(self.data_dirs.iter().filter(|dir| **dir != self.data_home).cloned());
+
+        paths
+    }
+
+    pub fn data_dirs_scan_order(&self) -> Vec<PathBuf> {
+        let mut paths = self.data_dirs_in_precedence();
+        paths.reverse();
+
+        paths
+    }
+
+    pub fn config_dirs_in_precedence(&self) -> Vec<PathBuf> {
+        let mut paths = vec![self.config_home.clone()];
+        paths.extend(self.config_dirs.iter().filter(|dir| **dir != self.config_home).cloned());
+
+        paths
+    }
+
+    pub fn find_data_file(&self, relative: impl AsRef<Path>) -> Option<PathBuf> {
+        existing_under(self.data_dirs_in_precedence(), relative.as_ref()).next()
+    }
+
+    pub fn data_files(&self, relative: impl AsRef<Path>) -> Vec<PathBuf> {
+        existing_under(self.data_dirs_in_precedence(), relative.as_ref()).collect()
+    }
+
+    pub fn find_config_file(&self, relative: impl AsRef<Path>) -> Option<PathBuf> {
+        existing_under(self.config_dirs_in_precedence(), relative.as_ref()).next()
+    }
+
+    pub fn config_files(&self, relative: impl AsRef<Path>) -> Vec<PathBuf> {
+        existing_under(self.config_dirs_in_precedence(), relative.as_ref()).collect()
+    }
+
+    pub fn app_config_dir(&self, app: &str) -> PathBuf {
+        self.config_home.join(app)
+    }
+
+    pub fn app_data_dir(&self, app: &str) -> PathBuf {
+        self.data_home.join(app)
+    }
+
+    pub fn app_cache_dir(&self, app: &str) -> PathBuf {
+        self.cache_home.join(app)
+    }
+
+    pub fn app_state_dir(&self, app: &str) -> PathBuf {
+        self.state_home.join(app)
+    }
+
+    // The validated $XDG_RUNTIME_DIR, see runtime_dir().
+    pub fn runtime_dir(&self) -> Option<PathBuf> {
+        self.runtime_dir.clone().filter(|dir| is_private_dir(dir))
+    }
+
+    pub fn user_dirs(&self) -> UserDirs {
+        let home = self.home.to_string_lossy();
+        let mut user_dirs = UserDirs::default();
+        let defaults = self.config_dirs.iter().find_map(|dir| fs::read_to_string(dir.join("user-dirs.defaults")).ok());
+        for line in defaults.as_deref().unwrap_or("").lines().filter(|line| !line.starts_with('#')) {
+            let Some((name, dir)) = line.split_once('=') else {
+                continue;
+            };
+            if let Some(slot) = user_dirs.slot(name.trim()) {
+                *slot = Some(self.home.join(dir.trim()));
+            }
+        }
+
+        let Ok(content) = fs::read_to_string(self.config_home.join("user-dirs.dirs")) else {
+            return user_dirs;
+        };
+        for line in content.lines().filter(|line| !line.trim_start().starts_with('#')) {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let Some(name) = key.trim().strip_prefix("XDG_").and_then(|key| key.strip_suffix("_DIR")) else {
+                continue;
+            };
+            if let Some(slot) = user_dirs.slot(name) {
+                *slot = parse_user_dir_value(value, &home);
+            }
+        }
+
+        user_dirs
+    }
+
+    // Points `kind` at `path` in user-dirs.dirs, like `xdg-user-dirs-update --set`. Other
+    // lines, comments included, are kept as they are. The file is replaced by a rename so
+    // readers never see it half written.
+    pub fn set_user_dir(&self, kind: UserDirKind, path: &Path) -> io::Result<()> {
+        if !path.is_absolute() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not absolute", path.display())));
+        }
+        let file = self.config_home.join("user-dirs.dirs");
+        let content = match fs::read_to_string(&file) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+
+        let key = format!("XDG_{}_DIR", kind.name());
+        let line = format!("{}={}", key, format_user_dir_value(path, &self.home));
+        let mut lines: Vec<String> = vec![];
+        let mut replaced = false;
+        for old in content.lines() {
+            let is_key = !old.trim_start().starts_with('#')
+                && old.split_once('=').is_some_and(|(name, _)| name.trim() == key);
+            if !is_key {
+                lines.push(old.to_string());
+            } else if !replaced {
+                lines.push(line.clone());
+                replaced = true;
+            }
+        }
+        if !replaced {
+            lines.push(line);
+        }
+
+        create_private_dir(&self.config_home)?;
+        let tmp = self.config_home.join(format!(".user-dirs.dirs.{}", std::process::id()));
+        fs::write(&tmp, lines.join("\n") + "\n")?;
+        fs::rename(&tmp, &file).inspect_err(|_| {
+            let _ = fs::remove_file(&tmp);
+        })
+    }
+}
+
+// Where user-specific data files are written.
+pub fn xdg_data_home() -> String {
+    BaseDirs::new().data_home().to_string_lossy().into_owned()
+}
+
+// Where user-specific configuration files are written.
+pub fn xdg_config_home() -> String {
+    BaseDirs::new().config_home().to_string_lossy().into_owned()
+}
+
+// $XDG_CONFIG_HOME, ~/.config by default.
+pub fn config_home() -> PathBuf {
+    BaseDirs::new().config_home
+}
+
+// $XDG_CONFIG_DIRS, /etc/xdg by default, highest precedence first.
+pub fn config_dirs() -> Vec<PathBuf> {
+    BaseDirs::new().config_dirs
+}
+
+// $XDG_CACHE_HOME, ~/.cache by default.
+pub fn cache_home() -> PathBuf {
+    BaseDirs::new().cache_home
+}
+
+// $XDG_STATE_HOME, ~/.local/state by default.
+pub fn state_home() -> PathBuf {
+    BaseDirs::new().state_home
+}
+
+// Per-application dirs under the user base dirs, e.g. ~/.config/myapp. They are not
+// created; pass them to create_private_dir before writing.
+pub fn app_config_dir(app: &str) -> PathBuf {
+    BaseDirs::new().app_config_dir(app)
+}
+
+pub fn app_data_dir(app: &str) -> PathBuf {
+    BaseDirs::new().app_data_dir(app)
+}
+
+pub fn app_cache_dir(app: &str) -> PathBuf {
+    BaseDirs::new().app_cache_dir(app)
+}
+
+pub fn app_state_dir(app: &str) -> PathBuf {
+    BaseDirs::new().app_state_dir(app)
+}
+
+#[cfg(unix)]
+fn private_dir_builder() -> fs::DirBuilder {
+    let mut builder = fs::DirBuilder::new();
+    builder.mode(0o700);
+
+    builder
+}
+
+#[cfg(not(unix))]
+fn private_dir_builder() -> fs::DirBuilder {
+    fs::DirBuilder::new()
+}
+
+// Creates `dir` and any missing parents with mode 0700, as the spec asks for base dirs.
+// Dirs that already exist keep their permissions.
+pub fn create_private_dir(dir: &Path) -> io::Result<PathBuf> {
+    private_dir_builder().recursive(true).create(dir)?;
+
+    Ok(dir.to_path_buf())
+}
+
+// Whether `dir` is a directory only the current user can access, as the spec requires of
+// $XDG_RUNTIME_DIR.
+#[cfg(unix)]
+fn is_private_dir(dir: &Path) -> bool {
+    let Ok(meta) = fs::symlink_metadata(dir) else {
+        return false;
     };
-    paths.sort_by(|a, b| {
-        let ra = rank_path(a);
-        let rb = rank_path(b);
-        if ra < rb {
-            return Ordering::Less;
-        } else if ra > rb {
-            return Ordering::Greater;
-        } else {
-            return b.cmp(a);
+
+    meta.is_dir() && meta.uid() == unsafe { libc::getuid() } && meta.mode() & 0o777 == 0o700
+}
+
+// Per-user profile dirs are already private there.
+#[cfg(not(unix))]
+fn is_private_dir(dir: &Path) -> bool {
+    dir.is_dir()
+}
+
+// $XDG_RUNTIME_DIR, for sockets and other runtime files. None when it is unset, relative,
+// or not a directory owned by the current user with mode 0700.
+pub fn runtime_dir() -> Option<PathBuf> {
+    BaseDirs::new().runtime_dir()
+}
+
+// runtime_dir, or else a private replacement under the temp dir, with a warning as the
+// spec asks. Unlike the real one it is not removed at logout.
+pub fn runtime_dir_or_fallback() -> io::Result<PathBuf> {
+    if let Some(dir) = runtime_dir() {
+        return Ok(dir);
+    }
+    #[cfg(unix)]
+    let dir = env::temp_dir().join(format!("xdg-runtime-{}", unsafe { libc::getuid() }));
+    // The temp dir is already per user.
+    #[cfg(not(unix))]
+    let dir = env::temp_dir().join("xdg-runtime");
+    warn!("XDG_RUNTIME_DIR is unusable, falling back to {}", dir.display());
+    if let Err(err) = private_dir_builder().create(&dir) {
+        if err.kind() != io::ErrorKind::AlreadyExists {
+            return Err(err);
+        }
+    }
+    // Someone else may have created it first.
+    if !is_private_dir(&dir) {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("{} is not private to the user", dir.display())));
+    }
+
+    Ok(dir)
+}
+
+// Creates a new file only the user can read, named `prefix` and a random suffix, in
+// runtime_dir_or_fallback. Existing files and symlinks are never opened.
+pub fn create_runtime_file(prefix: &str) -> io::Result<(PathBuf, fs::File)> {
+    let random = RandomState::new().build_hasher().finish();
+    let path = runtime_dir_or_fallback()?.join(format!("{}{:016x}", prefix, random));
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    Ok((path.clone(), options.open(&path)?))
+}
+
+// $XDG_DATA_HOME followed by $XDG_DATA_DIRS, in the order the spec gives them: the first
+// dir has the highest precedence. Relative entries are ignored as the spec requires, and
+// repeated ones are kept where they first appear.
+pub fn data_dirs_in_precedence() -> Vec<PathBuf> {
+    BaseDirs::new().data_dirs_in_precedence()
+}
+
+// The data dirs lowest precedence first. This is the order to load them in when entries
+// read later replace earlier ones, e.g. when filling a map.
+pub fn data_dirs_scan_order() -> Vec<PathBuf> {
+    BaseDirs::new().data_dirs_scan_order()
+}
+
+// Drops the dirs that do not exist, e.g. an unused /usr/local/share.
+pub fn existing_only(dirs: Vec<PathBuf>) -> Vec<PathBuf> {
+    dirs.into_iter().filter(|dir| dir.is_dir()).collect()
+}
+
+// The scan order as strings, under its old name.
+pub fn xdg_data_dirs() -> Vec<String> {
+    data_dirs_scan_order().into_iter().map(|dir| dir.to_string_lossy().into_owned()).collect()
+}
+
+fn existing_under(dirs: Vec<PathBuf>, relative: &Path) -> impl Iterator<Item = PathBuf> + '_ {
+    dirs.into_iter().map(move |dir| dir.join(relative)).filter(|path| path.exists())
+}
+
+// `relative` under the data dirs, e.g. "applications/foo.desktop", from the highest
+// precedence dir that has it.
+pub fn find_data_file(relative: impl AsRef<Path>) -> Option<PathBuf> {
+    BaseDirs::new().find_data_file(relative)
+}
+
+// Every existing `relative` under the data dirs, highest precedence first.
+pub fn data_files(relative: impl AsRef<Path>) -> Vec<PathBuf> {
+    BaseDirs::new().data_files(relative)
+}
+
+pub fn find_config_file(relative: impl AsRef<Path>) -> Option<PathBuf> {
+    BaseDirs::new().find_config_file(relative)
+}
+
+pub fn config_files(relative: impl AsRef<Path>) -> Vec<PathBuf> {
+    BaseDirs::new().config_files(relative)
+}
+
+// The well-known user directories. None when a directory is not configured, or disabled
+// by pointing it at $HOME.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UserDirs {
+    pub desktop: Option<PathBuf>,
+    pub download: Option<PathBuf>,
+    pub documents: Option<PathBuf>,
+    pub music: Option<PathBuf>,
+    pub pictures: Option<PathBuf>,
+    pub videos: Option<PathBuf>,
+    pub templates: Option<PathBuf>,
+    pub public_share: Option<PathBuf>,
+}
+
+// One of the UserDirs fields, for set_user_dir.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UserDirKind {
+    Desktop,
+    Download,
+    Documents,
+    Music,
+    Pictures,
+    Videos,
+    Templates,
+    PublicShare,
+}
+
+impl UserDirKind {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Desktop => "DESKTOP",
+            Self::Download => "DOWNLOAD",
+            Self::Documents => "DOCUMENTS",
+            Self::Music => "MUSIC",
+            Self::Pictures => "PICTURES",
+            Self::Videos => "VIDEOS",
+            Self::Templates => "TEMPLATES",
+            Self::PublicShare => "PUBLICSHARE",
         }
-    });
+    }
+}
 
-    let mut dedup_paths: Vec<String> = vec![];
-    for p in paths {
-        if dedup_paths.is_empty() || dedup_paths.last().unwrap().as_str().cmp(p) != Ordering::Equal {
-            dedup_paths.push(p.to_string());
+impl UserDirs {
+    // By the names user-dirs.dirs uses between "XDG_" and "_DIR".
+    fn slot(&mut self, name: &str) -> Option<&mut Option<PathBuf>> {
+        match name {
+            "DESKTOP" => Some(&mut self.desktop),
+            "DOWNLOAD" => Some(&mut self.download),
+            "DOCUMENTS" => Some(&mut self.documents),
+            "MUSIC" => Some(&mut self.music),
+            "PICTURES" => Some(&mut self.pictures),
+            "VIDEOS" => Some(&mut self.videos),
+            "TEMPLATES" => Some(&mut self.templates),
+            "PUBLICSHARE" => Some(&mut self.public_share),
+            _ => None,
         }
     }
+}
+
+// A user-dirs.dirs value: "$HOME/Desktop" or an absolute path, in shell double quotes.
+fn parse_user_dir_value(value: &str, home: &str) -> Option<PathBuf> {
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?.replace("\\\"", "\"").replace("\\\\", "\\");
+    let path = match value.strip_prefix("$HOME") {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("{}{}", home, rest),
+        Some(_) => return None,
+        None if value.starts_with('/') => value,
+        None => return None,
+    };
+
+    (path.trim_end_matches('/') != home.trim_end_matches('/')).then(|| PathBuf::from(path))
+}
+
+// The reverse of parse_user_dir_value, with paths under $HOME written relative to it.
+fn format_user_dir_value(path: &Path, home: &Path) -> String {
+    let value = match path.strip_prefix(home) {
+        Ok(rest) if rest.as_os_str().is_empty() => "$HOME".to_string(),
+        Ok(rest) => format!("$HOME/{}", rest.display()),
+        Err(_) => path.display().to_string(),
+    };
+
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// The user directories from $XDG_CONFIG_HOME/user-dirs.dirs, as xdg-user-dirs-update
+// writes it. Those missing there fall back to the system user-dirs.defaults, which holds
+// the untranslated names relative to $HOME.
+pub fn user_dirs() -> UserDirs {
+    BaseDirs::new().user_dirs()
+}
 
-    dedup_paths
+pub fn set_user_dir(kind: UserDirKind, path: &Path) -> io::Result<()> {
+    BaseDirs::new().set_user_dir(kind, path)
 }
@@ -0,0 +1,65 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::open;
+use crate::uri::path_to_uri;
+
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn to_uris(paths: &[PathBuf]) -> Vec<String> {
+    paths.iter().map(|path| path_to_uri(path)).collect()
+}
+
+// Calls a method of org.freedesktop.FileManager1, which takes the URIs and a startup id.
+#[cfg(feature = "dbus")]
+fn call_file_manager(method: &str, uris: &[String]) -> io::Result<()> {
+    let connection = zbus::blocking::Connection::session().map_err(io::Error::other)?;
+    connection.call_method(
+        Some("org.freedesktop.FileManager1"), "/org/freedesktop/FileManager1",
+        Some("org.freedesktop.FileManager1"), method, &(uris, ""),
+    ).map_err(io::Error::other)?;
+
+    Ok(())
+}
+
+#[cfg(not(feature = "dbus"))]
+fn call_file_manager(_method: &str, _uris: &[String]) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "built without D-Bus support"))
+}
+
+// Opens the directories with the inode/directory handler, each once.
+fn open_dirs(dirs: impl Iterator<Item = PathBuf>) -> io::Result<()> {
+    let mut unique: Vec<PathBuf> = vec![];
+    for dir in dirs {
+        if !unique.contains(&dir) {
+            unique.push(dir);
+        }
+    }
+    open::open_paths(&unique)?;
+
+    Ok(())
+}
+
+// Shows `paths` selected in their folders. Without a FileManager1 service, the folders are
+// opened instead.
+pub fn reveal_in_file_manager(paths: &[PathBuf]) -> io::Result<()> {
+    if call_file_manager("ShowItems", &to_uris(paths)).is_ok() {
+        return Ok(());
+    }
+
+    open_dirs(paths.iter().map(|path| {
+        let path = absolute(path);
+        path.parent().map(Path::to_path_buf).unwrap_or(path)
+    }))
+}
+
+// Opens the folders themselves, through FileManager1 if it is running.
+pub fn show_folders(paths: &[PathBuf]) -> io::Result<()> {
+    if call_file_manager("ShowFolders", &to_uris(paths)).is_ok() {
+        return Ok(());
+    }
+
+    open_dirs(paths.iter().cloned())
+}
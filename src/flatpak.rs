@@ -0,0 +1,64 @@
+// What the Exec line of a desktop file exported by Flatpak asks to run, e.g.
+// "/usr/bin/flatpak run --branch=stable --arch=x86_64 --command=gedit --file-forwarding
+// org.gnome.gedit @@ %F @@".
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FlatpakExport {
+    // From X-Flatpak, e.g. "org.gnome.gedit".
+    pub app_id: String,
+    pub branch: Option<String>,
+    pub arch: Option<String>,
+    pub command: Option<String>,
+    // Whether the arguments between @@ markers are files to pass into the sandbox.
+    pub file_forwarding: bool,
+    // What follows the app id, field codes included.
+    pub args: Vec<String>,
+}
+
+impl FlatpakExport {
+    // None unless `exec` runs `app_id` through "flatpak run".
+    pub fn from_exec(app_id: &str, exec: &str) -> Option<Self> {
+        let mut words = exec.split_whitespace();
+        words.by_ref().find(|word| word.rsplit('/').next() == Some("flatpak"))?;
+        if words.next()? != "run" {
+            return None;
+        }
+
+        let mut export = FlatpakExport { app_id: app_id.to_string(), ..Default::default() };
+        loop {
+            let word = words.next()?;
+            if word == app_id {
+                break;
+            } else if let Some(branch) = word.strip_prefix("--branch=") {
+                export.branch = Some(branch.to_string());
+            } else if let Some(arch) = word.strip_prefix("--arch=") {
+                export.arch = Some(arch.to_string());
+            } else if let Some(command) = word.strip_prefix("--command=") {
+                export.command = Some(command.to_string());
+            } else if word == "--file-forwarding" {
+                export.file_forwarding = true;
+            } else if !word.starts_with('-') {
+                // Another app than X-Flatpak names.
+                return None;
+            }
+        }
+        export.args = words.map(String::from).collect();
+
+        Some(export)
+    }
+
+    // An Exec value running the export with the flatpak on PATH. Options other than the
+    // branch, arch, command and file forwarding, such as a wrapper's environment, are left out.
+    pub fn exec(&self) -> String {
+        let mut words = vec![String::from("flatpak"), String::from("run")];
+        words.extend(self.branch.iter().map(|branch| format!("--branch={}", branch)));
+        words.extend(self.arch.iter().map(|arch| format!("--arch={}", arch)));
+        words.extend(self.command.iter().map(|command| format!("--command={}", command)));
+        if self.file_forwarding {
+            words.push(String::from("--file-forwarding"));
+        }
+        words.push(self.app_id.clone());
+        words.extend(self.args.iter().cloned());
+
+        words.join(" ")
+    }
+}
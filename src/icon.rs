@@ -1,42 +1,194 @@
-use std::{collections::HashMap, path::{Path, PathBuf}, ffi::OsString};
+use std::{collections::{BTreeMap, HashMap}, fmt, io, path::{Path, PathBuf}, ffi::OsString, fs::{self, File}, process::Command, sync::{OnceLock, RwLock}};
+use log::{debug, trace, warn};
 use regex::Regex;
 
-#[derive(Clone)]
+use crate::desktop_parser::{split_locale, DesktopFile, DesktopParserCallback};
+use crate::dirs;
+use crate::locale;
+use crate::mime_icon::MIMEIconIndex;
+
+#[derive(Clone, PartialEq, Eq)]
 pub struct BitmapIconDescription {
     pub size: usize,
     pub scale: usize,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq)]
 pub enum IconDescription {
     Scalable,
     Bitmap(BitmapIconDescription),
+    Unthemed,
 }
 
+#[derive(Clone)]
 pub struct Icon {
     pub name: String,
     pub path: PathBuf,
     pub desc: IconDescription,
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub enum IconError {
+    NotFound(String),
+    ThemeNotFound(String),
+    Io(PathBuf, io::ErrorKind),
+}
+
+impl fmt::Display for IconError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IconError::NotFound(name) => write!(f, "Icon {} not found", name),
+            IconError::ThemeNotFound(name) => write!(f, "Icon theme {} not found", name),
+            IconError::Io(path, kind) => write!(f, "Cannot read {}: {}", path.display(), kind),
+        }
+    }
+}
+
+impl std::error::Error for IconError {}
+
+pub enum IconSource {
+    Name(String),
+    Path(PathBuf),
+}
+
+impl From<&str> for IconSource {
+    // Icon= values are either theme icon names or absolute paths.
+    fn from(value: &str) -> Self {
+        if value.starts_with('/') {
+            IconSource::Path(PathBuf::from(value))
+        } else {
+            IconSource::Name(value.to_string())
+        }
+    }
+}
+
+// User pinned icons, keyed by desktop id (without .desktop) or WM class.
+#[derive(Default)]
+pub struct IconOverrides {
+    map: HashMap<String, String>,
+}
+
+impl IconOverrides {
+    pub fn load_default() -> io::Result<Self> {
+        Self::load_with_base_dirs(&dirs::BaseDirs::new())
+    }
+
+    pub fn load_with_base_dirs(base_dirs: &dirs::BaseDirs) -> io::Result<Self> {
+        let path = base_dirs.find_config_file("xdg-desktop/icon-overrides")
+            .unwrap_or_else(|| base_dirs.app_config_dir("xdg-desktop").join("icon-overrides"));
+        Self::load(&path)
+    }
+
+    // One key=value per line, where value is an icon name or an absolute path.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut overrides = Self::default();
+        for line in fs::read_to_string(path)?.lines().map(|l| l.trim()) {
+            if line.starts_with('#') || line.starts_with('[') {
+                continue;
+            }
+            if let Some((key, value)) = line.split_once('=') {
+                overrides.insert(key.trim(), value.trim());
+            }
+        }
+
+        Ok(overrides)
+    }
+
+    pub fn insert(&mut self, key: &str, icon: &str) {
+        self.map.insert(key.to_string(), icon.to_string());
+    }
+
+    pub fn get(&self, key: &str) -> Option<IconSource> {
+        self.map.get(key).map(|icon| IconSource::from(icon.as_str()))
+    }
+}
+
 pub struct IconIndex {
     pub index: HashMap<String, Vec<Icon>>,
 }
 
+#[derive(Clone, Default)]
+pub struct IconData {
+    pub display_name: Option<String>,
+    pub embedded_text_rectangle: Option<(i32, i32, i32, i32)>,
+    pub attach_points: Vec<(i32, i32)>,
+}
+
+#[derive(Default)]
+struct IconDataParser {
+    in_icon_data: bool,
+    key: String,
+    data: IconData,
+    // DisplayName by locale.
+    display_names: HashMap<String, String>,
+}
+
+fn parse_point(s: &str) -> Option<(i32, i32)> {
+    let (x, y) = s.split_once(',')?;
+    Some((x.trim().parse().ok()?, y.trim().parse().ok()?))
+}
+
+impl DesktopParserCallback for IconDataParser {
+    fn on_section(&mut self, name: &[u8]) -> bool {
+        self.in_icon_data = name == b"Icon Data";
+        true
+    }
+
+    fn on_key(&mut self, key: &[u8]) -> bool {
+        self.key = String::from_utf8_lossy(key).trim_end().to_string();
+        true
+    }
+
+    fn on_value(&mut self, value: &[u8]) -> bool {
+        if !self.in_icon_data {
+            return true;
+        }
+        let value = String::from_utf8_lossy(value).trim().to_string();
+        if self.key == "DisplayName" {
+            self.data.display_name = Some(value);
+        } else if let Some(("DisplayName", Some(lang))) = split_locale(&self.key) {
+            self.display_names.insert(lang.to_string(), value);
+        } else if self.key == "EmbeddedTextRectangle" {
+            let coords: Vec<i32> = value.split(',').filter_map(|c| c.trim().parse().ok()).collect();
+            if let [x0, y0, x1, y1] = coords[..] {
+                self.data.embedded_text_rectangle = Some((x0, y0, x1, y1));
+            }
+        } else if self.key == "AttachPoints" {
+            self.data.attach_points = value.split('|').filter_map(parse_point).collect();
+        }
+
+        true
+    }
+}
+
 impl Icon {
     pub fn pixel_size(&self) -> Option<usize> {
 	match &self.desc {
-	    IconDescription::Scalable => None,
+	    IconDescription::Scalable | IconDescription::Unthemed => None,
 	    IconDescription::Bitmap(desc) => Some(desc.size * desc.scale),
 	}
     }
+
+    // Metadata from the optional .icon file next to the image, with DisplayName in the
+    // locale of the process.
+    pub fn data(&self) -> Option<IconData> {
+        let file = File::open(self.path.with_extension("icon")).ok()?;
+        let desktop_file = DesktopFile::new(file).ok()?;
+        let mut parser = IconDataParser::default();
+        desktop_file.parse(&mut parser);
+        if let Some(name) = locale::best_match(&locale::current_fallback_chain(), &parser.display_names) {
+            parser.data.display_name = Some(name.clone());
+        }
+
+        Some(parser.data)
+    }
 }
 
 fn filename_is_image(filename: &OsString) -> bool {
     if let Some(s) = filename.to_str() {
 	return s.ends_with(".png") || s.ends_with(".svg");
     }
-    return false;
+    false
 }
 
 fn parse_desc(s: &str) -> Option<IconDescription> {
@@ -45,17 +197,15 @@ fn parse_desc(s: &str) -> Option<IconDescription> {
     }
     let re = Regex::new(r"(?<size>[0-9]+)x[0-9]+(?:@(?<scale>[0-9]+))?").unwrap();
 
-    let Some(m) = re.captures(s) else {
-	return None;
-    };
+    let m = re.captures(s)?;
 
-    let size = usize::from_str_radix(&m[1], 10).unwrap();
-    let scale = if m.name("scale").is_some() { usize::from_str_radix(&m["scale"], 10).unwrap() } else { 1 };
-    // eprintln!("size {} scale {}", size, scale);
+    let size = m[1].parse::<usize>().unwrap();
+    let scale = if m.name("scale").is_some() { m["scale"].parse::<usize>().unwrap() } else { 1 };
+    trace!("size {} scale {}", size, scale);
 
-    return Some(IconDescription::Bitmap(BitmapIconDescription {
+    Some(IconDescription::Bitmap(BitmapIconDescription {
 	size, scale,
-    }));
+    }))
 }
 
 impl IconIndex {
@@ -69,7 +219,7 @@ impl IconIndex {
 	    };
 	    let path = ent.path();
 	    let Ok(md) = path.metadata() else {
-		eprintln!("Icon: Cannot open {}", path.to_str().unwrap());
+		warn!("Icon: Cannot open {}", path.display());
 		continue;
 	    };
 	    if md.is_file() && filename_is_image(&ent.file_name()) {
@@ -80,7 +230,7 @@ impl IconIndex {
 	}
     }
 
-    fn add_image(&mut self, file: &Path, icon_desc: &IconDescription) -> () {
+    fn add_image(&mut self, file: &Path, icon_desc: &IconDescription) {
 	let (Some(filename), Some(ext)) = (file.file_name(), file.extension()) else {
 	    return;
 	};
@@ -96,7 +246,7 @@ impl IconIndex {
 	    return;
 	}
 
-	// eprintln!("Found icon {}", &icon_name);
+	trace!("Found icon {}", &icon_name);
 
 	let icon = Icon {
 	    name: String::from(icon_name), path: file.to_path_buf().clone(), desc: icon_desc.clone(),
@@ -111,7 +261,7 @@ impl IconIndex {
 
     fn scan_all_dir(&mut self, root_dir: &Path) {
 	let Ok(dir) = root_dir.read_dir() else {
-	    // eprintln!("Icon: Cannot read_dir: {}", root_dir.to_str().unwrap());
+	    debug!("Icon: Cannot read_dir: {}", root_dir.display());
 	    return;
 	};
 	for ent in dir {
@@ -131,7 +281,7 @@ impl IconIndex {
 
     pub fn scan_with_theme<'a, PathIterator>(&mut self, themes: Vec<&str>, paths: PathIterator)
     where PathIterator: Iterator<Item = &'a Path> {
-        let pathbufs: Vec<PathBuf> = paths.map(|p| PathBuf::from(p)).collect();
+        let pathbufs: Vec<PathBuf> = paths.map(PathBuf::from).collect();
 	for th in themes {
 	    for pbuf in &pathbufs {
 		let mut pbuf = pbuf.clone();
@@ -148,3 +298,808 @@ impl IconIndex {
 	}
     }
 }
+
+impl Default for IconIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[cfg(feature = "raster")]
+pub fn scale_to_png(src: &Path, size: usize, dest: &Path) -> io::Result<()> {
+    let to_io_error = |e: image::ImageError| io::Error::new(io::ErrorKind::InvalidData, e);
+    let img = image::open(src).map_err(to_io_error)?;
+    img.resize(size as u32, size as u32, image::imageops::FilterType::Lanczos3)
+        .save_with_format(dest, image::ImageFormat::Png).map_err(to_io_error)
+}
+
+pub const FALLBACK_ICON_SVG: &[u8] = include_bytes!("../assets/fallback-icon.svg");
+
+const ICON_EXTENSIONS: [&str; 3] = ["svg", "png", "xpm"];
+
+fn split_icon_filename(filename: &str) -> Option<(&str, &str)> {
+    let (name, ext) = filename.rsplit_once('.')?;
+    if ICON_EXTENSIONS.contains(&ext) {
+        Some((name, ext))
+    } else {
+        None
+    }
+}
+
+pub struct IconInfo {
+    pub name: String,
+    pub contexts: Vec<String>,
+    pub sizes: Vec<IconDescription>,
+}
+
+impl IconInfo {
+    fn add_context(&mut self, context: &str) {
+        if !context.is_empty() && !self.contexts.iter().any(|c| c == context) {
+            self.contexts.push(context.to_string());
+        }
+    }
+
+    fn add_size(&mut self, desc: &IconDescription) {
+        if !self.sizes.contains(desc) {
+            self.sizes.push(desc.clone());
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SizeStrategy {
+    // Only bitmap directories of exactly the size, and scalable ones covering it.
+    Exact,
+    // Otherwise the closest larger icon, then the closest smaller one.
+    #[default]
+    NearestLarger,
+    // Otherwise the closest smaller icon, then the closest larger one.
+    NearestSmaller,
+    // Otherwise the closest icon in either direction.
+    Nearest,
+    // The first icon found, whatever its size.
+    AnySize,
+}
+
+#[derive(Clone)]
+pub struct IconLookupOptions {
+    // Used by the lookups that do not take a SizeStrategy.
+    pub size_strategy: SizeStrategy,
+    // Extensions tried in each directory, most preferred first.
+    pub extensions: Vec<String>,
+    pub embedded_fallback: bool,
+}
+
+impl Default for IconLookupOptions {
+    fn default() -> Self {
+        Self {
+            size_strategy: SizeStrategy::default(),
+            extensions: ICON_EXTENSIONS.iter().map(|ext| ext.to_string()).collect(),
+            embedded_fallback: true,
+        }
+    }
+}
+
+pub struct IconDirectory {
+    pub subdir: String,
+    pub context: String,
+    pub desc: IconDescription,
+    // Pixel sizes this directory is a match for, following its Type.
+    min_pixels: usize,
+    max_pixels: usize,
+    paths: Vec<PathBuf>,
+    listing: OnceLock<DirectoryListing>,
+}
+
+#[derive(Default)]
+struct DirectoryListing {
+    files: HashMap<String, PathBuf>,
+    failures: Vec<IconError>,
+}
+
+impl IconDirectory {
+    fn new(subdir: String, context: String, desc: IconDescription, (min_pixels, max_pixels): (usize, usize)) -> Self {
+        Self {
+            subdir, context, desc, min_pixels, max_pixels, paths: vec![], listing: OnceLock::new(),
+        }
+    }
+
+    // Positive if the icons here are larger than requested, negative if smaller.
+    fn size_distance(&self, size: usize) -> i64 {
+        if size < self.min_pixels {
+            (self.min_pixels - size) as i64
+        } else if size > self.max_pixels {
+            -((size - self.max_pixels) as i64)
+        } else {
+            0
+        }
+    }
+
+    // Bitmap dirs by their nominal size alone, whatever their Threshold. Scalable icons
+    // render at any size in their range.
+    fn has_exact_size(&self, size: usize) -> bool {
+        match &self.desc {
+            IconDescription::Bitmap(desc) => desc.size * desc.scale == size,
+            _ => self.size_distance(size) == 0,
+        }
+    }
+
+    // Directories are only read the first time an icon is looked up in them.
+    fn listing(&self) -> &DirectoryListing {
+        self.listing.get_or_init(|| {
+            let mut listing = DirectoryListing::default();
+            for path in &self.paths {
+                let dir = match path.read_dir() {
+                    Ok(dir) => dir,
+                    Err(err) => {
+                        listing.failures.push(IconError::Io(path.clone(), err.kind()));
+                        continue;
+                    },
+                };
+                for ent in dir.flatten() {
+                    let Ok(filename) = ent.file_name().into_string() else {
+                        continue;
+                    };
+                    if split_icon_filename(&filename).is_some() {
+                        listing.files.insert(filename, ent.path());
+                    }
+                }
+            }
+            listing
+        })
+    }
+
+    fn files(&self) -> &HashMap<String, PathBuf> {
+        &self.listing().files
+    }
+
+    fn find<Pred>(&self, name: &str, options: &IconLookupOptions, pred: &Pred) -> Option<Icon>
+    where Pred: Fn(&Path, &IconDescription) -> bool {
+        for ext in &options.extensions {
+            let Some(path) = self.files().get(&format!("{}.{}", name, ext)) else {
+                continue;
+            };
+            // Dangling symlinks are never candidates, pred only sees files that exist.
+            if path.is_file() && pred(path, &self.desc) {
+                return Some(Icon {
+                    name: name.to_string(), path: path.clone(), desc: self.desc.clone(),
+                });
+            }
+        }
+
+        None
+    }
+}
+
+#[derive(Default)]
+struct IconThemeIndexParser {
+    section: String,
+    key: String,
+    inherits: Vec<String>,
+    name: Option<String>,
+    // Name by locale.
+    names: HashMap<String, String>,
+    directories: Vec<String>,
+    dir_keys: HashMap<String, HashMap<String, String>>,
+}
+
+impl DesktopParserCallback for IconThemeIndexParser {
+    fn on_section(&mut self, name: &[u8]) -> bool {
+        self.section = String::from_utf8_lossy(name).into_owned();
+        true
+    }
+
+    fn on_key(&mut self, key: &[u8]) -> bool {
+        self.key = String::from_utf8_lossy(key).trim_end().to_string();
+        true
+    }
+
+    fn on_value(&mut self, value: &[u8]) -> bool {
+        let value = String::from_utf8_lossy(value).trim().to_string();
+        if self.section == "Icon Theme" {
+            let list = value.split(',').filter(|s| !s.is_empty()).map(|s| s.to_string());
+            if self.key == "Inherits" {
+                self.inherits.extend(list);
+            } else if self.key == "Name" {
+                self.name = Some(value);
+            } else if let Some(("Name", Some(lang))) = split_locale(&self.key) {
+                self.names.insert(lang.to_string(), value);
+            } else if self.key == "Directories" || self.key == "ScaledDirectories" {
+                self.directories.extend(list);
+            }
+        } else {
+            self.dir_keys.entry(self.section.clone()).or_default().insert(self.key.clone(), value);
+        }
+
+        true
+    }
+}
+
+pub struct IconOrigin {
+    // None for icons placed directly in an icon base directory.
+    pub theme: Option<String>,
+    pub subdir: String,
+    pub context: String,
+    pub icon: Icon,
+}
+
+pub(crate) fn read_theme_inherits(index_file: &Path) -> Vec<String> {
+    let Ok(desktop_file) = File::open(index_file).and_then(DesktopFile::new) else {
+        return vec![];
+    };
+    let mut parser = IconThemeIndexParser::default();
+    desktop_file.parse(&mut parser);
+
+    parser.inherits
+}
+
+pub(crate) fn icon_base_dirs<'a, PathIterator>(paths: PathIterator, home: &Path) -> Vec<PathBuf>
+where PathIterator: Iterator<Item = &'a Path> {
+    let mut base_dirs: Vec<PathBuf> = paths.map(|p| p.join("icons")).collect();
+    base_dirs.push(home.join(".icons"));
+
+    base_dirs
+}
+
+pub struct IconTheme {
+    pub name: String,
+    // Name from index.theme in the locale of the process.
+    pub display_name: Option<String>,
+    pub inherits: Vec<String>,
+    theme_dirs: Vec<PathBuf>,
+    scalable_dirs: Vec<IconDirectory>,
+    bitmap_dirs: BTreeMap<usize, Vec<IconDirectory>>,
+}
+
+impl IconTheme {
+    // The theme in the icons dir of each data dir in `paths`, e.g. /usr/share.
+    pub fn new<'a, PathIterator>(name: &str, paths: PathIterator) -> Option<Self>
+    where PathIterator: Iterator<Item = &'a Path> {
+        let icon_dirs: Vec<PathBuf> = paths.map(|p| p.join("icons")).collect();
+        Self::from_icon_dirs(name, icon_dirs.iter().map(PathBuf::as_path))
+    }
+
+    // The theme in each icon base dir in `paths`, e.g. /usr/share/icons or ~/.icons.
+    pub fn from_icon_dirs<'a, PathIterator>(name: &str, paths: PathIterator) -> Option<Self>
+    where PathIterator: Iterator<Item = &'a Path> {
+        Self::open(name, paths).ok()
+    }
+
+    // As from_icon_dirs, telling why the theme cannot be used.
+    pub fn open<'a, PathIterator>(name: &str, paths: PathIterator) -> Result<Self, IconError>
+    where PathIterator: Iterator<Item = &'a Path> {
+        let theme_dirs: Vec<PathBuf> = paths.map(|p| p.join(name)).filter(|p| p.is_dir()).collect();
+        if theme_dirs.is_empty() {
+            return Err(IconError::ThemeNotFound(name.to_string()));
+        }
+
+        let mut theme = Self {
+            name: name.to_string(), display_name: None, inherits: vec![], theme_dirs,
+            scalable_dirs: vec![], bitmap_dirs: BTreeMap::new(),
+        };
+        let index_file = theme.theme_dirs.iter().rev().map(|p| p.join("index.theme")).find(|p| p.is_file());
+        let dirs = match index_file {
+            Some(index_file) => theme.parse_index(&index_file)?,
+            None => theme.guess_dirs(),
+        };
+
+        for mut dir in dirs {
+            dir.paths = theme.theme_dirs.iter().map(|p| p.join(&dir.subdir)).filter(|p| p.is_dir()).collect();
+            match &dir.desc {
+                IconDescription::Bitmap(desc) => theme.bitmap_dirs.entry(desc.size * desc.scale).or_default().push(dir),
+                _ => theme.scalable_dirs.push(dir),
+            }
+        }
+
+        Ok(theme)
+    }
+
+    fn parse_index(&mut self, index_file: &Path) -> Result<Vec<IconDirectory>, IconError> {
+        let mut parser = IconThemeIndexParser::default();
+        let io_error = |err: io::Error| IconError::Io(index_file.to_path_buf(), err.kind());
+        let desktop_file = DesktopFile::new(File::open(index_file).map_err(io_error)?).map_err(io_error)?;
+        desktop_file.parse(&mut parser);
+        self.inherits = parser.inherits;
+        self.display_name = locale::best_match(&locale::current_fallback_chain(), &parser.names).cloned().or(parser.name);
+
+        let mut result = vec![];
+        for subdir in parser.directories {
+            let Some(keys) = parser.dir_keys.get(&subdir) else {
+                continue;
+            };
+            let Some(Ok(size)) = keys.get("Size").map(|s| s.parse::<usize>()) else {
+                continue;
+            };
+            let key_or = |key: &str, default: usize| keys.get(key).and_then(|s| s.parse::<usize>().ok()).unwrap_or(default);
+            let scale = key_or("Scale", 1);
+            let context = keys.get("Context").cloned().unwrap_or_default();
+            let (desc, range) = match keys.get("Type").map(|t| t.as_str()) {
+                Some("Scalable") => (IconDescription::Scalable, (key_or("MinSize", size), key_or("MaxSize", size))),
+                Some("Fixed") => (IconDescription::Bitmap(BitmapIconDescription { size, scale }), (size, size)),
+                _ => {
+                    let threshold = key_or("Threshold", 2);
+                    (IconDescription::Bitmap(BitmapIconDescription { size, scale }), (size.saturating_sub(threshold), size + threshold))
+                },
+            };
+            result.push(IconDirectory::new(subdir, context, desc, (range.0 * scale, range.1 * scale)));
+        }
+
+        Ok(result)
+    }
+
+    // Themes without an index.theme are laid out as <size>/<context>.
+    fn guess_dirs(&self) -> Vec<IconDirectory> {
+        let mut result: Vec<IconDirectory> = vec![];
+        for theme_dir in &self.theme_dirs {
+            let Ok(dir) = theme_dir.read_dir() else {
+                continue;
+            };
+            for size_ent in dir.flatten() {
+                let Ok(size_name) = size_ent.file_name().into_string() else {
+                    continue;
+                };
+                let Some(desc) = parse_desc(&size_name) else {
+                    continue;
+                };
+                let range = match &desc {
+                    IconDescription::Bitmap(b) => (b.size.saturating_sub(2) * b.scale, (b.size + 2) * b.scale),
+                    _ => (1, usize::MAX),
+                };
+                let Ok(size_dir) = size_ent.path().read_dir() else {
+                    continue;
+                };
+                for ctx_ent in size_dir.flatten() {
+                    let Ok(context) = ctx_ent.file_name().into_string() else {
+                        continue;
+                    };
+                    let subdir = format!("{}/{}", size_name, context);
+                    if ctx_ent.path().is_dir() && !result.iter().any(|d| d.subdir == subdir) {
+                        result.push(IconDirectory::new(subdir, context, desc.clone(), range));
+                    }
+                }
+            }
+        }
+
+        result
+    }
+
+    fn all_dirs(&self) -> impl Iterator<Item = &IconDirectory> {
+        self.bitmap_dirs.values().flatten().chain(self.scalable_dirs.iter())
+    }
+
+    // Reads every directory up front and reports the ones that failed.
+    pub fn scan(&self) -> Result<(), Vec<IconError>> {
+        let failures: Vec<IconError> = self.all_dirs().flat_map(|dir| dir.listing().failures.iter().cloned()).collect();
+        if failures.is_empty() { Ok(()) } else { Err(failures) }
+    }
+
+    pub fn icons(&self) -> Vec<IconInfo> {
+        let mut result: BTreeMap<String, IconInfo> = BTreeMap::new();
+        for dir in self.all_dirs() {
+            for filename in dir.files().keys() {
+                let Some((name, _)) = split_icon_filename(filename) else {
+                    continue;
+                };
+                let info = result.entry(name.to_string())
+                    .or_insert_with(|| IconInfo { name: name.to_string(), contexts: vec![], sizes: vec![] });
+                info.add_context(&dir.context);
+                info.add_size(&dir.desc);
+            }
+        }
+
+        result.into_values().collect()
+    }
+
+    pub fn find_all_icons(&self, name: &str) -> Vec<Icon> {
+        let mut result = vec![];
+        for dir in self.all_dirs() {
+            for ext in ICON_EXTENSIONS {
+                if let Some(path) = dir.files().get(&format!("{}.{}", name, ext)).filter(|p| p.is_file()) {
+                    result.push(Icon { name: name.to_string(), path: path.clone(), desc: dir.desc.clone() });
+                }
+            }
+        }
+
+        result
+    }
+
+    fn identify(&self, path: &Path) -> Option<IconOrigin> {
+        let filename = path.file_name()?.to_str()?;
+        let (name, _) = split_icon_filename(filename)?;
+        for theme_dir in &self.theme_dirs {
+            let Some(subdir) = path.parent().and_then(|p| p.strip_prefix(theme_dir).ok()).and_then(|p| p.to_str()) else {
+                continue;
+            };
+            if let Some(dir) = self.all_dirs().find(|d| d.subdir == subdir) {
+                return Some(IconOrigin {
+                    theme: Some(self.name.clone()), subdir: dir.subdir.clone(), context: dir.context.clone(),
+                    icon: Icon { name: name.to_string(), path: path.to_path_buf(), desc: dir.desc.clone() },
+                });
+            }
+        }
+
+        None
+    }
+
+    // Directories are tried outward from the requested size, in the order the strategy
+    // prefers, and the first one with the icon wins. Only those tried are read. A file
+    // rejected by pred is skipped as if it did not exist, so the next extension or directory
+    // is tried.
+    pub fn find_icon_pred<Pred>(&self, name: &str, size: usize, strategy: SizeStrategy, options: &IconLookupOptions, pred: &Pred) -> Option<Icon>
+    where Pred: Fn(&Path, &IconDescription) -> bool {
+        let mut dirs: Vec<((u64, u64), &IconDirectory)> = self.all_dirs().filter_map(|dir| {
+            let distance = dir.size_distance(size);
+            let key = match strategy {
+                SizeStrategy::Exact if dir.has_exact_size(size) => (0, 0),
+                SizeStrategy::Exact => return None,
+                _ if distance == 0 => (0, 0),
+                SizeStrategy::AnySize => (0, 0),
+                SizeStrategy::NearestLarger => ((distance < 0) as u64, distance.unsigned_abs()),
+                SizeStrategy::NearestSmaller => ((distance > 0) as u64, distance.unsigned_abs()),
+                SizeStrategy::Nearest => (distance.unsigned_abs(), (distance < 0) as u64),
+            };
+            Some((key, dir))
+        }).collect();
+        // Stable, so dirs at the same distance keep their order.
+        dirs.sort_by_key(|(key, _)| *key);
+
+        dirs.into_iter().find_map(|(_, dir)| dir.find(name, options, pred))
+    }
+}
+
+fn command_output(cmd: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(cmd).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let value = String::from_utf8_lossy(&output.stdout).trim().trim_matches('\'').to_string();
+    if value.is_empty() { None } else { Some(value) }
+}
+
+fn ini_value(path: &Path, section: &str, key: &str) -> Option<String> {
+    let content = fs::read_to_string(path).ok()?;
+    let mut in_section = section.is_empty();
+    for line in content.lines().map(|l| l.trim()) {
+        if line.starts_with('[') {
+            in_section = line == format!("[{}]", section);
+            continue;
+        }
+        if !in_section {
+            continue;
+        }
+        let Some((k, v)) = line.split_once('=') else {
+            continue;
+        };
+        if k.trim() == key {
+            let v = v.trim().trim_matches('"');
+            return if v.is_empty() { None } else { Some(v.to_string()) };
+        }
+    }
+
+    None
+}
+
+pub fn current_theme_name() -> Option<String> {
+    let base_dirs = dirs::BaseDirs::new();
+    let config_value = |relative: &str, section: &str, key: &str| {
+        base_dirs.config_files(relative).iter().find_map(|path| ini_value(path, section, key))
+    };
+
+    command_output("gsettings", &["get", "org.gnome.desktop.interface", "icon-theme"])
+        .or_else(|| command_output("dconf", &["read", "/org/gnome/desktop/interface/icon-theme"]))
+        .or_else(|| config_value("gtk-3.0/settings.ini", "Settings", "gtk-icon-theme-name"))
+        .or_else(|| ini_value(&base_dirs.home().join(".gtkrc-2.0"), "", "gtk-icon-theme-name"))
+        .or_else(|| config_value("kdeglobals", "Icons", "Theme"))
+}
+
+// Where write_fallback_icon put the compiled-in icon, once per process.
+static FALLBACK_ICON_PATH: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+// A file left by an earlier run is kept as it is.
+fn write_fallback_icon() -> Option<PathBuf> {
+    let path = dirs::cache_home().join("xdg-desktop/icons/fallback.svg");
+    if !path.is_file() {
+        let dir = path.parent()?;
+        fs::create_dir_all(dir).ok()?;
+        // Other processes may be reading it already.
+        let tmp = dir.join(format!(".fallback.svg.{}", std::process::id()));
+        fs::write(&tmp, FALLBACK_ICON_SVG).and_then(|_| fs::rename(&tmp, &path)).inspect_err(|_| {
+            let _ = fs::remove_file(&tmp);
+        }).ok()?;
+    }
+
+    Some(path)
+}
+
+pub struct IconCollection {
+    pub themes: Vec<IconTheme>,
+    options: IconLookupOptions,
+    pub overrides: IconOverrides,
+    lookup_cache: RwLock<HashMap<(String, usize, SizeStrategy), Option<Icon>>>,
+    // Icon base directories, lowest precedence first.
+    base_dirs: Vec<PathBuf>,
+}
+
+impl IconCollection {
+    pub fn scan_current_theme() -> Self {
+        Self::new_default(&current_theme_name().unwrap_or(String::from("hicolor")))
+    }
+
+    pub fn new_default(theme: &str) -> Self {
+        Self::with_base_dirs(theme, &dirs::BaseDirs::new())
+    }
+
+    pub fn with_env(theme: &str, env: impl dirs::EnvProvider) -> Self {
+        Self::with_base_dirs(theme, &dirs::BaseDirs::from_env(env))
+    }
+
+    // Themes from the data dirs and ~/.icons of `base_dirs`.
+    pub fn with_base_dirs(theme: &str, base_dirs: &dirs::BaseDirs) -> Self {
+        let paths = dirs::existing_only(base_dirs.data_dirs_scan_order());
+        Self::load(theme, icon_base_dirs(paths.iter().map(PathBuf::as_path), base_dirs.home()))
+    }
+
+    // Unlike new(), fails if the requested theme itself cannot be loaded.
+    pub fn try_new<'a, PathIterator>(theme: &str, paths: PathIterator) -> Result<Self, IconError>
+    where PathIterator: Iterator<Item = &'a Path> {
+        let collection = Self::new(theme, paths);
+        if !collection.themes.iter().any(|th| th.name == theme) {
+            return Err(IconError::ThemeNotFound(theme.to_string()));
+        }
+
+        Ok(collection)
+    }
+
+    pub fn new<'a, PathIterator>(theme: &str, paths: PathIterator) -> Self
+    where PathIterator: Iterator<Item = &'a Path> {
+        Self::load(theme, icon_base_dirs(paths, dirs::BaseDirs::new().home()))
+    }
+
+    fn load(theme: &str, base_dirs: Vec<PathBuf>) -> Self {
+        let mut themes: Vec<IconTheme> = vec![];
+        let mut pending = vec![theme.to_string()];
+
+        // Depth first through Inherits, hicolor is always the last resort.
+        while let Some(name) = pending.pop() {
+            if name == "hicolor" || themes.iter().any(|th| th.name == name) {
+                continue;
+            }
+            let Some(th) = IconTheme::from_icon_dirs(&name, base_dirs.iter().map(|p| p.as_path())) else {
+                continue;
+            };
+            pending.extend(th.inherits.iter().rev().cloned());
+            themes.push(th);
+        }
+        if let Some(th) = IconTheme::from_icon_dirs("hicolor", base_dirs.iter().map(|p| p.as_path())) {
+            themes.push(th);
+        }
+
+        Self { themes, options: IconLookupOptions::default(), overrides: IconOverrides::default(), lookup_cache: RwLock::new(HashMap::new()), base_dirs }
+    }
+
+    pub fn list_icons(&self) -> Vec<IconInfo> {
+        let mut result: BTreeMap<String, IconInfo> = BTreeMap::new();
+        for th in &self.themes {
+            for info in th.icons() {
+                let Some(existing) = result.get_mut(&info.name) else {
+                    result.insert(info.name.clone(), info);
+                    continue;
+                };
+                for context in &info.contexts {
+                    existing.add_context(context);
+                }
+                for desc in &info.sizes {
+                    existing.add_size(desc);
+                }
+            }
+        }
+
+        result.into_values().collect()
+    }
+
+    pub fn options(&self) -> &IconLookupOptions {
+        &self.options
+    }
+
+    pub fn set_options(&mut self, options: IconLookupOptions) {
+        self.options = options;
+        self.lookup_cache.write().unwrap().clear();
+    }
+
+    pub fn scan(&self) -> Result<(), Vec<IconError>> {
+        let failures: Vec<IconError> = self.themes.iter().filter_map(|th| th.scan().err()).flatten().collect();
+        if failures.is_empty() { Ok(()) } else { Err(failures) }
+    }
+
+    pub fn lookup(&self, name: &str, size: usize) -> Result<Icon, IconError> {
+        self.find_icon(name, size, self.options.size_strategy).ok_or_else(|| IconError::NotFound(name.to_string()))
+    }
+
+    pub fn find_icon_or_fallback(&self, name: &str, size: usize) -> Option<Icon> {
+        self.find_icon(name, size, self.options.size_strategy).or_else(|| self.fallback_icon())
+    }
+
+    // The compiled-in icon, written to the cache directory on first use.
+    pub fn fallback_icon(&self) -> Option<Icon> {
+        if !self.options.embedded_fallback {
+            return None;
+        }
+        let path = FALLBACK_ICON_PATH.get_or_init(write_fallback_icon).clone()?;
+
+        Some(Icon { name: String::from("fallback"), path, desc: IconDescription::Scalable })
+    }
+
+    pub fn find_icon(&self, name: &str, size: usize, strategy: SizeStrategy) -> Option<Icon> {
+        let key = (name.to_string(), size, strategy);
+        if let Some(icon) = self.lookup_cache.read().unwrap().get(&key) {
+            return icon.clone();
+        }
+        let icon = self.find_icon_pred(name, size, strategy, &|_, _| true);
+        self.lookup_cache.write().unwrap().insert(key, icon.clone());
+
+        icon
+    }
+
+    pub fn find_icon_pred<Pred>(&self, name: &str, size: usize, strategy: SizeStrategy, pred: &Pred) -> Option<Icon>
+    where Pred: Fn(&Path, &IconDescription) -> bool {
+        self.find_icon_with_theme(name, size, strategy, pred).map(|(_, icon)| icon)
+    }
+
+    // As find_icon_pred, also giving the name of the theme that has the icon, or None for
+    // unthemed icons.
+    fn find_icon_with_theme<Pred>(&self, name: &str, size: usize, strategy: SizeStrategy, pred: &Pred) -> Option<(Option<&str>, Icon)>
+    where Pred: Fn(&Path, &IconDescription) -> bool {
+        self.themes.iter().find_map(|th| th.find_icon_pred(name, size, strategy, &self.options, pred).map(|icon| (Some(th.name.as_str()), icon)))
+            .or_else(|| self.find_unthemed_icon(name, pred).map(|icon| (None, icon)))
+    }
+
+    // Files placed directly in an icon base directory, outside of any theme.
+    fn find_unthemed_icon<Pred>(&self, name: &str, pred: &Pred) -> Option<Icon>
+    where Pred: Fn(&Path, &IconDescription) -> bool {
+        for base_dir in self.base_dirs.iter().rev() {
+            for ext in &self.options.extensions {
+                let path = base_dir.join(format!("{}.{}", name, ext));
+                if path.is_file() && pred(&path, &IconDescription::Unthemed) {
+                    return Some(Icon { name: name.to_string(), path, desc: IconDescription::Unthemed });
+                }
+            }
+        }
+
+        None
+    }
+
+    #[cfg(feature = "raster")]
+    pub fn render_to_png(&self, name: &str, size: usize, dest: &Path) -> std::io::Result<()> {
+        let is_png = |p: &Path, _: &IconDescription| p.extension().is_some_and(|e| e == "png");
+        let Some(icon) = self.find_icon_pred(name, size, SizeStrategy::NearestLarger, &is_png) else {
+            return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("no bitmap icon for {}", name)));
+        };
+        scale_to_png(&icon.path, size, dest)
+    }
+
+    #[cfg(feature = "raster")]
+    pub fn render_all_to_png(&self, jobs: &[(&str, PathBuf)], size: usize) -> Vec<std::io::Result<()>> {
+        let nr_threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        let chunk_size = jobs.len().div_ceil(nr_threads).max(1);
+        std::thread::scope(|s| {
+            let handles: Vec<_> = jobs.chunks(chunk_size).map(|chunk| {
+                (chunk.len(), s.spawn(move || chunk.iter().map(|(name, dest)| self.render_to_png(name, size, dest)).collect::<Vec<_>>()))
+            }).collect();
+            // A thread that panicked fails every job it had.
+            handles.into_iter().flat_map(|(len, h)| {
+                h.join().unwrap_or_else(|_| (0..len).map(|_| Err(std::io::Error::other("rendering thread panicked"))).collect())
+            }).collect()
+        })
+    }
+
+    // Every variant from the first theme providing the icon, like the lookup itself.
+    pub fn find_all_icons(&self, name: &str) -> Vec<Icon> {
+        self.themes.iter().map(|th| th.find_all_icons(name)).find(|icons| !icons.is_empty()).unwrap_or_default()
+    }
+
+    pub fn resolve(&self, source: &IconSource, size: usize) -> Option<Icon> {
+        match source {
+            IconSource::Name(name) => self.find_icon(name, size, self.options.size_strategy),
+            IconSource::Path(path) => {
+                let filename = path.file_name()?.to_str()?;
+                let (name, ext) = split_icon_filename(filename)?;
+                if !path.is_file() {
+                    return None;
+                }
+                let desc = if ext == "svg" { IconDescription::Scalable } else { IconDescription::Unthemed };
+                Some(Icon { name: name.to_string(), path: path.clone(), desc })
+            },
+        }
+    }
+
+    // Overrides for any of the ids win over the application's own Icon= value.
+    pub fn resolve_app_icon(&self, ids: &[&str], source: &IconSource, size: usize) -> Option<Icon> {
+        ids.iter().filter_map(|id| self.overrides.get(id)).find_map(|src| self.resolve(&src, size))
+            .or_else(|| self.resolve(source, size))
+    }
+
+    // Which theme and directory an icon file belongs to.
+    pub fn identify(&self, path: &Path) -> Option<IconOrigin> {
+        let canonical = path.canonicalize().ok();
+        let candidates = [Some(path.to_path_buf()), canonical];
+        for path in candidates.iter().flatten() {
+            if let Some(origin) = self.themes.iter().find_map(|th| th.identify(path)) {
+                return Some(origin);
+            }
+            let (Some(parent), Some(filename)) = (path.parent(), path.file_name().and_then(|f| f.to_str())) else {
+                continue;
+            };
+            if let (true, Some((name, _))) = (self.base_dirs.iter().any(|d| d == parent), split_icon_filename(filename)) {
+                return Some(IconOrigin {
+                    theme: None, subdir: String::new(), context: String::new(),
+                    icon: Icon { name: name.to_string(), path: path.clone(), desc: IconDescription::Unthemed },
+                });
+            }
+        }
+
+        None
+    }
+
+    // All names are tried in a theme before falling back to the inherited ones.
+    pub fn find_icon_any(&self, names: &[&str], size: usize) -> Option<Icon> {
+        self.themes.iter().find_map(|th| {
+            names.iter().find_map(|name| th.find_icon_pred(name, size, self.options.size_strategy, &self.options, &|_, _| true))
+        }).or_else(|| names.iter().find_map(|name| self.find_unthemed_icon(name, &|_, _| true)))
+    }
+
+    pub fn find_mime_icon(&self, mime_icons: &MIMEIconIndex, mime: &str, size: usize) -> Option<Icon> {
+        let names = mime_icons.icon_names(mime);
+        self.find_icon_any(&names.iter().map(|s| s.as_str()).collect::<Vec<&str>>(), size)
+    }
+}
+
+// Icons scaled to exact pixel sizes under $XDG_CACHE_HOME/xdg-desktop/icons/<theme>/<size>/,
+// regenerated whenever the source icon is newer. Only PNG sources are used, as those are
+// what the raster feature can decode.
+#[cfg(feature = "raster")]
+pub struct ScaledIconCache<'a> {
+    collection: &'a IconCollection,
+    cache_dir: PathBuf,
+}
+
+#[cfg(feature = "raster")]
+impl<'a> ScaledIconCache<'a> {
+    pub fn new(collection: &'a IconCollection) -> Self {
+        Self::with_cache_dir(collection, dirs::cache_home().join("xdg-desktop/icons"))
+    }
+
+    pub fn with_cache_dir(collection: &'a IconCollection, cache_dir: PathBuf) -> Self {
+        Self { collection, cache_dir }
+    }
+
+    pub fn get(&self, name: &str, size: usize) -> io::Result<PathBuf> {
+        let is_png = |p: &Path, _: &IconDescription| p.extension().is_some_and(|e| e == "png");
+        let Some((theme, icon)) = self.collection.find_icon_with_theme(name, size, SizeStrategy::NearestLarger, &is_png) else {
+            return Err(io::Error::new(io::ErrorKind::NotFound, IconError::NotFound(name.to_string())));
+        };
+        if icon.pixel_size() == Some(size) {
+            return Ok(icon.path);
+        }
+
+        // Other themes have other icons under the same name, so the copies are kept apart
+        // by the theme the icon came from.
+        let dir = self.cache_dir.join(theme.unwrap_or(".unthemed")).join(size.to_string());
+        let dest = dir.join(format!("{}.png", name));
+        let src_mod = fs::metadata(&icon.path)?.modified()?;
+        if let Ok(dst_mod) = fs::metadata(&dest).and_then(|md| md.modified()) {
+            if dst_mod > src_mod {
+                return Ok(dest);
+            }
+        }
+
+        fs::create_dir_all(&dir)?;
+        scale_to_png(&icon.path, size, &dest)?;
+        Ok(dest)
+    }
+}
+
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<IconCollection>();
+};
@@ -0,0 +1,183 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+const MAJOR_VERSION: u16 = 1;
+const MINOR_VERSION: u16 = 0;
+
+// Image flags, by file suffix.
+const HAS_SUFFIX_XPM: u16 = 1;
+const HAS_SUFFIX_SVG: u16 = 2;
+const HAS_SUFFIX_PNG: u16 = 4;
+const HAS_ICON_FILE: u16 = 8;
+
+fn suffix_flag(path: &Path) -> Option<u16> {
+    match path.extension()?.to_str()? {
+        "png" => Some(HAS_SUFFIX_PNG),
+        "svg" => Some(HAS_SUFFIX_SVG),
+        "xpm" => Some(HAS_SUFFIX_XPM),
+        "icon" => Some(HAS_ICON_FILE),
+        _ => None,
+    }
+}
+
+// The hash GTK uses, over signed chars.
+fn icon_name_hash(name: &str) -> u32 {
+    let mut bytes = name.bytes().map(|byte| byte as i8 as u32);
+    let Some(first) = bytes.next() else {
+        return 0;
+    };
+    bytes.fold(first, |hash, byte| (hash << 5).wrapping_sub(hash).wrapping_add(byte))
+}
+
+fn bucket_count(icons: usize) -> usize {
+    let is_prime = |n: usize| n >= 2 && (2..).take_while(|d| d * d <= n).all(|d| !n.is_multiple_of(d));
+    ((icons / 3).max(2)..).find(|n| is_prime(*n)).unwrap_or(2)
+}
+
+// icon name -> (directory index -> flags), for every icon file below `theme_dir`. A dir gets
+// the next index in `dirs` if it has icons, before its subdirs are visited. Dirs already in
+// `visited` are skipped, so symlinks cannot loop.
+fn collect_icons(theme_dir: &Path, dir: &Path, visited: &mut HashSet<PathBuf>, dirs: &mut Vec<String>, icons: &mut BTreeMap<String, BTreeMap<u16, u16>>) -> Result<()> {
+    if !visited.insert(dir.canonicalize()?) {
+        return Ok(());
+    }
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?.filter_map(|entry| entry.ok().map(|entry| entry.path())).collect();
+    entries.sort();
+    // Images refer to their dir by a 16-bit index.
+    let dir_index = u16::try_from(dirs.len())
+        .map_err(|_| Error::new(ErrorKind::InvalidData, format!("{} has too many icon dirs", theme_dir.display())))?;
+    let mut has_icons = false;
+    // Files directly in the theme dir, such as index.theme, are not icons.
+    for path in entries.iter().filter(|path| dir != theme_dir && !path.is_dir()) {
+        let (Some(flag), Some(name)) = (suffix_flag(path), path.file_stem().and_then(|stem| stem.to_str())) else {
+            continue;
+        };
+        has_icons = true;
+        *icons.entry(name.to_string()).or_default().entry(dir_index).or_default() |= flag;
+    }
+    if has_icons {
+        dirs.push(dir.strip_prefix(theme_dir).unwrap_or(dir).to_string_lossy().into_owned());
+    }
+    for path in entries.iter().filter(|path| path.is_dir()) {
+        collect_icons(theme_dir, path, visited, dirs, icons)?;
+    }
+
+    Ok(())
+}
+
+struct CacheWriter {
+    data: Vec<u8>,
+}
+
+impl CacheWriter {
+    fn offset(&self) -> u32 {
+        self.data.len() as u32
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.data.extend(value.to_be_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.data.extend(value.to_be_bytes());
+    }
+
+    fn set_u32(&mut self, at: u32, value: u32) {
+        self.data[at as usize..at as usize + 4].copy_from_slice(&value.to_be_bytes());
+    }
+
+    // A NUL-terminated string, padded to keep the next offset 4-aligned.
+    fn string(&mut self, text: &str) -> u32 {
+        let offset = self.offset();
+        self.data.extend(text.as_bytes());
+        self.data.push(0);
+        while !self.data.len().is_multiple_of(4) {
+            self.data.push(0);
+        }
+
+        offset
+    }
+}
+
+// The icon-theme.cache GTK reads for `theme_dir`, in the format of gtk-update-icon-cache
+// without embedded image data.
+pub fn build_icon_cache(theme_dir: &Path) -> Result<Vec<u8>> {
+    let mut dirs = vec![];
+    let mut icons = BTreeMap::new();
+    collect_icons(theme_dir, theme_dir, &mut HashSet::new(), &mut dirs, &mut icons)?;
+
+    let n_buckets = bucket_count(icons.len());
+    let mut buckets: Vec<Vec<&String>> = vec![vec![]; n_buckets];
+    for name in icons.keys() {
+        buckets[icon_name_hash(name) as usize % n_buckets].push(name);
+    }
+
+    let mut out = CacheWriter { data: vec![] };
+    out.u16(MAJOR_VERSION);
+    out.u16(MINOR_VERSION);
+    out.u32(12);
+    let dir_list_at = out.offset();
+    out.u32(0);
+    out.u32(n_buckets as u32);
+    let buckets_at = out.offset();
+    for _ in 0..n_buckets {
+        out.u32(0xffffffff);
+    }
+
+    for (bucket, names) in buckets.iter().enumerate() {
+        let mut link_at = buckets_at + 4 * bucket as u32;
+        for name in names {
+            let icon_at = out.offset();
+            out.set_u32(link_at, icon_at);
+            link_at = icon_at;
+            out.u32(0xffffffff);
+            out.u32(0);
+            out.u32(0);
+            let name_at = out.string(name);
+            out.set_u32(icon_at + 4, name_at);
+            let images_at = out.offset();
+            out.set_u32(icon_at + 8, images_at);
+            let images = &icons[*name];
+            out.u32(images.len() as u32);
+            for (dir_index, flags) in images {
+                out.u16(*dir_index);
+                out.u16(*flags);
+                out.u32(0);
+            }
+        }
+    }
+
+    let list_at = out.offset();
+    out.set_u32(dir_list_at, list_at);
+    out.u32(dirs.len() as u32);
+    let offsets_at = out.offset();
+    for _ in &dirs {
+        out.u32(0);
+    }
+    for (i, dir) in dirs.iter().enumerate() {
+        let dir_at = out.string(dir);
+        out.set_u32(offsets_at + 4 * i as u32, dir_at);
+    }
+
+    Ok(out.data)
+}
+
+// Writes <theme_dir>/icon-theme.cache, replacing it by a rename. GTK ignores a cache older
+// than the theme dir, so run it again after adding icons.
+pub fn write_icon_cache(theme_dir: &Path) -> Result<PathBuf> {
+    let data = build_icon_cache(theme_dir)?;
+    let path = theme_dir.join("icon-theme.cache");
+    let tmp = theme_dir.join(format!(".icon-theme.cache.{}", std::process::id()));
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, &path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })?;
+    // The rename itself changed the dir, which would make the cache look stale.
+    let dir_mtime = fs::metadata(theme_dir)?.modified()?;
+    File::options().write(true).open(&path)?.set_modified(dir_mtime.max(SystemTime::now()))?;
+
+    Ok(path)
+}
@@ -1,5 +1,49 @@
 pub mod icon;
+pub mod icon_cache;
+pub mod cursor;
 pub mod menu;
 pub mod desktop_parser;
 pub mod dirs;
+pub mod locale;
+pub mod session;
+pub mod terminal;
+pub mod autostart;
+#[cfg(unix)]
+pub mod trash;
+pub mod recent;
+pub mod thumbnails;
+pub mod bookmarks;
+pub mod appimage;
+pub mod wine;
+pub mod flatpak;
+pub mod open;
+pub mod open_with;
+pub mod usage;
+pub mod scope;
+pub mod snap;
+pub mod uri;
+pub mod defaults;
+pub mod file_manager;
+#[cfg(feature = "dbus")]
+pub mod notify;
+pub mod desktop_entry;
+#[cfg(unix)]
+pub mod trust;
+pub mod menu_edit;
+#[cfg(feature = "running")]
+pub mod running;
 pub mod mime_glob;
+pub mod mime_type;
+pub mod mime_icon;
+pub mod mime_magic;
+mod mime_bundled;
+pub mod mime_treemagic;
+pub mod mime_xmlns;
+pub mod mime_charset;
+pub mod mime_cache;
+pub mod mime_info;
+pub mod mime_compile;
+#[cfg(feature = "watch")]
+pub mod mime_watch;
+pub mod mime_db;
+pub mod cli;
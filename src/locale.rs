@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+
+use crate::dirs::{EnvProvider, StdEnv};
+
+// The locale identifiers to look up for `locale`, best first, as the desktop entry spec
+// matches them: lang_COUNTRY@MODIFIER, lang_COUNTRY, lang@MODIFIER, then lang. The
+// encoding is dropped. Empty for the C and POSIX locales.
+pub fn fallback_chain(locale: &str) -> Vec<String> {
+    let (rest, modifier) = match locale.split_once('@') {
+        Some((rest, modifier)) => (rest, Some(modifier)),
+        None => (locale, None),
+    };
+    let rest = rest.split('.').next().unwrap_or(rest);
+    let (lang, country) = match rest.split_once('_') {
+        Some((lang, country)) => (lang, Some(country)),
+        None => (rest, None),
+    };
+    if lang.is_empty() || lang == "C" || lang == "POSIX" {
+        return vec![];
+    }
+
+    let mut chain = vec![];
+    if let (Some(country), Some(modifier)) = (country, modifier) {
+        chain.push(format!("{}_{}@{}", lang, country, modifier));
+    }
+    if let Some(country) = country {
+        chain.push(format!("{}_{}", lang, country));
+    }
+    if let Some(modifier) = modifier {
+        chain.push(format!("{}@{}", lang, modifier));
+    }
+    chain.push(lang.to_string());
+
+    chain
+}
+
+// The messages locale as gettext picks it: LC_ALL, then LC_MESSAGES, then LANG, with the
+// colon separated LANGUAGE list ahead of it unless the locale is C. Each is expanded by
+// fallback_chain, keeping the first of repeated identifiers.
+pub fn current_fallback_chain_from(env: impl EnvProvider) -> Vec<String> {
+    let var = |name: &str| env.var(name).filter(|value| !value.is_empty());
+    let Some(locale) = var("LC_ALL").or_else(|| var("LC_MESSAGES")).or_else(|| var("LANG")) else {
+        return vec![];
+    };
+    let mut locales: Vec<String> = vec![];
+    if !fallback_chain(&locale).is_empty() {
+        locales.extend(var("LANGUAGE").iter().flat_map(|list| list.split(':').map(String::from)));
+    }
+    locales.push(locale);
+
+    let mut chain: Vec<String> = vec![];
+    for id in locales.iter().flat_map(|locale| fallback_chain(locale)) {
+        if !chain.contains(&id) {
+            chain.push(id);
+        }
+    }
+
+    chain
+}
+
+pub fn current_fallback_chain() -> Vec<String> {
+    current_fallback_chain_from(StdEnv)
+}
+
+// The translation for the first identifier of `chain` there is one for.
+pub fn best_match<'a, T>(chain: &[String], translations: &'a HashMap<String, T>) -> Option<&'a T> {
+    chain.iter().find_map(|id| translations.get(id))
+}
@@ -1,22 +1,34 @@
+use log::{debug, trace, warn};
 use regex::Regex;
 
+use crate::appimage;
+use crate::autostart;
 use crate::desktop_parser::{DesktopFile, DesktopParserCallback};
 use crate::dirs;
+use crate::flatpak::FlatpakExport;
+use crate::icon::{Icon, IconCollection, IconSource};
+use crate::locale;
+use crate::mime_type::MimeType;
+use crate::session::{self, Session};
+use crate::snap::SnapExport;
+use crate::uri;
+use crate::wine;
 use core::{fmt, str};
 use std::collections::HashMap;
-use std::env;
-use std::ffi::OsString;
 use std::fs::{read_dir, File, OpenOptions};
 use std::io::Write;
 use std::mem::swap;
+#[cfg(unix)]
+use std::os::fd::AsRawFd;
 use std::path::{Path, PathBuf};
-use std::str::FromStr;
 
 pub struct MenuItemDetailEntry {
     pub exec: String,
     pub wmclass: String,
     pub is_terminal: bool,
-    pub mimes: Vec<String>,
+    pub mimes: Vec<MimeType>,
+    // PrefersNonDefaultGPU or X-KDE-RunOnDiscreteGpu.
+    pub prefers_non_default_gpu: bool,
 }
 
 pub enum MenuItemDetail {
@@ -27,25 +39,30 @@ pub enum MenuItemDetail {
 
 impl MenuItemDetailEntry {
     fn guess_wmclass(&mut self) -> String {
-	let args = self.exec.split(" ").collect::<Vec<&str>>();
+	// Wrappers such as "env NAME=VALUE cmd" are skipped.
+	let args = self.exec.split(" ").skip_while(|arg| *arg == "env" || arg.contains('=')).collect::<Vec<&str>>();
+	if args.is_empty() {
+	    return String::new();
+	}
 	let cmd_prefix = "--command=";
 	if args[0].ends_with("flatpak") {
 	    for arg in &args[1..] {
-		if arg.starts_with(cmd_prefix) {
-		    return String::from(&arg[cmd_prefix.len()..]);
+		if let Some(command) = arg.strip_prefix(cmd_prefix) {
+		    return String::from(command);
 		}
 	    }
 	}
 
-	return String::from(args[0].split("/").last().unwrap());
+	String::from(args[0].split("/").last().unwrap())
     }
     pub fn exec_with_filenames(&self, paths: &Vec<&PathBuf>) -> Vec<String> {
         let escape_path = |m: &str, p: &&PathBuf| -> String {
-            let s = p.to_str().unwrap().replace('\'', "\\\'");
             if m == "%U" || m == "%u" {
-                format!("\"file://{}\"", s)
+                // Percent-encoded URIs have no characters the shell would expand in single
+                // quotes, except quotes themselves.
+                format!("'{}'", uri::path_to_uri(p).replace('\'', "'\\''"))
             } else {
-                format!("\"{}\"", s)
+                format!("\"{}\"", p.to_str().unwrap().replace('\'', "\\\'"))
             }
         };
         let marker_regex = Regex::new("%[uUfF%]").unwrap();
@@ -67,11 +84,38 @@ impl MenuItemDetailEntry {
                     next_path_id += 1;
                 }
             }
+            fragments.push(self.exec[sstart..].to_string());
             result.push(fragments.join(""));
         }
 
         result
     }
+
+    // Exec with no files or URLs, to start the application from a menu.
+    pub fn exec_without_args(&self) -> String {
+        autostart::strip_field_codes(&self.exec)
+    }
+
+    // Exec for a URL other than a local file. Only %u and %U can take it; file codes are
+    // dropped. Without either the URL is appended, as xdg-open does.
+    pub fn exec_with_url(&self, url: &str) -> String {
+        let marker_regex = Regex::new("%[a-zA-Z%]").unwrap();
+        let quoted = format!("'{}'", url.replace('\'', "'\\''"));
+        let mut takes_url = false;
+        let exec = marker_regex.replace_all(&self.exec, |m: &regex::Captures| match &m[0] {
+            "%u" | "%U" => {
+                takes_url = true;
+                quoted.clone()
+            }
+            "%%" => String::from("%"),
+            _ => String::new(),
+        }).trim().to_string();
+
+        match takes_url {
+            true => exec,
+            false => format!("{} {}", exec, quoted),
+        }
+    }
 }
 
 pub struct MenuItem {
@@ -80,7 +124,14 @@ pub struct MenuItem {
     pub categories: String,
     pub basename: String,
     idx: usize,
+    // NoDisplay=true, or left out of the current desktop by OnlyShowIn or NotShowIn.
     pub hidden: bool,
+    // X-Flatpak, for apps exported by Flatpak.
+    pub flatpak_id: Option<String>,
+    // The snap instance, for apps exported by snapd.
+    pub snap_name: Option<String>,
+    // Made by Wine or Proton for a Windows program, see wine::is_wine_entry.
+    pub is_wine: bool,
     pub detail: MenuItemDetail,
 }
 
@@ -88,23 +139,35 @@ impl MenuItem {
     fn new() -> Self {
 	MenuItem {
 	    name: String::new(), icon: String::new(), categories: String::new(),
-	    idx: 0, basename: String::new(), hidden: false, detail: MenuItemDetail::Unknown,
+	    idx: 0, basename: String::new(), hidden: false, flatpak_id: None, snap_name: None, is_wine: false, detail: MenuItemDetail::Unknown,
 	}
     }
     fn root() -> Self {
 	MenuItem {
 	    name: String::from("FvwmApplications"), icon: String::from("_root"), categories: String::new(),
-	    idx: 0, basename: String::from(""), hidden: true, detail: MenuItemDetail::Directory,
+	    idx: 0, basename: String::from(""), hidden: true, flatpak_id: None, snap_name: None, is_wine: false, detail: MenuItemDetail::Directory,
 	}
     }
 
     fn other() -> Self {
 	MenuItem {
 	    name: String::from("Others"), icon: String::from("applications-other"), categories: String::new(),
-	    idx: 1, basename: String::from("__other_apps"), hidden: false, detail: MenuItemDetail::Directory,
+	    idx: 1, basename: String::from("__other_apps"), hidden: false, flatpak_id: None, snap_name: None, is_wine: false, detail: MenuItemDetail::Directory,
 	}
     }
 
+    pub fn icon_source(&self) -> IconSource {
+        IconSource::from(self.icon.as_str())
+    }
+
+    pub fn resolve_icon(&self, icons: &IconCollection, size: usize) -> Option<Icon> {
+        let mut ids = vec![self.basename.as_str()];
+        if let Some(ent) = self.detail_entry() {
+            ids.push(ent.wmclass.as_str());
+        }
+        icons.resolve_app_icon(&ids, &self.icon_source(), size)
+    }
+
     pub fn detail_entry(&self) -> Option<&MenuItemDetailEntry> {
         if let MenuItemDetail::Entry(ent) = &self.detail {
             Some(ent)
@@ -150,7 +213,7 @@ impl Menu {
 		    };
 		    submenu.print(index, printer);
 		},
-		_ => printer.print(&item),
+		_ => printer.print(item),
 	    }
 	}
 	printer.leave_menu(menu_ref);
@@ -158,12 +221,18 @@ impl Menu {
 }
 
 struct MenuIndexDesktopParser {
-    name_str: String,
+    // "Name[lang]" keys along the locale fallback chain, then "Name".
+    name_keys: Vec<String>,
+    // Position in name_keys of the key the current name came from.
+    name_rank: usize,
     filename: String,
+    path: PathBuf,
 
     current: MenuItem,
     current_key: String,
     in_action: bool,
+    // From session::Session, for OnlyShowIn and NotShowIn.
+    current_desktops: Vec<String>,
 }
 
 impl DesktopParserCallback for MenuIndexDesktopParser {
@@ -171,12 +240,12 @@ impl DesktopParserCallback for MenuIndexDesktopParser {
 	if name.starts_with(b"Desktop Action") {
 	    self.in_action = true;
 	} else if name.starts_with(b"Desktop Entry") {
-	    self.current.detail = MenuItemDetail::Entry(MenuItemDetailEntry{ exec: String::new(), wmclass: String::new(), is_terminal: false, mimes: vec![] })
+	    self.current.detail = MenuItemDetail::Entry(MenuItemDetailEntry{ exec: String::new(), wmclass: String::new(), is_terminal: false, mimes: vec![], prefers_non_default_gpu: false })
 	} else {
-            eprintln!("Unrecognized section {}", String::from_utf8_lossy(name));
+            debug!("Unrecognized section {}", String::from_utf8_lossy(name));
             return false;
 	}
-        return true;
+        true
     }
     fn on_key(&mut self, key: &[u8]) -> bool {
 	if !self.in_action {
@@ -192,23 +261,35 @@ impl DesktopParserCallback for MenuIndexDesktopParser {
 
 	if self.current_key == "Type" && value == b"Directory" {
 	    self.current.detail = MenuItemDetail::Directory;
-	} else if self.current_key == self.name_str {
-	    self.current.name = decode(value);
+	} else if let Some(rank) = self.name_keys.iter().position(|key| *key == self.current_key) {
+	    if rank <= self.name_rank {
+		self.current.name = decode(value);
+		self.name_rank = rank;
+	    }
 	} else if self.current_key == "Icon" {
 	    self.current.icon = decode(value);
 	} else if self.current_key == "Categories" {
 	    self.current.categories = decode(value);
 	} else if self.current_key == "NoDisplay" {
-	    self.current.hidden = value.to_ascii_lowercase() == b"true";
+	    self.current.hidden |= value.eq_ignore_ascii_case(b"true");
+	} else if self.current_key == "OnlyShowIn" || self.current_key == "NotShowIn" {
+	    let listed = decode(value).split(';').any(|desktop| self.current_desktops.iter().any(|current| current == desktop));
+	    self.current.hidden |= listed == (self.current_key == "NotShowIn");
+	} else if self.current_key == "X-Flatpak" {
+	    self.current.flatpak_id = Some(decode(value));
+	} else if self.current_key == "X-SnapInstanceName" {
+	    self.current.snap_name = Some(decode(value));
 	} else if let MenuItemDetail::Entry(detail) = &mut self.current.detail {
 	    if self.current_key == "Exec" {
 		detail.exec = decode(value);
 	    } else if self.current_key == "StartupWMClass" {
 		detail.wmclass = decode(value);
 	    } else if self.current_key == "Terminal" {
-                detail.is_terminal = value.to_ascii_lowercase() == b"true";
+                detail.is_terminal = value.eq_ignore_ascii_case(b"true");
+            } else if self.current_key == "PrefersNonDefaultGPU" || self.current_key == "X-KDE-RunOnDiscreteGpu" {
+                detail.prefers_non_default_gpu |= value.eq_ignore_ascii_case(b"true");
             } else if self.current_key == "MimeType" {
-                detail.mimes = String::from_utf8_lossy(value).split(';').map(|s| s.to_string()).collect();
+                detail.mimes = String::from_utf8_lossy(value).split(';').filter_map(MimeType::parse).collect();
             }
 	}
 
@@ -221,17 +302,17 @@ pub enum AssocType {
     Default, Add, Remove,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct Assoc {
     pub filename: String,
-    pub mime: String,
+    pub mime: MimeType,
     pub assoc_type: AssocType,
 }
 
 impl fmt::Display for AssocType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            AssocType::Add => write!(f, "Add Associations"),
+            AssocType::Add => write!(f, "Added Associations"),
             AssocType::Remove => write!(f, "Removed Associations"),
             AssocType::Default => write!(f, "Default Applications"),
         }
@@ -239,7 +320,8 @@ impl fmt::Display for AssocType {
 }
 
 struct MenuIndexAssocParser {
-    cur_mime: String,
+    // None while inside an entry with an invalid type.
+    cur_mime: Option<MimeType>,
     cur_assoc: AssocType,
 
     assocs: Vec<Assoc>,
@@ -249,12 +331,12 @@ impl DesktopParserCallback for MenuIndexAssocParser {
     fn on_section(&mut self, name: &[u8]) -> bool {
         if name.starts_with(b"Default Applications") {
             self.cur_assoc = AssocType::Default;
-        } else if name.starts_with(b"Add Associations") {
+        } else if name.starts_with(b"Added Associations") || name.starts_with(b"Add Associations") {
             self.cur_assoc = AssocType::Add;
         } else if name.starts_with(b"Removed Associations") {
             self.cur_assoc = AssocType::Remove;
         } else {
-            eprintln!("Unrecognized section {}", String::from_utf8_lossy(name));
+            debug!("Unrecognized section {}", String::from_utf8_lossy(name));
             return false;
         }
 
@@ -262,19 +344,22 @@ impl DesktopParserCallback for MenuIndexAssocParser {
     }
 
     fn on_key(&mut self, key: &[u8]) -> bool {
-        self.cur_mime = String::from_utf8_lossy(key).to_string();
+        self.cur_mime = MimeType::parse(&String::from_utf8_lossy(key));
         true
     }
 
     fn on_value(&mut self, value: &[u8]) -> bool {
-        for s in value.to_vec().split(|ch| *ch == b';') {
-            if s.len() == 0 {
+        let Some(mime) = &self.cur_mime else {
+            return true;
+        };
+        for s in value.split(|ch| *ch == b';') {
+            if s.is_empty() {
                 continue;
             }
             let Ok(filename) = str::from_utf8(s) else {
                 continue;
             };
-            self.assocs.push(Assoc { filename: filename.to_string(), mime: self.cur_mime.clone(), assoc_type: self.cur_assoc });
+            self.assocs.push(Assoc { filename: filename.to_string(), mime: mime.clone(), assoc_type: self.cur_assoc });
         }
 
         true
@@ -288,17 +373,86 @@ pub struct MenuAssociation {
 
 pub struct MenuIndex {
     pub index: HashMap<String, Menu>,
-    pub mime_assoc_index: HashMap<String, MenuAssociation>,
+    pub mime_assoc_index: HashMap<MimeType, MenuAssociation>,
     pub items: Vec<MenuItem>,
     pub local_assocs: Vec<Assoc>,
+    // local_assocs as last read or written, to tell our changes from those of other writers.
+    saved_assocs: Vec<Assoc>,
 
     filename_index: HashMap<String, usize>,
 
     desk_parser: MenuIndexDesktopParser,
     assoc_parser: MenuIndexAssocParser,
+    base_dirs: dirs::BaseDirs,
+    // From session::Session::mimeapps_names.
+    mimeapps_names: Vec<String>,
+    // Dirs with AppImages to add to the menu, see add_appimage_dir.
+    appimage_dirs: Vec<PathBuf>,
+    // The name of the submenu Wine entries go to, see group_wine_entries.
+    wine_menu: Option<String>,
 }
 
-fn decode(bytes: &[u8]) -> String { return String::from_utf8_lossy(bytes).into_owned(); }
+// The associations of a mimeapps.list, or None if it cannot be read.
+fn read_assocs(path: &Path) -> Option<Vec<Assoc>> {
+    let mut parser = MenuIndexAssocParser { cur_mime: None, cur_assoc: AssocType::Default, assocs: vec![] };
+    DesktopFile::new(File::open(path).ok()?).ok()?.parse(&mut parser);
+
+    Some(parser.assocs)
+}
+
+// A mimeapps.list with a group per association type and a line per type, listing its
+// desktop files in order.
+fn assocs_to_string(assocs: &[Assoc]) -> String {
+    let mut content = String::new();
+    for assoc_type in [AssocType::Default, AssocType::Add, AssocType::Remove] {
+        let of_type: Vec<&Assoc> = assocs.iter().filter(|assoc| assoc.assoc_type == assoc_type).collect();
+        if of_type.is_empty() {
+            continue;
+        }
+        if !content.is_empty() {
+            content.push('\n');
+        }
+        content += &format!("[{}]\n", assoc_type);
+        let mut mimes: Vec<&MimeType> = vec![];
+        for assoc in &of_type {
+            if !mimes.contains(&&assoc.mime) {
+                mimes.push(&assoc.mime);
+            }
+        }
+        for mime in mimes {
+            let filenames: String = of_type.iter().filter(|assoc| assoc.mime == *mime).map(|assoc| assoc.filename.clone() + ";").collect();
+            content += &format!("{}={}\n", mime, filenames);
+        }
+    }
+
+    content
+}
+
+// Holds an exclusive flock on `path`, created if needed, until the file is dropped.
+#[cfg(unix)]
+fn lock_file(path: &Path) -> std::io::Result<File> {
+    let file = OpenOptions::new().write(true).create(true).truncate(false).open(path)?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(file)
+}
+
+// Other systems go without the lock; the merge with the current file still applies.
+#[cfg(not(unix))]
+fn lock_file(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().write(true).create(true).truncate(false).open(path)
+}
+
+// The basename of the submenu made by MenuIndex::group_wine_entries.
+const WINE_MENU: &str = "__wine_apps";
+
+fn decode(bytes: &[u8]) -> String { String::from_utf8_lossy(bytes).into_owned() }
+
+fn name_keys(chain: &[String]) -> Vec<String> {
+    chain.iter().map(|lang| format!("Name[{}]", lang)).chain([String::from("Name")]).collect()
+}
 
 impl MenuIndex {
     pub fn new_default() -> Self {
@@ -306,33 +460,63 @@ impl MenuIndex {
     }
 
     pub fn new(locale: Option<String>) -> Self {
-	let mut name_str = String::from("Name");
-	if let Some(lc) = locale {
-	    name_str += "[";
-	    name_str += &lc;
-	    name_str += "]";
-	}
+	MenuIndex::with_base_dirs(locale, dirs::BaseDirs::new())
+    }
+
+    pub fn with_env(locale: Option<String>, env: impl dirs::EnvProvider) -> Self {
+	let session = Session::from_env(|name: &str| env.var(name));
+	let chain = match locale {
+	    Some(lc) => locale::fallback_chain(&lc),
+	    None => locale::current_fallback_chain_from(|name: &str| env.var(name)),
+	};
+	let mut index = MenuIndex::with_session(None, dirs::BaseDirs::from_env(env), &session);
+	index.desk_parser.name_keys = name_keys(&chain);
+
+	index
+    }
+
+    // Scans and writes under `base_dirs` rather than the process environment. Names are
+    // localized for `locale`, or for the locale of the process when it is None.
+    pub fn with_base_dirs(locale: Option<String>, base_dirs: dirs::BaseDirs) -> Self {
+	MenuIndex::with_session(locale, base_dirs, &session::current())
+    }
+
+    // As with_base_dirs, for the desktops of `session` instead of the current one. They pick
+    // the desktop-specific mimeapps.list files and hide items by OnlyShowIn and NotShowIn.
+    pub fn with_session(locale: Option<String>, base_dirs: dirs::BaseDirs, session: &Session) -> Self {
+	let chain = match locale {
+	    Some(lc) => locale::fallback_chain(&lc),
+	    None => locale::current_fallback_chain(),
+	};
 	let other_item = MenuItem::other();
         let desk_parser = MenuIndexDesktopParser {
-            name_str,
+            name_keys: name_keys(&chain),
+            name_rank: usize::MAX,
 	    filename: other_item.basename.clone(),
+	    path: PathBuf::new(),
 	    current: other_item,
 	    current_key: String::new(),
 	    in_action: false,
+	    current_desktops: session.current_desktops.clone(),
         };
         let assoc_parser = MenuIndexAssocParser {
-            cur_mime: String::new(),
+            cur_mime: None,
             cur_assoc: AssocType::Default,
             assocs: vec![],
         };
-	return MenuIndex {
+	MenuIndex {
 	    index: HashMap::from([(String::new(), Menu::new(0))]),
             mime_assoc_index: HashMap::new(),
 	    items: vec![MenuItem::root()],
             local_assocs: Vec::new(),
+            saved_assocs: Vec::new(),
             filename_index: HashMap::new(),
 	    desk_parser,
             assoc_parser,
+            base_dirs,
+            mimeapps_names: session.mimeapps_names(),
+            appimage_dirs: vec![],
+            wine_menu: None,
 	}
     }
 
@@ -340,12 +524,25 @@ impl MenuIndex {
 	let mut current = MenuItem::new();
 	swap(&mut current, &mut self.desk_parser.current);
 	self.desk_parser.in_action = false;
+	self.desk_parser.name_rank = usize::MAX;
 	if !current.name.is_empty() {
 	    current.basename = self.desk_parser.filename.clone();
 	    current.idx = self.items.len();
 	    if let MenuItemDetail::Directory = current.detail {
 		self.index.insert(self.desk_parser.filename.clone(), Menu::new(current.idx));
 	    } else if let MenuItemDetail::Entry(detail) = &mut current.detail {
+		// Run Flatpak exports with the flatpak on PATH, keeping only what selects the app.
+		let export = current.flatpak_id.as_ref().and_then(|app_id| FlatpakExport::from_exec(app_id, &detail.exec));
+		if let Some(export) = export {
+		    detail.exec = export.exec();
+		}
+		current.is_wine = wine::is_wine_entry(&detail.exec, &current.categories, &self.desk_parser.path);
+		// Snap apps start through their wrapper in /snap/bin.
+		let snap = SnapExport::from_exec(current.snap_name.as_deref(), &detail.exec);
+		if let Some(snap) = snap {
+		    detail.exec = snap.exec(&self.desk_parser.path);
+		    current.snap_name = Some(snap.instance_name);
+		}
 		if detail.wmclass.is_empty() {
 		    // Guess the wmclass
 		    detail.wmclass = detail.guess_wmclass();
@@ -355,19 +552,31 @@ impl MenuIndex {
 
             return true;
 	}
-        return false;
+        false
     }
     fn assoc_parser_reset(&mut self) -> Vec<Assoc> {
-        self.assoc_parser.cur_mime = String::new();
+        self.assoc_parser.cur_mime = None;
         let mut result: Vec<Assoc> = vec![];
         swap(&mut result, &mut self.assoc_parser.assocs);
 
         result
     }
 
+    // Also lists the AppImages directly in `dir`, e.g. ~/Applications, on the next scan.
+    // Their desktop files are made under $XDG_CACHE_HOME/appimages.
+    pub fn add_appimage_dir(&mut self, dir: impl Into<PathBuf>) {
+        self.appimage_dirs.push(dir.into());
+    }
+
+    // Puts the entries Wine and Proton make in a submenu of their own named `name`, e.g.
+    // "Wine", instead of Others, on the next scan.
+    pub fn group_wine_entries(&mut self, name: &str) {
+        self.wine_menu = Some(name.to_string());
+    }
+
     pub fn scan(&mut self) {
-        let paths = dirs::xdg_data_dirs();
-        self.scan_all(paths.iter().map(|s| Path::new(s)));
+        let paths = dirs::existing_only(self.base_dirs.data_dirs_scan_order());
+        self.scan_all(paths.iter().map(PathBuf::as_path));
     }
 
     pub fn scan_all<'a, PathIterator>(&mut self, paths: PathIterator)
@@ -379,12 +588,40 @@ impl MenuIndex {
 		self.scan_prefix_path(p);
 	    }
 	}
+        for desktop_file in appimage::desktop_files_for(&self.base_dirs, &self.appimage_dirs) {
+            self.scan_file(&desktop_file, "");
+        }
+        // mimeapps.list in the config dirs takes precedence over the ones in the data dirs.
+        for dir in self.base_dirs.config_dirs_in_precedence().iter().rev() {
+            let assocs = self.scan_mimeapps(&dir.join("mimeapps.list"));
+            if dir == self.base_dirs.config_home() {
+                if let Some(assocs) = assocs {
+                    self.saved_assocs = assocs.clone();
+                    self.local_assocs = assocs;
+                }
+            }
+            self.scan_desktop_mimeapps(dir);
+        }
+
+        if let Some(name) = &self.wine_menu {
+            let idx = self.items.len();
+            self.items.push(MenuItem {
+                name: name.clone(), icon: String::from("wine"), categories: String::new(),
+                idx, basename: String::from(WINE_MENU), hidden: false, flatpak_id: None, snap_name: None, is_wine: false,
+                detail: MenuItemDetail::Directory,
+            });
+            self.index.insert(String::from(WINE_MENU), Menu::new(idx));
+        }
 
 	// Connect all items.
 	for item in &self.items {
 	    if item.idx == 0 {
 		continue;
 	    }
+	    if item.is_wine && self.wine_menu.is_some() {
+		self.index.get_mut(WINE_MENU).unwrap().children.push(item.idx);
+		continue;
+	    }
 
 	    if item.categories.is_empty() {
 		if let MenuItemDetail::Directory = item.detail {
@@ -395,16 +632,16 @@ impl MenuIndex {
 
 	    let mut in_menu = false;
 	    for key in item.categories.split(";") {
-		if key == "" { continue; }
+		if key.is_empty() { continue; }
 		if let Some(menu) = self.index.get_mut(key) {
 		    menu.children.push(item.idx);
 		    in_menu = true;
 		} else {
-		    // eprintln!("Cannot find category {} in {}", key, item.basename);
+		    trace!("Cannot find category {} in {}", key, item.basename);
 		}
 	    }
 	    if item.basename != "__other_apps" && !in_menu {
-		// eprintln!("adding {} Others...", item.basename);
+		trace!("adding {} Others...", item.basename);
 		self.index.get_mut("__other_apps").unwrap().children.push(item.idx);
 	    }
 	}
@@ -422,116 +659,205 @@ impl MenuIndex {
         }
     }
 
+    // Parses one .desktop or .directory file into an item.
+    fn scan_file(&mut self, path: &Path, prefix: &str) {
+	let Some(filename) = path.file_name().unwrap().to_str() else {
+	    warn!("cannot decode filename {}", &path.display());
+	    return;
+	};
+
+	self.desk_parser.filename = String::from(prefix) + &filename[..filename.len() - path.extension().unwrap().len() - 1];
+	self.desk_parser.path = path.to_path_buf();
+	let Ok(file) = File::open(path) else {
+	    warn!("Cannot open {}", path.display());
+	    return;
+	};
+	let Ok(parser) = DesktopFile::new(file) else {
+	    warn!("Cannot parse {}", path.display());
+	    return;
+	};
+
+	trace!("Parsing file {}", path.display());
+	parser.parse(&mut self.desk_parser);
+	if self.desk_parser_reset() {
+            self.filename_index.insert(String::from(prefix) + filename, self.items.len() - 1);
+        }
+    }
+
+    // Adds a desktop file from outside the applications dirs, e.g. one on the desktop, and
+    // returns its index into items.
+    pub fn add_desktop_file(&mut self, path: &Path) -> Option<usize> {
+	let count = self.items.len();
+	if path.extension().is_none_or(|ext| ext != "desktop") {
+	    return None;
+	}
+	self.scan_file(path, "");
+
+	(self.items.len() > count).then(|| self.items.len() - 1)
+    }
+
+    fn scan_dir(&mut self, dir: &Path, ext: &str) {
+	let Ok(entries) = read_dir(dir) else {
+	    return;
+	};
+	for dirent in entries {
+	    let Ok(ent) = dirent else {
+		warn!("invalid dirent");
+		continue;
+	    };
+	    let path = ent.path();
+	    if !path.is_file() || path.extension().is_none_or(|e| e != ext) {
+		trace!("ignoring file {} expecting ext {}", &path.display(), ext);
+		continue;
+	    }
+	    self.scan_file(&path, "");
+	}
+    }
+
     fn scan_prefix_path(&mut self, p: &Path) {
 	let app_dir = p.join("applications");
 	let dir_dir = p.join("desktop-directories");
 	for (p, ext) in [(app_dir, "desktop"), (dir_dir, "directory")] {
-	    let Ok(dir) = read_dir(&p) else {
-		continue;
-	    };
-	    for dirent in dir {
-		let Ok(ent) = dirent else {
-		    eprintln!("invalid dirent");
-		    continue;
-		};
-		let path = ent.path();
-		if !path.is_file() || !path.extension().is_some_and(|e| e == ext) {
-		    // eprintln!("ignoring file {} expecting ext {}", &path.display(), ext);
-		    continue;
-		}
-		let Some(filename) = path.file_name().unwrap().to_str() else {
-		    eprintln!("cannot decode filename {}", &path.display());
-		    continue;
-		};
-
-		self.desk_parser.filename = filename[..filename.len() - path.extension().unwrap().len() - 1].to_string();
-		let Ok(file) = File::open(path.clone()) else {
-		    eprintln!("Cannot open {}", path.to_str().unwrap());
-		    continue;
-		};
-		let Ok(parser) = DesktopFile::new(file) else {
-		    eprintln!("Cannot parse {}", path.to_str().unwrap());
-		    continue;
-		};
-
-		// eprintln!("Parsing file {}", path.to_str().unwrap());
-		parser.parse(&mut self.desk_parser);
-		if self.desk_parser_reset() {
-                    self.filename_index.insert(filename.to_string(), self.items.len() - 1);
-                }
-	    }
+	    self.scan_dir(&p, ext);
             if ext == "directory" {
                 continue;
             }
 
-            let Ok(mime_assoc_file) = File::open(p.join("mimeapps.list")) else {
+            let assocs = self.scan_mimeapps(&p.join("mimeapps.list"));
+            if p == self.base_dirs.data_home().join("applications") {
+                if let Some(assocs) = assocs {
+                    self.saved_assocs = assocs.clone();
+                    self.local_assocs = assocs;
+                }
+            }
+            self.scan_desktop_mimeapps(&p);
+	}
+    }
+
+    // The $desktop-mimeapps.list files of the current desktops in `dir`, which take
+    // precedence over its mimeapps.list.
+    fn scan_desktop_mimeapps(&mut self, dir: &Path) {
+        for name in self.mimeapps_names.clone().iter().rev() {
+            self.scan_mimeapps(&dir.join(name));
+        }
+    }
+
+    // Applies a mimeapps.list to the entries indexed so far. Returns what it holds, or None
+    // if it cannot be read.
+    fn scan_mimeapps(&mut self, path: &Path) -> Option<Vec<Assoc>> {
+        let assoc_parser = DesktopFile::new(File::open(path).ok()?).ok()?;
+        assoc_parser.parse(&mut self.assoc_parser);
+        let assocs = self.assoc_parser_reset();
+        for assoc in assocs.iter().cloned() {
+            let Some(idx) = self.filename_index.get(&assoc.filename) else {
                 continue;
             };
-            let Ok(assoc_parser) = DesktopFile::new(mime_assoc_file) else {
+            let MenuItemDetail::Entry(ent) = &mut self.items[*idx].detail else {
                 continue;
             };
-            assoc_parser.parse(&mut self.assoc_parser);
-            let assocs = self.assoc_parser_reset();
-            let local_dir = env::var("HOME").unwrap_or("/root".to_string()) + "/.local/share/applications";
-            if p == OsString::from_str(local_dir.as_str()).unwrap() {
-                self.local_assocs = assocs.clone();
-            }
-            for assoc in assocs {
-                let Some(idx) = self.filename_index.get(&assoc.filename) else {
-                    continue;
-                };
-                let MenuItemDetail::Entry(ent) = &mut self.items[*idx].detail else {
-                    continue;
-                };
-
-                if assoc.assoc_type == AssocType::Add {
-                    ent.mimes.push(assoc.mime);
-                } else if assoc.assoc_type == AssocType::Remove {
-                    if let Some(to_remove) = ent.mimes.iter().position(|m| *m == assoc.mime) {
-                        ent.mimes.remove(to_remove);
-                    }
-                } else if assoc.assoc_type == AssocType::Default {
-                    self.mime_assoc_index.insert(assoc.mime.clone(), MenuAssociation { default: Some(*idx), all: vec![] });
+
+            if assoc.assoc_type == AssocType::Add {
+                ent.mimes.push(assoc.mime);
+            } else if assoc.assoc_type == AssocType::Remove {
+                if let Some(to_remove) = ent.mimes.iter().position(|m| *m == assoc.mime) {
+                    ent.mimes.remove(to_remove);
                 }
+            } else if assoc.assoc_type == AssocType::Default {
+                self.mime_assoc_index.insert(assoc.mime.clone(), MenuAssociation { default: Some(*idx), all: vec![] });
             }
-	}
+        }
+
+        Some(assocs)
     }
 
     pub fn print(&self, printer: &mut impl MenuPrinter) {
 	self.index.get("").unwrap().print(self, printer);
     }
 
-    pub fn change_default_assoc(&mut self, mime: &str, idx: usize) {
+    pub fn change_default_assoc(&mut self, mime: &MimeType, idx: usize) {
         let filename = self.items[idx].basename.clone() + ".desktop";
-        let mut old_default: Option<usize> = None;
-        if self.mime_assoc_index.get_mut(mime).map(|assoc| { old_default = std::mem::replace(&mut assoc.default, Some(idx)); }).is_none() {
-            self.mime_assoc_index.insert(mime.to_string(), MenuAssociation { default: Some(idx), all: Vec::new() });
+        if self.mime_assoc_index.get_mut(mime).map(|assoc| { assoc.default = Some(idx); }).is_none() {
+            self.mime_assoc_index.insert(mime.clone(), MenuAssociation { default: Some(idx), all: Vec::new() });
         }
 
-        if old_default.is_none() {
-            self.local_assocs.push(Assoc { filename, mime: mime.to_string(), assoc_type: AssocType::Default });
-            return;
+        // The user's own default for the type, if any, is replaced.
+        match self.local_assocs.iter_mut().find(|assoc| assoc.assoc_type == AssocType::Default && assoc.mime == *mime) {
+            Some(assoc) => assoc.filename = filename,
+            None => self.local_assocs.push(Assoc { filename, mime: mime.clone(), assoc_type: AssocType::Default }),
         }
+    }
 
-        for assoc in self.local_assocs.iter_mut() {
-            if assoc.assoc_type == AssocType::Default && assoc.filename == filename && assoc.mime.as_str() == mime {
-                assoc.filename = filename;
-                break;
-            }
+    pub fn base_dirs(&self) -> &dirs::BaseDirs {
+        &self.base_dirs
+    }
+
+    // The desktop-specific mimeapps.list names it applies, highest precedence first.
+    pub fn mimeapps_names(&self) -> &[String] {
+        &self.mimeapps_names
+    }
+
+    // The item of a desktop file by its name, e.g. "firefox.desktop".
+    pub fn find_desktop_file(&self, filename: &str) -> Option<usize> {
+        self.filename_index.get(filename).copied()
+    }
+
+    // The desktop file name of the default application for `mime`, as `xdg-mime query
+    // default` prints it.
+    pub fn default_desktop_file(&self, mime: &MimeType) -> Option<String> {
+        let idx = self.mime_assoc_index.get(mime)?.default?;
+
+        Some(self.items[idx].basename.clone() + ".desktop")
+    }
+
+    // Makes the application of desktop file `filename` the default for `mimes` and saves
+    // mimeapps.list, as `xdg-mime default` does.
+    pub fn set_default_app(&mut self, filename: &str, mimes: &[MimeType]) -> std::io::Result<()> {
+        let Some(idx) = self.find_desktop_file(filename) else {
+            return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("application '{}' not found", filename)));
+        };
+        for mime in mimes {
+            self.change_default_assoc(mime, idx);
         }
+
+        self.write_default_assoc()
     }
 
-    pub fn write_default_assoc(&self) -> std::io::Result<()> {
-        let mut file = OpenOptions::new().write(true).truncate(true).create(true).open(env::var("HOME").unwrap_or("/root".to_string()) + "/.local/share/applications/mimeapps.list")?;
-        let mut cur_sec: Option<AssocType> = None;
-        for assoc in &self.local_assocs {
-            if cur_sec != Some(assoc.assoc_type) {
-                file.write_fmt(format_args!("[{}]\n", assoc.assoc_type))?;
-                cur_sec = Some(assoc.assoc_type);
+    // Writes to $XDG_CONFIG_HOME/mimeapps.list, where the spec wants user changes. Other
+    // programs may have changed the file since the scan, so it is read again and only the
+    // changes made to local_assocs since are applied to it, with a new default for a type
+    // replacing theirs. Writers using this crate take turns through a lock file, and the file
+    // is replaced by a rename, so readers never see it half written.
+    pub fn write_default_assoc(&mut self) -> std::io::Result<()> {
+        let config_home = self.base_dirs.config_home().to_path_buf();
+        dirs::create_private_dir(&config_home)?;
+        let path = config_home.join("mimeapps.list");
+        let _lock = lock_file(&config_home.join(".mimeapps.list.lock"))?;
+
+        let removed: Vec<&Assoc> = self.saved_assocs.iter().filter(|assoc| !self.local_assocs.contains(assoc)).collect();
+        let added: Vec<&Assoc> = self.local_assocs.iter().filter(|assoc| !self.saved_assocs.contains(assoc)).collect();
+        let mut merged = read_assocs(&path).unwrap_or_else(|| self.saved_assocs.clone());
+        merged.retain(|assoc| !removed.contains(&assoc));
+        for assoc in added {
+            if assoc.assoc_type == AssocType::Default {
+                merged.retain(|other| other.assoc_type != AssocType::Default || other.mime != assoc.mime);
+            }
+            if !merged.contains(assoc) {
+                merged.push(assoc.clone());
             }
-            file.write_fmt(format_args!("{}={}\n", &assoc.mime, &assoc.filename))?;
         }
 
+        let tmp = config_home.join(format!(".mimeapps.list.{}", std::process::id()));
+        let written = OpenOptions::new().write(true).truncate(true).create(true).open(&tmp)
+            .and_then(|mut file| file.write_all(assocs_to_string(&merged).as_bytes()))
+            .and_then(|_| std::fs::rename(&tmp, &path));
+        if written.is_err() {
+            let _ = std::fs::remove_file(&tmp);
+        }
+        written?;
+
+        self.saved_assocs = merged.clone();
+        self.local_assocs = merged;
+
         Ok(())
     }
 }
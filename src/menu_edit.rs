@@ -0,0 +1,250 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+
+use roxmltree::Node;
+
+use crate::desktop_entry::escape_value;
+use crate::dirs::BaseDirs;
+use crate::mime_info::{escape_xml, parse_document};
+
+const ROOT_MENU: &str = "Applications";
+const MENU_DOCTYPE: &str = "<!DOCTYPE Menu PUBLIC \"-//freedesktop//DTD Menu 1.0//EN\"\n \"http://www.freedesktop.org/standards/menu-spec/1.0/menu.dtd\">";
+
+// The changes to one submenu, by the <Name>s leading to it from the root menu.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MenuOverride {
+    pub path: Vec<String>,
+    // A .directory file name giving the menu's name and icon.
+    pub directory: Option<String>,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    // Some(true) for <Deleted/>, Some(false) for <NotDeleted/>.
+    pub deleted: Option<bool>,
+}
+
+impl MenuOverride {
+    fn is_empty(&self) -> bool {
+        self.directory.is_none() && self.include.is_empty() && self.exclude.is_empty() && self.deleted.is_none()
+    }
+}
+
+// Menu edits kept as a snippet in $XDG_CONFIG_HOME/menus/applications-merged, which menu
+// implementations merge into the applications menu.
+#[derive(Clone, Debug, Default)]
+pub struct MenuEdits {
+    pub menus: Vec<MenuOverride>,
+}
+
+fn merged_dir(base_dirs: &BaseDirs) -> PathBuf {
+    base_dirs.config_home().join("menus/applications-merged")
+}
+
+fn snippet_path(base_dirs: &BaseDirs, name: &str) -> Result<PathBuf> {
+    if name.is_empty() || name.contains('/') {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("Invalid menu snippet name {}", name)));
+    }
+
+    Ok(merged_dir(base_dirs).join(format!("{}.menu", name)))
+}
+
+fn filenames(node: Node) -> Vec<String> {
+    node.descendants().filter(|child| child.has_tag_name("Filename"))
+        .filter_map(|child| child.text()).map(|text| text.trim().to_string()).collect()
+}
+
+fn parse_menu(node: Node, path: &[String], menus: &mut Vec<MenuOverride>) {
+    let mut menu = MenuOverride { path: path.to_vec(), ..MenuOverride::default() };
+    let mut submenus = vec![];
+    for child in node.children().filter(Node::is_element) {
+        match child.tag_name().name() {
+            "Directory" => menu.directory = child.text().map(|text| text.trim().to_string()),
+            "Include" => menu.include.extend(filenames(child)),
+            "Exclude" => menu.exclude.extend(filenames(child)),
+            "Deleted" => menu.deleted = Some(true),
+            "NotDeleted" => menu.deleted = Some(false),
+            "Menu" => submenus.push(child),
+            _ => {}
+        }
+    }
+    menus.push(menu);
+    for submenu in submenus {
+        let Some(name) = submenu.children().find(|child| child.has_tag_name("Name")).and_then(|name| name.text()) else {
+            continue;
+        };
+        let mut sub_path = path.to_vec();
+        sub_path.push(name.trim().to_string());
+        parse_menu(submenu, &sub_path, menus);
+    }
+}
+
+impl MenuEdits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The edits saved as `name` before, or none if there is no such snippet.
+    pub fn load_for(base_dirs: &BaseDirs, name: &str) -> Result<Self> {
+        let text = match fs::read_to_string(snippet_path(base_dirs, name)?) {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Self::new()),
+            Err(err) => return Err(err),
+        };
+        let doc = parse_document(&text)?;
+        let mut menus = vec![];
+        parse_menu(doc.root_element(), &[], &mut menus);
+        // Menus that only lead to edited submenus are implied by their paths.
+        menus.retain(|menu| !menu.is_empty());
+
+        Ok(Self { menus })
+    }
+
+    pub fn load(name: &str) -> Result<Self> {
+        Self::load_for(&BaseDirs::new(), name)
+    }
+
+    fn menu_mut(&mut self, path: &[&str]) -> &mut MenuOverride {
+        let index = match self.menus.iter().position(|menu| menu.path == path) {
+            Some(index) => index,
+            None => {
+                self.menus.push(MenuOverride { path: path.iter().map(|s| s.to_string()).collect(), ..MenuOverride::default() });
+                self.menus.len() - 1
+            }
+        };
+
+        &mut self.menus[index]
+    }
+
+    // Keeps `desktop_id` out of the menu at `path`. An empty path is the root menu.
+    pub fn hide(&mut self, path: &[&str], desktop_id: &str) {
+        let menu = self.menu_mut(path);
+        menu.include.retain(|id| id != desktop_id);
+        if !menu.exclude.iter().any(|id| id == desktop_id) {
+            menu.exclude.push(desktop_id.to_string());
+        }
+    }
+
+    // Puts `desktop_id` in the menu at `path`, even if its categories would not.
+    pub fn show(&mut self, path: &[&str], desktop_id: &str) {
+        let menu = self.menu_mut(path);
+        menu.exclude.retain(|id| id != desktop_id);
+        if !menu.include.iter().any(|id| id == desktop_id) {
+            menu.include.push(desktop_id.to_string());
+        }
+    }
+
+    pub fn move_item(&mut self, desktop_id: &str, from: &[&str], to: &[&str]) {
+        self.hide(from, desktop_id);
+        self.show(to, desktop_id);
+    }
+
+    pub fn set_directory(&mut self, path: &[&str], directory_file: &str) {
+        self.menu_mut(path).directory = Some(directory_file.to_string());
+    }
+
+    pub fn set_deleted(&mut self, path: &[&str], deleted: bool) {
+        self.menu_mut(path).deleted = Some(deleted);
+    }
+
+    fn write_menu(&self, path: &[String], indent: usize, xml: &mut String) {
+        let pad = "  ".repeat(indent);
+        let name = path.last().map(String::as_str).unwrap_or(ROOT_MENU);
+        *xml += &format!("{}<Menu>\n{}  <Name>{}</Name>\n", pad, pad, escape_xml(name));
+        if let Some(menu) = self.menus.iter().find(|menu| menu.path == path) {
+            if let Some(directory) = &menu.directory {
+                *xml += &format!("{}  <Directory>{}</Directory>\n", pad, escape_xml(directory));
+            }
+            match menu.deleted {
+                Some(true) => *xml += &format!("{}  <Deleted/>\n", pad),
+                Some(false) => *xml += &format!("{}  <NotDeleted/>\n", pad),
+                None => {}
+            }
+            for (tag, ids) in [("Include", &menu.include), ("Exclude", &menu.exclude)] {
+                if ids.is_empty() {
+                    continue;
+                }
+                *xml += &format!("{}  <{}>\n", pad, tag);
+                for id in ids {
+                    *xml += &format!("{}    <Filename>{}</Filename>\n", pad, escape_xml(id));
+                }
+                *xml += &format!("{}  </{}>\n", pad, tag);
+            }
+        }
+
+        // Submenus directly below this one, in the order they were first edited.
+        let mut children: Vec<&String> = vec![];
+        for menu in &self.menus {
+            if menu.path.len() > path.len() && menu.path.starts_with(path) && !children.contains(&&menu.path[path.len()]) {
+                children.push(&menu.path[path.len()]);
+            }
+        }
+        for child in children {
+            let mut child_path = path.to_vec();
+            child_path.push(child.clone());
+            self.write_menu(&child_path, indent + 1, xml);
+        }
+        *xml += &format!("{}</Menu>\n", pad);
+    }
+
+    pub fn to_xml(&self) -> String {
+        let mut xml = format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n{}\n", MENU_DOCTYPE);
+        self.write_menu(&[], 0, &mut xml);
+
+        xml
+    }
+
+    // Writes the snippet as <name>.menu, replacing it by a rename. Returns its path.
+    pub fn save_for(&self, base_dirs: &BaseDirs, name: &str) -> Result<PathBuf> {
+        let path = snippet_path(base_dirs, name)?;
+        fs::create_dir_all(merged_dir(base_dirs))?;
+        let tmp = merged_dir(base_dirs).join(format!(".{}.menu.{}", name, std::process::id()));
+        fs::write(&tmp, self.to_xml())?;
+        fs::rename(&tmp, &path).inspect_err(|_| {
+            let _ = fs::remove_file(&tmp);
+        })?;
+
+        Ok(path)
+    }
+
+    pub fn save(&self, name: &str) -> Result<PathBuf> {
+        self.save_for(&BaseDirs::new(), name)
+    }
+}
+
+// Writes $XDG_DATA_HOME/desktop-directories/<id>.directory, which takes precedence over a
+// system file of the same name. Returns the file name to use with set_directory.
+pub fn write_directory_for(base_dirs: &BaseDirs, id: &str, name: &str, icon: Option<&str>, comment: Option<&str>) -> Result<String> {
+    let filename = if id.ends_with(".directory") { id.to_string() } else { format!("{}.directory", id) };
+    if filename.contains('/') || filename == ".directory" || name.trim().is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("Invalid directory entry {}", id)));
+    }
+    let dir = base_dirs.data_home().join("desktop-directories");
+    fs::create_dir_all(&dir)?;
+    let mut content = format!("[Desktop Entry]\nType=Directory\nName={}\n", escape_value(name));
+    if let Some(icon) = icon {
+        content += &format!("Icon={}\n", escape_value(icon));
+    }
+    if let Some(comment) = comment {
+        content += &format!("Comment={}\n", escape_value(comment));
+    }
+    fs::write(dir.join(&filename), content)?;
+
+    Ok(filename)
+}
+
+pub fn write_directory(id: &str, name: &str, icon: Option<&str>, comment: Option<&str>) -> Result<String> {
+    write_directory_for(&BaseDirs::new(), id, name, icon, comment)
+}
+
+// Renames the menu at `path` through a user .directory file and records it in the snippet.
+pub fn rename_menu_for(base_dirs: &BaseDirs, edits: &mut MenuEdits, path: &[&str], name: &str, icon: Option<&str>) -> Result<()> {
+    let id = format!("{}-user", if path.is_empty() { ROOT_MENU.to_string() } else { path.join("-") });
+    let filename = write_directory_for(base_dirs, &id, name, icon, None)?;
+    edits.set_directory(path, &filename);
+
+    Ok(())
+}
+
+pub fn rename_menu(edits: &mut MenuEdits, path: &[&str], name: &str, icon: Option<&str>) -> Result<()> {
+    rename_menu_for(&BaseDirs::new(), edits, path, name, icon)
+}
@@ -0,0 +1,16 @@
+// A snapshot of the freedesktop.org database for systems without one, see
+// assets/mime/README.
+#[cfg(feature = "bundled-mime")]
+pub(crate) fn bundled_file(name: &str) -> Option<&'static [u8]> {
+    match name {
+        "globs2" => Some(include_bytes!("../assets/mime/globs2")),
+        "magic" => Some(include_bytes!("../assets/mime/magic")),
+        "subclasses" => Some(include_bytes!("../assets/mime/subclasses")),
+        _ => None,
+    }
+}
+
+#[cfg(not(feature = "bundled-mime"))]
+pub(crate) fn bundled_file(_name: &str) -> Option<&'static [u8]> {
+    None
+}
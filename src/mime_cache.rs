@@ -0,0 +1,220 @@
+use core::str;
+use std::fs::{self, File};
+use std::io::{Error, ErrorKind, Result};
+use std::iter;
+use std::path::Path;
+
+use memmap::{Mmap, MmapOptions};
+
+use crate::mime_magic::{to_host_order, MagicRule, MagicSection};
+use crate::mime_type::MimeType;
+
+const ALIAS_LIST: usize = 4;
+const PARENT_LIST: usize = 8;
+const LITERAL_LIST: usize = 12;
+const REVERSE_SUFFIX_TREE: usize = 16;
+const GLOB_LIST: usize = 20;
+const MAGIC_LIST: usize = 24;
+const NAMESPACE_LIST: usize = 28;
+const ICONS_LIST: usize = 32;
+const GENERIC_ICONS_LIST: usize = 36;
+
+const CASE_SENSITIVE_FLAG: u32 = 0x100;
+
+// Reader for the mime.cache file compiled by update-mime-database.
+pub struct MimeCache {
+    region: Mmap,
+}
+
+impl MimeCache {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)?;
+        let region = unsafe { MmapOptions::new().map(&file)? };
+        let cache = Self { region };
+        let major = cache.u16_at(0);
+        let minor = cache.u16_at(2);
+        if major != Some(1) || !matches!(minor, Some(1) | Some(2)) {
+            return Err(Error::new(ErrorKind::InvalidData, "unsupported mime.cache version"));
+        }
+
+        Ok(cache)
+    }
+
+    // The cache of a mime dir, unless it is missing or older than the text files.
+    pub fn open_dir(mime_dir: &Path) -> Option<Self> {
+        let cache_path = mime_dir.join("mime.cache");
+        let cache_mtime = fs::metadata(&cache_path).and_then(|meta| meta.modified()).ok()?;
+        for name in ["globs2", "globs", "magic", "subclasses", "aliases", "icons", "generic-icons", "XMLnamespaces"] {
+            let Ok(mtime) = fs::metadata(mime_dir.join(name)).and_then(|meta| meta.modified()) else {
+                continue;
+            };
+            if mtime > cache_mtime {
+                return None;
+            }
+        }
+
+        Self::open(&cache_path).ok()
+    }
+
+    fn u16_at(&self, offset: usize) -> Option<u16> {
+        Some(u16::from_be_bytes(self.region.get(offset..offset + 2)?.try_into().ok()?))
+    }
+
+    fn u32_at(&self, offset: usize) -> Option<u32> {
+        Some(u32::from_be_bytes(self.region.get(offset..offset + 4)?.try_into().ok()?))
+    }
+
+    fn offset_at(&self, offset: usize) -> Option<usize> {
+        self.u32_at(offset).map(|value| value as usize)
+    }
+
+    fn str_at(&self, offset: usize) -> Option<&str> {
+        let len = self.region.get(offset..)?.iter().position(|ch| *ch == 0)?;
+        str::from_utf8(&self.region[offset..offset + len]).ok()
+    }
+
+    // (offset of first entry, number of entries) of a header list.
+    fn list(&self, header: usize) -> Option<(usize, usize)> {
+        let list = self.offset_at(header)?;
+        Some((list + 4, self.offset_at(list)?))
+    }
+
+    fn pairs(&self, header: usize) -> Option<Vec<(&str, &str)>> {
+        let (start, len) = self.list(header)?;
+        (0..len).map(|i| {
+            let entry = start + i * 8;
+            Some((self.str_at(self.offset_at(entry)?)?, self.str_at(self.offset_at(entry + 4)?)?))
+        }).collect()
+    }
+
+    pub fn aliases(&self) -> Option<Vec<(&str, &str)>> {
+        self.pairs(ALIAS_LIST)
+    }
+
+    // (mime, icon name) pairs.
+    pub fn icons(&self) -> Option<Vec<(&str, &str)>> {
+        self.pairs(ICONS_LIST)
+    }
+
+    pub fn generic_icons(&self) -> Option<Vec<(&str, &str)>> {
+        self.pairs(GENERIC_ICONS_LIST)
+    }
+
+    // (namespace, local name, mime) of XML root elements.
+    pub fn namespaces(&self) -> Option<Vec<(&str, &str, &str)>> {
+        let (start, len) = self.list(NAMESPACE_LIST)?;
+        (0..len).map(|i| {
+            let entry = start + i * 12;
+            Some((
+                self.str_at(self.offset_at(entry)?)?,
+                self.str_at(self.offset_at(entry + 4)?)?,
+                self.str_at(self.offset_at(entry + 8)?)?,
+            ))
+        }).collect()
+    }
+
+    pub fn parents(&self) -> Option<Vec<(&str, Vec<&str>)>> {
+        let (start, len) = self.list(PARENT_LIST)?;
+        (0..len).map(|i| {
+            let entry = start + i * 8;
+            let mime = self.str_at(self.offset_at(entry)?)?;
+            let parent_list = self.offset_at(entry + 4)?;
+            let parents = (0..self.offset_at(parent_list)?)
+                .map(|j| self.str_at(self.offset_at(parent_list + 4 + j * 4)?))
+                .collect::<Option<Vec<&str>>>()?;
+            Some((mime, parents))
+        }).collect()
+    }
+
+    // Glob entries as (weight, mime, pattern, case_sensitive). Suffix tree entries are
+    // turned back into "*suffix" patterns.
+    pub fn globs(&self) -> Option<Vec<(usize, &str, String, bool)>> {
+        let mut globs = vec![];
+        for header in [LITERAL_LIST, GLOB_LIST] {
+            let (start, len) = self.list(header)?;
+            for i in 0..len {
+                let entry = start + i * 12;
+                let pattern = self.str_at(self.offset_at(entry)?)?;
+                let mime = self.str_at(self.offset_at(entry + 4)?)?;
+                let flags = self.u32_at(entry + 8)?;
+                globs.push(((flags & 0xff) as usize, mime, pattern.to_string(), flags & CASE_SENSITIVE_FLAG != 0));
+            }
+        }
+        let (start, len) = self.list(REVERSE_SUFFIX_TREE)?;
+        let first = self.offset_at(start)?;
+        self.walk_suffix_tree(first, len, &mut vec![], &mut globs)?;
+
+        Some(globs)
+    }
+
+    fn walk_suffix_tree<'a>(&'a self, first: usize, len: usize, reversed: &mut Vec<char>,
+                            globs: &mut Vec<(usize, &'a str, String, bool)>) -> Option<()> {
+        for i in 0..len {
+            let node = first + i * 12;
+            let ch = self.u32_at(node)?;
+            if ch == 0 {
+                // Leaves keep the mime and flags where inner nodes keep their children.
+                let mime = self.str_at(self.offset_at(node + 4)?)?;
+                let flags = self.u32_at(node + 8)?;
+                let pattern = suffix_pattern(reversed);
+                globs.push(((flags & 0xff) as usize, mime, pattern, flags & CASE_SENSITIVE_FLAG != 0));
+                continue;
+            }
+            reversed.push(char::from_u32(ch)?);
+            self.walk_suffix_tree(self.offset_at(node + 8)?, self.offset_at(node + 4)?, reversed, globs)?;
+            reversed.pop();
+        }
+
+        Some(())
+    }
+
+    pub(crate) fn magic_sections(&self) -> Option<Vec<MagicSection>> {
+        let list = self.offset_at(MAGIC_LIST)?;
+        let len = self.offset_at(list)?;
+        let first = self.offset_at(list + 8)?;
+        (0..len).map(|i| {
+            let entry = first + i * 16;
+            let mut rules = vec![];
+            self.flatten_matchlets(self.offset_at(entry + 12)?, self.offset_at(entry + 8)?, 0, &mut rules)?;
+            Some(MagicSection {
+                priority: self.offset_at(entry)?,
+                mime: MimeType::parse(self.str_at(self.offset_at(entry + 4)?)?)?,
+                rules,
+                no_magic: false,
+            })
+        }).collect()
+    }
+
+    // Matchlets form a tree in the cache; the text format flattens them with indents.
+    fn flatten_matchlets(&self, first: usize, len: usize, indent: usize, rules: &mut Vec<MagicRule>) -> Option<()> {
+        for i in 0..len {
+            let matchlet = first + i * 32;
+            let value_len = self.offset_at(matchlet + 12)?;
+            let value_offset = self.offset_at(matchlet + 16)?;
+            let mask_offset = self.offset_at(matchlet + 20)?;
+            let word_size = self.offset_at(matchlet + 8)?;
+            let mut value = self.region.get(value_offset..value_offset + value_len)?.to_vec();
+            let mut mask = if mask_offset == 0 {
+                None
+            } else {
+                Some(self.region.get(mask_offset..mask_offset + value_len)?.to_vec())
+            };
+            // Swapped as in the text magic file, so both match alike.
+            to_host_order(&mut value, mask.as_deref_mut(), word_size);
+            rules.push(MagicRule {
+                indent,
+                start: self.offset_at(matchlet)?,
+                value,
+                mask,
+                range: self.offset_at(matchlet + 4)?.max(1),
+            });
+            self.flatten_matchlets(self.offset_at(matchlet + 28)?, self.offset_at(matchlet + 24)?, indent + 1, rules)?;
+        }
+
+        Some(())
+    }
+}
+
+fn suffix_pattern(reversed: &[char]) -> String {
+    iter::once('*').chain(reversed.iter().rev().copied()).collect()
+}
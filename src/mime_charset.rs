@@ -0,0 +1,56 @@
+use core::str;
+
+// Byte order marks, longest first so UTF-32LE is not taken for UTF-16LE.
+const BOMS: [(&[u8], &str); 5] = [
+    (&[0x00, 0x00, 0xfe, 0xff], "UTF-32BE"),
+    (&[0xff, 0xfe, 0x00, 0x00], "UTF-32LE"),
+    (&[0xef, 0xbb, 0xbf], "UTF-8"),
+    (&[0xfe, 0xff], "UTF-16BE"),
+    (&[0xff, 0xfe], "UTF-16LE"),
+];
+
+// UTF-16 without a BOM, told apart by the NULs that high bytes of mostly-ASCII text leave
+// on every other position.
+fn utf16_without_bom(data: &[u8]) -> Option<&'static str> {
+    let pairs = data.len() / 2;
+    if pairs < 2 {
+        return None;
+    }
+    let even_nuls = data.iter().step_by(2).filter(|ch| **ch == 0).count();
+    let odd_nuls = data.iter().skip(1).step_by(2).filter(|ch| **ch == 0).count();
+    if even_nuls * 4 >= pairs * 3 && odd_nuls == 0 {
+        Some("UTF-16BE")
+    } else if odd_nuls * 4 >= pairs * 3 && even_nuls == 0 {
+        Some("UTF-16LE")
+    } else {
+        None
+    }
+}
+
+// A charset hint for the leading bytes of a text file, as an IANA charset name suitable
+// for "charset=" parameters. Anything that is neither Unicode nor ASCII is reported as one
+// of the common single byte charsets.
+pub fn sniff_charset(data: &[u8]) -> &'static str {
+    if let Some((_, charset)) = BOMS.iter().find(|(bom, _)| data.starts_with(bom)) {
+        return charset;
+    }
+    if let Some(charset) = utf16_without_bom(data) {
+        return charset;
+    }
+    if data.is_ascii() {
+        return "US-ASCII";
+    }
+    match str::from_utf8(data) {
+        Ok(_) => return "UTF-8",
+        // A multi-byte sequence cut off at the end of the sample is fine.
+        Err(err) if err.error_len().is_none() => return "UTF-8",
+        Err(_) => {}
+    }
+    // C1 control codes are unused in ISO-8859-1 text, but hold quotes and dashes in
+    // windows-1252.
+    if data.iter().any(|ch| (0x80..0xa0).contains(ch)) {
+        "windows-1252"
+    } else {
+        "ISO-8859-1"
+    }
+}
@@ -0,0 +1,337 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use log::warn;
+use roxmltree::Node;
+
+use crate::mime_info::{parse_document, parse_mime_type, MIMEInfo};
+
+const MAGIC_HEADER: &[u8] = b"MIME-Magic\0\n";
+const TREEMAGIC_HEADER: &str = "MIME-TreeMagic\0\n";
+const GENERATED: &str = "# This file was automatically generated by xdg-desktop\n";
+
+// Everything the packages say about one type.
+#[derive(Default)]
+struct CompiledType {
+    info: MIMEInfo,
+    // (priority, encoded rules) of each <magic> element.
+    magic: Vec<(usize, Vec<u8>)>,
+    treemagic: Vec<(usize, String)>,
+    // (namespace, local name) of <root-XML> elements.
+    namespaces: Vec<(String, String)>,
+}
+
+// A C integer literal: decimal, 0x hex or 0 octal.
+fn parse_integer(text: &str) -> Option<u64> {
+    let text = text.trim();
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16).ok()
+    } else if text.len() > 1 && text.starts_with('0') {
+        u64::from_str_radix(&text[1..], 8).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+// The value of a string match, with \n, \xHH, \NNN and other backslash escapes.
+fn unescape_string(text: &str) -> Vec<u8> {
+    let bytes = text.as_bytes();
+    let mut result = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' || i + 1 == bytes.len() {
+            result.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        i += 1;
+        match bytes[i] {
+            b'n' => result.push(b'\n'),
+            b'r' => result.push(b'\r'),
+            b't' => result.push(b'\t'),
+            b'x' => {
+                let len = bytes[i + 1..].iter().take(2).take_while(|ch| ch.is_ascii_hexdigit()).count();
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 1 + len]).unwrap_or("");
+                result.push(u8::from_str_radix(hex, 16).unwrap_or(b'x'));
+                i += len;
+            }
+            b'0'..=b'7' => {
+                let len = bytes[i..].iter().take(3).take_while(|ch| (b'0'..=b'7').contains(ch)).count();
+                let octal = std::str::from_utf8(&bytes[i..i + len]).unwrap_or("0");
+                result.push(u16::from_str_radix(octal, 8).unwrap_or(0) as u8);
+                i += len - 1;
+            }
+            other => result.push(other),
+        }
+        i += 1;
+    }
+
+    result
+}
+
+fn parse_hex_bytes(text: &str) -> Option<Vec<u8>> {
+    let hex = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X"))?;
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
+
+// A <match> and its nested matches as lines of the binary magic file.
+fn encode_match(node: Node, indent: usize, out: &mut Vec<u8>) -> Option<()> {
+    let (start, end) = match node.attribute("offset")?.split_once(':') {
+        Some((start, end)) => (start.parse::<usize>().ok()?, end.parse::<usize>().ok()?),
+        None => {
+            let start = node.attribute("offset")?.parse::<usize>().ok()?;
+            (start, start)
+        }
+    };
+    let value_text = node.attribute("value")?;
+    let mask_text = node.attribute("mask");
+    // Host byte order values are stored big-endian with their word size, and swapped by
+    // readers on little-endian machines.
+    let (value, mask, word_size) = match node.attribute("type")? {
+        "string" => (unescape_string(value_text), mask_text.map(parse_hex_bytes), 1),
+        numeric => {
+            let (size, big_endian, word_size) = match numeric {
+                "byte" => (1, true, 1),
+                "big16" => (2, true, 1),
+                "big32" => (4, true, 1),
+                "little16" => (2, false, 1),
+                "little32" => (4, false, 1),
+                "host16" => (2, true, 2),
+                "host32" => (4, true, 4),
+                _ => return None,
+            };
+            let to_bytes = |number: u64| {
+                let be = number.to_be_bytes()[8 - size..].to_vec();
+                if big_endian { be } else { be.into_iter().rev().collect() }
+            };
+            let value = to_bytes(parse_integer(value_text)?);
+            (value, mask_text.map(|mask| parse_integer(mask).map(to_bytes)), word_size)
+        }
+    };
+    let mask = match mask {
+        Some(Some(mask)) if mask.len() == value.len() => Some(mask),
+        Some(_) => return None,
+        None => None,
+    };
+    if value.len() > u16::MAX as usize || end < start {
+        return None;
+    }
+
+    if indent > 0 {
+        out.extend(indent.to_string().bytes());
+    }
+    out.extend(format!(">{}=", start).bytes());
+    out.extend((value.len() as u16).to_be_bytes());
+    out.extend(&value);
+    if let Some(mask) = mask {
+        out.push(b'&');
+        out.extend(mask);
+    }
+    if word_size > 1 {
+        out.extend(format!("~{}", word_size).bytes());
+    }
+    if end > start {
+        out.extend(format!("+{}", end - start + 1).bytes());
+    }
+    out.push(b'\n');
+    for child in node.children().filter(|child| child.has_tag_name("match")) {
+        encode_match(child, indent + 1, out)?;
+    }
+
+    Some(())
+}
+
+fn encode_treematch(node: Node, indent: usize, out: &mut String) -> Option<()> {
+    let path = node.attribute("path")?;
+    let kind = node.attribute("type").unwrap_or("any");
+    if indent > 0 {
+        *out += &indent.to_string();
+    }
+    *out += &format!(">\"{}\"={}", path, kind);
+    for option in ["executable", "match-case", "non-empty"] {
+        if node.attribute(option) == Some("true") {
+            *out += &format!(",{}", option);
+        }
+    }
+    if let Some(mime) = node.attribute("mimetype") {
+        *out += &format!(",{}", mime);
+    }
+    out.push('\n');
+    for child in node.children().filter(|child| child.has_tag_name("treematch")) {
+        encode_treematch(child, indent + 1, out)?;
+    }
+
+    Some(())
+}
+
+fn priority(node: Node) -> usize {
+    node.attribute("priority").and_then(|p| p.parse().ok()).unwrap_or(50)
+}
+
+fn read_packages(mime_dir: &Path) -> Result<BTreeMap<String, CompiledType>> {
+    let mut packages: Vec<_> = fs::read_dir(mime_dir.join("packages"))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "xml"))
+        .collect();
+    packages.sort();
+
+    let mut types: BTreeMap<String, CompiledType> = BTreeMap::new();
+    for package in packages {
+        let text = fs::read_to_string(&package)?;
+        let doc = match parse_document(&text) {
+            Ok(doc) => doc,
+            Err(err) => {
+                warn!("Cannot parse {}: {}", package.display(), err);
+                continue;
+            }
+        };
+        for node in doc.root_element().children().filter(|node| node.has_tag_name("mime-type")) {
+            let Some(info) = parse_mime_type(node) else {
+                continue;
+            };
+            let compiled = types.entry(info.mime.clone()).or_default();
+            compiled.info.mime = info.mime.clone();
+            // Later packages can drop what earlier ones defined before adding their own.
+            for child in node.children().filter(Node::is_element) {
+                match child.tag_name().name() {
+                    "glob-deleteall" => compiled.info.globs.clear(),
+                    "magic-deleteall" => compiled.magic.clear(),
+                    _ => {}
+                }
+            }
+            compiled.info.merge(info);
+            for child in node.children().filter(Node::is_element) {
+                match child.tag_name().name() {
+                    "magic" => {
+                        let mut rules = vec![];
+                        let valid = child.children().filter(|m| m.has_tag_name("match"))
+                            .all(|m| encode_match(m, 0, &mut rules).is_some());
+                        if !valid {
+                            warn!("Ignoring invalid magic of {} in {}", compiled.info.mime, package.display());
+                        } else if !rules.is_empty() {
+                            compiled.magic.push((priority(child), rules));
+                        }
+                    }
+                    "treemagic" => {
+                        let mut rules = String::new();
+                        let valid = child.children().filter(|m| m.has_tag_name("treematch"))
+                            .all(|m| encode_treematch(m, 0, &mut rules).is_some());
+                        if valid && !rules.is_empty() {
+                            compiled.treemagic.push((priority(child), rules));
+                        }
+                    }
+                    "root-XML" => {
+                        if let (Some(namespace), Some(local)) = (child.attribute("namespaceURI"), child.attribute("localName")) {
+                            compiled.namespaces.push((namespace.to_string(), local.to_string()));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    Ok(types)
+}
+
+fn lines(header: &str, lines: impl Iterator<Item = String>) -> String {
+    let mut content = String::from(header);
+    for line in lines {
+        content += &line;
+        content.push('\n');
+    }
+
+    content
+}
+
+// Replaces `path` by a rename, so readers see either the old file or the whole new one.
+fn write_replacing(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Result<()> {
+    let path = path.as_ref();
+    let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    let tmp = path.with_file_name(format!(".{}.{}", name, std::process::id()));
+    fs::write(&tmp, contents).and_then(|_| fs::rename(&tmp, path)).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
+}
+
+// What update-mime-database generates for `mime_dir` from its packages/*.xml: globs2, globs,
+// magic, treemagic, XMLnamespaces, subclasses, aliases, icons, generic-icons, types and the
+// per-type XML files. A mime.cache left from an earlier run is removed, as it would shadow
+// the new files.
+pub fn compile_mime_dir(mime_dir: &Path) -> Result<()> {
+    let types = read_packages(mime_dir)?;
+    if types.is_empty() {
+        return Err(Error::new(ErrorKind::NotFound, format!("no types in {}/packages", mime_dir.display())));
+    }
+    let infos = || types.values().map(|compiled| &compiled.info);
+
+    // Patterns that ignore case are matched against the lowercased file name.
+    let mut globs: Vec<(usize, &str, String, bool)> = infos()
+        .flat_map(|info| info.globs.iter().map(|glob| {
+            let pattern = if glob.case_sensitive { glob.pattern.clone() } else { glob.pattern.to_lowercase() };
+            (glob.weight, info.mime.as_str(), pattern, glob.case_sensitive)
+        }))
+        .collect();
+    globs.sort_by(|a, b| (Reverse(a.0), a.1, &a.2).cmp(&(Reverse(b.0), b.1, &b.2)));
+    globs.dedup();
+    write_replacing(mime_dir.join("globs2"), lines(GENERATED, globs.iter().map(|(weight, mime, pattern, cs)| {
+        format!("{}:{}:{}{}", weight, mime, pattern, if *cs { ":cs" } else { "" })
+    })))?;
+    write_replacing(mime_dir.join("globs"), lines(GENERATED, globs.iter().map(|(_, mime, pattern, _)| format!("{}:{}", mime, pattern))))?;
+
+    let mut magic: Vec<(usize, &str, &[u8])> = types.values()
+        .flat_map(|compiled| compiled.magic.iter().map(|(priority, rules)| (*priority, compiled.info.mime.as_str(), rules.as_slice())))
+        .collect();
+    magic.sort_by_key(|(priority, mime, _)| (Reverse(*priority), *mime));
+    let mut magic_file = MAGIC_HEADER.to_vec();
+    for (priority, mime, rules) in magic {
+        magic_file.extend(format!("[{}:{}]\n", priority, mime).bytes());
+        magic_file.extend(rules);
+    }
+    write_replacing(mime_dir.join("magic"), magic_file)?;
+
+    let mut treemagic: Vec<(usize, &str, &str)> = types.values()
+        .flat_map(|compiled| compiled.treemagic.iter().map(|(priority, rules)| (*priority, compiled.info.mime.as_str(), rules.as_str())))
+        .collect();
+    treemagic.sort_by_key(|(priority, mime, _)| (Reverse(*priority), *mime));
+    write_replacing(mime_dir.join("treemagic"), lines(TREEMAGIC_HEADER, treemagic.iter().map(|(priority, mime, rules)| {
+        format!("[{}:{}]\n{}", priority, mime, rules.trim_end())
+    })))?;
+
+    write_replacing(mime_dir.join("XMLnamespaces"), lines("", types.values().flat_map(|compiled| {
+        compiled.namespaces.iter().map(|(namespace, local)| format!("{} {} {}", namespace, local, compiled.info.mime))
+    })))?;
+    write_replacing(mime_dir.join("subclasses"), lines("", infos().flat_map(|info| {
+        info.subclass_of.iter().map(|parent| format!("{} {}", info.mime, parent))
+    })))?;
+    write_replacing(mime_dir.join("aliases"), lines("", infos().flat_map(|info| {
+        info.aliases.iter().map(|alias| format!("{} {}", alias, info.mime))
+    })))?;
+    write_replacing(mime_dir.join("icons"), lines("", infos().filter_map(|info| Some(format!("{}:{}", info.mime, info.icon.as_ref()?)))))?;
+    write_replacing(mime_dir.join("generic-icons"), lines("", infos().filter_map(|info| {
+        Some(format!("{}:{}", info.mime, info.generic_icon.as_ref()?))
+    })))?;
+    write_replacing(mime_dir.join("types"), lines("", infos().map(|info| info.mime.clone())))?;
+
+    for info in infos() {
+        let Some((media, subtype)) = info.mime.split_once('/') else {
+            continue;
+        };
+        if media.is_empty() || subtype.is_empty() || media.starts_with('.') || subtype.contains('/') {
+            continue;
+        }
+        fs::create_dir_all(mime_dir.join(media))?;
+        write_replacing(mime_dir.join(media).join(format!("{}.xml", subtype)), info.to_type_xml())?;
+    }
+
+    match fs::remove_file(mime_dir.join("mime.cache")) {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
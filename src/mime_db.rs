@@ -0,0 +1,406 @@
+use core::str;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, Metadata};
+use std::io::{Error, ErrorKind, Read, Result};
+#[cfg(unix)]
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::Path;
+use std::process::Command;
+use std::sync::OnceLock;
+
+use crate::dirs;
+use crate::mime_bundled::bundled_file;
+use crate::mime_cache::MimeCache;
+use crate::mime_charset::sniff_charset;
+use crate::mime_compile::compile_mime_dir;
+use crate::mime_glob::MIMEGlobIndex;
+use crate::mime_info::MIMEInfo;
+use crate::mime_magic::MIMEMagicIndex;
+use crate::mime_treemagic::MIMETreeMagicIndex;
+use crate::mime_type::MimeType;
+use crate::uri::{uri_to_path, url_scheme};
+use crate::mime_xmlns::MIMEXMLNamespaceIndex;
+
+// Bytes read to find the root element of XML files, past any comments and doctype.
+const XML_HEAD_SIZE: usize = 4096;
+
+// How much a guess can be trusted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    // Only the text/binary heuristic applied.
+    Fallback,
+    // A glob or a magic rule matched, but nothing confirmed it.
+    Likely,
+    // Glob and magic results agree, or the content names its own type.
+    Certain,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MimeGuess {
+    pub mime: String,
+    pub confidence: Confidence,
+    // For text types, e.g. "UTF-8". Only guess_with_charset fills it in.
+    pub charset: Option<&'static str>,
+}
+
+#[derive(Clone, Copy)]
+pub enum GuessInput<'a> {
+    Path(&'a Path),
+    Bytes(&'a [u8]),
+}
+
+impl<'a> From<&'a Path> for GuessInput<'a> {
+    fn from(path: &'a Path) -> Self {
+        GuessInput::Path(path)
+    }
+}
+
+impl<'a> From<&'a [u8]> for GuessInput<'a> {
+    fn from(data: &'a [u8]) -> Self {
+        GuessInput::Bytes(data)
+    }
+}
+
+pub struct MimeDatabase {
+    globs: MIMEGlobIndex,
+    magic: MIMEMagicIndex,
+    // Few systems lack it, and only directory content detection needs it.
+    treemagic: Option<MIMETreeMagicIndex>,
+    xml_namespaces: Option<MIMEXMLNamespaceIndex>,
+    subclasses: HashMap<MimeType, Vec<MimeType>>,
+}
+
+fn add_subclass(subclasses: &mut HashMap<MimeType, Vec<MimeType>>, defined: &HashSet<MimeType>, mime: &str, parent: &str) {
+    let (Some(mime), Some(parent)) = (MimeType::parse(mime), MimeType::parse(parent)) else {
+        return;
+    };
+    if defined.contains(&mime) {
+        return;
+    }
+    let parents = subclasses.entry(mime).or_default();
+    if !parents.contains(&parent) {
+        parents.push(parent);
+    }
+}
+
+// The parents of a type come from the highest precedence dir that lists any, replacing
+// those of lower dirs.
+fn load_subclasses() -> HashMap<MimeType, Vec<MimeType>> {
+    let mut subclasses: HashMap<MimeType, Vec<MimeType>> = HashMap::new();
+    let mut found = false;
+    let mut defined: HashSet<MimeType> = HashSet::new();
+    for data_dir in dirs::data_dirs_in_precedence() {
+        defined.extend(subclasses.keys().cloned());
+        let mime_dir = data_dir.join("mime");
+        let cache = MimeCache::open_dir(&mime_dir);
+        if let Some(cached) = cache.as_ref().and_then(MimeCache::parents) {
+            found = true;
+            for (mime, parents) in cached {
+                parents.into_iter().for_each(|parent| add_subclass(&mut subclasses, &defined, mime, parent));
+            }
+            continue;
+        }
+        let Ok(content) = fs::read_to_string(mime_dir.join("subclasses")) else {
+            continue;
+        };
+        found = true;
+        for line in content.lines() {
+            if let Some((mime, parent)) = line.split_once(' ') {
+                add_subclass(&mut subclasses, &defined, mime, parent);
+            }
+        }
+    }
+    if let (false, Some(bundled)) = (found, bundled_file("subclasses")) {
+        for line in String::from_utf8_lossy(bundled).lines() {
+            if let Some((mime, parent)) = line.split_once(' ') {
+                add_subclass(&mut subclasses, &defined, mime, parent);
+            }
+        }
+    }
+
+    subclasses
+}
+
+// A mount point lives on another device than its parent, or is its own parent.
+#[cfg(unix)]
+fn is_mount_point(path: &Path, meta: &Metadata) -> bool {
+    let parent = path.canonicalize().ok().and_then(|path| path.parent().map(|parent| parent.to_path_buf()));
+    match parent.and_then(|parent| fs::metadata(parent).ok()) {
+        Some(parent_meta) => parent_meta.dev() != meta.dev(),
+        None => true,
+    }
+}
+
+// Only the roots of drives are told apart there.
+#[cfg(not(unix))]
+fn is_mount_point(path: &Path, _meta: &Metadata) -> bool {
+    path.canonicalize().is_ok_and(|path| path.parent().is_none())
+}
+
+#[cfg(unix)]
+fn special_file_type(meta: &Metadata) -> Option<&'static str> {
+    let file_type = meta.file_type();
+    if file_type.is_fifo() {
+        Some("inode/fifo")
+    } else if file_type.is_socket() {
+        Some("inode/socket")
+    } else if file_type.is_char_device() {
+        Some("inode/chardevice")
+    } else if file_type.is_block_device() {
+        Some("inode/blockdevice")
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+fn special_file_type(_meta: &Metadata) -> Option<&'static str> {
+    None
+}
+
+fn inode_type_of(path: &Path, meta: &Metadata) -> Option<&'static str> {
+    let file_type = meta.file_type();
+    if file_type.is_symlink() {
+        Some("inode/symlink")
+    } else if file_type.is_dir() {
+        Some(if is_mount_point(path, meta) { "inode/mount-point" } else { "inode/directory" })
+    } else {
+        special_file_type(meta)
+    }
+}
+
+// The inode/* type of anything that is not a regular file, without following symlinks.
+pub fn inode_type(path: &Path) -> Option<&'static str> {
+    inode_type_of(path, &fs::symlink_metadata(path).ok()?)
+}
+
+// The spec fallback: the first 32 bytes look like text when they hold no NUL or other
+// control characters besides whitespace and decode as UTF-8. A UTF-16 BOM is enough on its
+// own, since UTF-16 text is full of NULs.
+fn looks_like_text(data: &[u8]) -> bool {
+    if data.starts_with(&[0xff, 0xfe]) || data.starts_with(&[0xfe, 0xff]) {
+        return true;
+    }
+    let data = data.strip_prefix(&[0xef, 0xbb, 0xbf]).unwrap_or(data);
+    let head = &data[..data.len().min(32)];
+    if head.iter().any(|ch| (*ch < 0x20 && !b"\t\n\r\x0c\x1b".contains(ch)) || *ch == 0x7f) {
+        return false;
+    }
+    match str::from_utf8(head) {
+        Ok(_) => true,
+        // A multi-byte sequence cut off at the end of the window is fine.
+        Err(err) => err.error_len().is_none(),
+    }
+}
+
+impl MimeDatabase {
+    // A process-wide instance, built on first use. It is never reloaded; long-running
+    // processes that watch for changes should own a MimeDatabase instead. None when the
+    // system has no MIME database.
+    pub fn shared() -> Option<&'static MimeDatabase> {
+        static SHARED: OnceLock<Option<MimeDatabase>> = OnceLock::new();
+        SHARED.get_or_init(|| MimeDatabase::new().ok()).as_ref()
+    }
+
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            globs: MIMEGlobIndex::new()?,
+            magic: MIMEMagicIndex::new()?,
+            treemagic: MIMETreeMagicIndex::new().ok(),
+            xml_namespaces: MIMEXMLNamespaceIndex::new().ok(),
+            subclasses: load_subclasses(),
+        })
+    }
+
+    pub fn reload(&mut self) -> Result<()> {
+        *self = Self::new()?;
+
+        Ok(())
+    }
+
+    pub fn globs(&self) -> &MIMEGlobIndex {
+        &self.globs
+    }
+
+    pub fn magic(&self) -> &MIMEMagicIndex {
+        &self.magic
+    }
+
+    // Direct parents, including the implicit text/plain and application/octet-stream ones.
+    fn parents(&self, mime: &str) -> Vec<&str> {
+        let mut parents: Vec<&str> = self.subclasses.get(mime)
+            .map(|parents| parents.iter().map(MimeType::as_str).collect())
+            .unwrap_or_default();
+        if mime.starts_with("text/") && mime != "text/plain" && !parents.contains(&"text/plain") {
+            parents.push("text/plain");
+        }
+        if !mime.starts_with("inode/") && mime != "application/octet-stream" && parents.is_empty() {
+            parents.push("application/octet-stream");
+        }
+
+        parents
+    }
+
+    // Every type `mime` inherits from, nearest first, without `mime` itself.
+    pub fn ancestors<'a>(&'a self, mime: &'a str) -> Vec<&'a str> {
+        let mut ancestors: Vec<&str> = vec![];
+        let mut pos = 0;
+        let mut current = mime;
+        loop {
+            for parent in self.parents(current) {
+                if parent != mime && !ancestors.contains(&parent) {
+                    ancestors.push(parent);
+                }
+            }
+            let Some(next) = ancestors.get(pos) else {
+                break;
+            };
+            current = *next;
+            pos += 1;
+        }
+
+        ancestors
+    }
+
+    pub fn is_subclass_of(&self, mime: &str, parent: &str) -> bool {
+        mime == parent || self.ancestors(mime).contains(&parent)
+    }
+
+    pub fn guess<'a>(&self, input: impl Into<GuessInput<'a>>) -> MimeGuess {
+        match input.into() {
+            GuessInput::Bytes(data) => self.guess_content(&[], data),
+            GuessInput::Path(path) => {
+                // Symlinks are guessed by their target, like everything else that opens them.
+                if let Some(mime) = fs::metadata(path).ok().and_then(|meta| inode_type_of(path, &meta)) {
+                    return MimeGuess { mime: mime.to_string(), confidence: Confidence::Certain, charset: None };
+                }
+                let filename = path.file_name().and_then(|name| name.to_str());
+                let globs = filename.map(|name| self.globs.best_matches(name)).unwrap_or_default();
+                if globs.len() == 1 && !self.is_xml(globs[0]) {
+                    return MimeGuess { mime: globs[0].to_string(), confidence: Confidence::Likely, charset: None };
+                }
+
+                // Unreadable files are treated as empty.
+                self.guess_content(&globs, &self.read_head(path).unwrap_or_default())
+            }
+        }
+    }
+
+    // As guess, also sniffing the charset of text types. The type is the one guess gives;
+    // text files are then read for the charset even when their name settled it.
+    pub fn guess_with_charset<'a>(&self, input: impl Into<GuessInput<'a>>) -> MimeGuess {
+        let input = input.into();
+        let mut guess = self.guess(input);
+        if !self.is_subclass_of(&guess.mime, "text/plain") {
+            return guess;
+        }
+        guess.charset = match input {
+            GuessInput::Bytes(data) => Some(sniff_charset(data)),
+            GuessInput::Path(path) => self.read_head(path).map(|data| sniff_charset(&data)),
+        };
+
+        guess
+    }
+
+    // The leading bytes of the file that content guesses look at.
+    fn read_head(&self, path: &Path) -> Option<Vec<u8>> {
+        let mut data = vec![];
+        File::open(path).ok()?.take(self.head_size() as u64).read_to_end(&mut data).ok()?;
+
+        Some(data)
+    }
+
+    // Register a type for the current user. The definition is written as a package in
+    // $XDG_DATA_HOME/mime/packages and compiled with update-mime-database, or by
+    // compile_mime_dir without that tool.
+    pub fn install_type(&mut self, definition: &MIMEInfo) -> Result<()> {
+        // The type names the package file, so it must be a plain "media/subtype".
+        let mime = MimeType::parse(&definition.mime).filter(|mime| *mime == definition.mime.as_str())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("invalid MIME type {}", definition.mime)))?;
+        let mime_dir = Path::new(&dirs::xdg_data_home()).join("mime");
+        let packages_dir = mime_dir.join("packages");
+        fs::create_dir_all(&packages_dir)?;
+        fs::write(packages_dir.join(format!("{}-{}.xml", mime.media(), mime.subtype())), definition.to_xml())?;
+
+        let compiled = Command::new("update-mime-database").arg(&mime_dir).status().is_ok_and(|status| status.success());
+        if !compiled {
+            compile_mime_dir(&mime_dir)?;
+        }
+
+        self.reload()
+    }
+
+    // Guess from the leading bytes of a stream, such as stdin.
+    pub fn guess_reader(&self, reader: impl Read) -> Result<MimeGuess> {
+        let mut data = vec![];
+        reader.take(self.head_size() as u64).read_to_end(&mut data)?;
+
+        Ok(self.guess(data.as_slice()))
+    }
+
+    // x-scheme-handler/<scheme> for URLs such as https: or mailto:, and the type of the
+    // file for local file: URLs. None when `url` is not a URL.
+    pub fn mime_for_url(&self, url: &str) -> Option<String> {
+        let scheme = url_scheme(url)?;
+        if scheme != "file" {
+            return Some(format!("x-scheme-handler/{}", scheme));
+        }
+
+        // A file on another host cannot be read, only handed to an application.
+        let Some(path) = uri_to_path(url) else {
+            return Some(String::from("x-scheme-handler/file"));
+        };
+        Some(self.guess(path.as_path()).mime)
+    }
+
+    // The x-content/* types of a directory, e.g. x-content/image-dcf for a camera card.
+    pub fn guess_dir_content(&self, root: &Path) -> Vec<&str> {
+        let Some(treemagic) = &self.treemagic else {
+            return vec![];
+        };
+
+        treemagic.sniff_dir(root, |path| self.guess(path).mime)
+    }
+
+    // Leading bytes the content checks look at.
+    fn head_size(&self) -> usize {
+        match self.xml_namespaces {
+            Some(_) => self.magic.max_extent().max(XML_HEAD_SIZE),
+            None => self.magic.max_extent(),
+        }
+    }
+
+    // XML types without a more specific one yet, which the root element may refine.
+    fn is_xml(&self, mime: &str) -> bool {
+        self.xml_namespaces.is_some() && (mime == "application/xml" || mime == "text/xml")
+    }
+
+    // Resolve (possibly conflicting) glob results with the content.
+    fn guess_content(&self, globs: &[&str], data: &[u8]) -> MimeGuess {
+        let (mime, confidence) = match self.magic.sniff_bytes(data).map(|magic| magic.mime) {
+            Some(magic) => {
+                // The content wins over names it disagrees with, as the spec asks.
+                match globs.iter().find(|mime| self.is_subclass_of(mime, magic)) {
+                    Some(mime) => (*mime, Confidence::Certain),
+                    None => (magic, Confidence::Likely),
+                }
+            }
+            None => match globs.first() {
+                Some(mime) => (*mime, Confidence::Likely),
+                None if looks_like_text(data) => ("text/plain", Confidence::Fallback),
+                None => ("application/octet-stream", Confidence::Fallback),
+            },
+        };
+
+        // The root element of a generic XML document can name its actual type.
+        let refined = self.xml_namespaces.as_ref()
+            .filter(|_| self.is_xml(mime))
+            .and_then(|namespaces| namespaces.sniff_bytes(data));
+        let (mime, confidence) = match refined {
+            Some(refined) => (refined, Confidence::Certain),
+            None => (mime, confidence),
+        };
+
+        MimeGuess { mime: mime.to_string(), confidence, charset: None }
+    }
+}
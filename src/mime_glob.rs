@@ -1,17 +1,125 @@
 use core::str;
-use std::{collections::HashMap, fs::File};
-use std::io::Result;
+use std::cmp::Reverse;
+use std::{collections::{HashMap, HashSet}, fs::File, path::{Path, PathBuf}, thread};
+use std::io::{Error, ErrorKind, Result};
 
-use glob::Pattern;
+use aho_corasick::AhoCorasick;
+use glob::{MatchOptions, Pattern};
 use memmap::MmapOptions;
 
+use crate::dirs;
+use crate::mime_bundled::bundled_file;
+use crate::mime_cache::MimeCache;
+use crate::mime_type::{MatchSource, MimeMatch, MimeType};
+
 struct MIMEGlobItem {
     score: usize,
-    mime: String,
+    mime: MimeType,
     pattern: Option<Pattern>,
+    pattern_len: usize,
+    // The exact name for wildcard-free patterns.
+    literal: Option<String>,
+    case_sensitive: bool,
+}
+
+impl MIMEGlobItem {
+    fn matches(&self, filename: &str) -> bool {
+        let options = MatchOptions { case_sensitive: self.case_sensitive, ..MatchOptions::new() };
+        self.pattern.as_ref().is_some_and(|pattern| pattern.matches_with(filename, options))
+    }
+
+    fn to_match(&self) -> MimeMatch<'_> {
+        let source = if self.literal.is_some() {
+            MatchSource::Literal
+        } else if self.pattern.is_some() {
+            MatchSource::Glob
+        } else {
+            MatchSource::Suffix
+        };
+
+        MimeMatch { mime: self.mime.as_str(), weight: self.score, source }
+    }
+
+    // Literal names rank above everything else, then weight, then pattern length.
+    fn rank(&self) -> (bool, usize, usize) {
+        (self.literal.is_some(), self.score, self.pattern_len)
+    }
+}
+
+// Suffix globs keyed by their text read backwards, so a single walk from the end of a
+// filename finds every suffix it ends with.
+struct SuffixTrie {
+    nodes: Vec<SuffixTrieNode>,
+}
+
+#[derive(Default)]
+struct SuffixTrieNode {
+    // Sorted by char.
+    children: Vec<(char, usize)>,
+    // In precedence order.
+    items: Vec<MIMEGlobItem>,
+}
+
+impl SuffixTrie {
+    fn new() -> Self {
+        Self { nodes: vec![SuffixTrieNode::default()] }
+    }
+
+    fn insert(&mut self, suffix: impl DoubleEndedIterator<Item = char>, item: MIMEGlobItem) {
+        let mut node = 0;
+        for ch in suffix.rev() {
+            node = match self.nodes[node].children.binary_search_by_key(&ch, |(ch, _)| *ch) {
+                Ok(pos) => self.nodes[node].children[pos].1,
+                Err(pos) => {
+                    self.nodes.push(SuffixTrieNode::default());
+                    let child = self.nodes.len() - 1;
+                    self.nodes[node].children.insert(pos, (ch, child));
+                    child
+                }
+            };
+        }
+        self.nodes[node].items.push(item);
+    }
+
+    fn child(&self, node: usize, ch: char) -> Option<usize> {
+        let children = &self.nodes[node].children;
+        children.binary_search_by_key(&ch, |(ch, _)| *ch).ok().map(|pos| children[pos].1)
+    }
 }
 
-fn parse_mime_glob<'a, Callback>(slice: &'a [u8], mut callback: Callback) where Callback: FnMut(&'a [u8], &'a [u8], &'a [u8]) -> bool {
+// The longest run of plain characters a pattern requires, which any matching name
+// contains. "[...]" classes count as wildcards.
+fn longest_literal_segment(ptn: &str) -> Option<&str> {
+    let mut longest: Option<&str> = None;
+    let mut start = 0;
+    let mut chars = ptn.char_indices().peekable();
+    while let Some((pos, ch)) = chars.next() {
+        if !matches!(ch, '*' | '?' | '[') {
+            continue;
+        }
+        if pos > start && longest.is_none_or(|longest| longest.len() < pos - start) {
+            longest = Some(&ptn[start..pos]);
+        }
+        if ch == '[' {
+            // A ']' right after the opening "[" or "[!" is a member, not the end.
+            chars.next_if(|(_, ch)| *ch == '!');
+            chars.next_if(|(_, ch)| *ch == ']');
+            for (_, ch) in chars.by_ref() {
+                if ch == ']' {
+                    break;
+                }
+            }
+        }
+        start = chars.peek().map_or(ptn.len(), |(pos, _)| *pos);
+    }
+    if ptn.len() > start && longest.is_none_or(|longest| longest.len() < ptn.len() - start) {
+        longest = Some(&ptn[start..]);
+    }
+
+    longest
+}
+
+fn parse_mime_glob<'a, Callback>(slice: &'a [u8], mut callback: Callback) -> bool where Callback: FnMut(&'a [u8], &'a [u8], &'a [u8], &'a [u8]) -> bool {
     let mut line_start = 0;
     while line_start < slice.len() {
         let Some(line_size) = slice[line_start..].iter().position(|ch| *ch == b'\n') else {
@@ -19,101 +127,451 @@ fn parse_mime_glob<'a, Callback>(slice: &'a [u8], mut callback: Callback) where
         };
 
         if slice[line_start] != b'#' {
-            let line_args = slice[line_start..line_start + line_size].split(|ch| *ch == b':').into_iter().take(3).collect::<Vec<&'a [u8]>>();
+            let line_args = slice[line_start..line_start + line_size].split(|ch| *ch == b':').take(4).collect::<Vec<&'a [u8]>>();
             if line_args.len() < 3 {
                 line_start += line_size + 1;
                 continue;
             }
-            if !callback(line_args[0], line_args[1], line_args[2]) {
-                break;
+            let flags = line_args.get(3).copied().unwrap_or(b"");
+            if !callback(line_args[0], line_args[1], line_args[2], flags) {
+                return false;
             }
         }
 
         line_start += line_size + 1;
     }
+
+    true
 }
 
-pub fn mime_glob_foreach<ForCallback>(
-    mut for_callback: ForCallback) -> Result<()>
-where ForCallback: FnMut(usize, String, &str) -> bool {
-    let file = File::open("/usr/share/mime/globs2")?;
+// The legacy globs format is mime:pattern, with an implied weight of 50.
+fn parse_legacy_mime_glob<'a, Callback>(slice: &'a [u8], mut callback: Callback) -> bool where Callback: FnMut(&'a [u8], &'a [u8]) -> bool {
+    for line in slice.split(|ch| *ch == b'\n') {
+        if line.is_empty() || line[0] == b'#' {
+            continue;
+        }
+        let Some(pos) = line.iter().position(|ch| *ch == b':') else {
+            continue;
+        };
+        if !callback(&line[..pos], &line[pos + 1..]) {
+            return false;
+        }
+    }
+
+    true
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GlobEntry {
+    pub weight: usize,
+    pub mime: String,
+    pub pattern: String,
+    // Such as "cs" for case-sensitive patterns.
+    pub flags: Vec<String>,
+}
+
+impl GlobEntry {
+    pub fn case_sensitive(&self) -> bool {
+        self.flags.iter().any(|flag| flag == "cs")
+    }
+}
+
+// The glob entries of a set of mime dirs, e.g. to build a MIMEGlobIndex from something
+// other than the system database.
+pub struct MimeGlobSource {
+    entries: Vec<GlobEntry>,
+}
+
+impl MimeGlobSource {
+    // Reads the mime.cache (or globs2, or globs, whichever is usable first) of each mime
+    // dir, highest precedence first. A type listed with the __NOGLOBS__ pattern drops its
+    // globs from lower precedence dirs, and entries repeated in lower dirs are skipped.
+    pub fn open(paths: &[PathBuf]) -> Result<Self> {
+        let mut entries = vec![];
+        let mut found = false;
+        let mut overridden: HashSet<String> = HashSet::new();
+        let mut seen: HashSet<(String, String, bool)> = HashSet::new();
+        for mime_dir in paths {
+            let Some(dir_entries) = read_dir_globs(mime_dir)? else {
+                continue;
+            };
+            found = true;
+            let mut cleared = vec![];
+            for entry in dir_entries {
+                if entry.pattern == "__NOGLOBS__" {
+                    cleared.push(entry.mime);
+                    continue;
+                }
+                if overridden.contains(&entry.mime)
+                    || !seen.insert((entry.mime.clone(), entry.pattern.clone(), entry.case_sensitive())) {
+                    continue;
+                }
+                entries.push(entry);
+            }
+            overridden.extend(cleared);
+        }
+        if !found {
+            return Err(Error::new(ErrorKind::NotFound, "no mime.cache, globs2 or globs in any mime dir"));
+        }
+
+        Ok(Self { entries })
+    }
+
+    // The mime dirs of all data dirs, or the bundled database when there are none.
+    pub fn system() -> Result<Self> {
+        let paths: Vec<PathBuf> = dirs::data_dirs_in_precedence().iter().map(|data_dir| data_dir.join("mime")).collect();
+        match Self::open(&paths) {
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                let Some(bundled) = bundled_file("globs2") else {
+                    return Err(err);
+                };
+                Ok(Self { entries: parse_globs2_entries(bundled) })
+            }
+            result => result,
+        }
+    }
+
+    pub fn entries(&self) -> &[GlobEntry] {
+        &self.entries
+    }
+}
+
+impl IntoIterator for MimeGlobSource {
+    type Item = GlobEntry;
+    type IntoIter = std::vec::IntoIter<GlobEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+// The entries of one mime dir. None when the dir has no glob files.
+fn read_dir_globs(mime_dir: &Path) -> Result<Option<Vec<GlobEntry>>> {
+    let cache = MimeCache::open_dir(mime_dir);
+    if let Some(globs) = cache.as_ref().and_then(MimeCache::globs) {
+        return Ok(Some(globs.into_iter().map(|(weight, mime, pattern, cs)| GlobEntry {
+            weight,
+            mime: mime.to_string(),
+            pattern,
+            flags: if cs { vec![String::from("cs")] } else { vec![] },
+        }).collect()));
+    }
+    let Ok(file) = File::open(mime_dir.join("globs2")) else {
+        let Ok(file) = File::open(mime_dir.join("globs")) else {
+            return Ok(None);
+        };
+        let region = unsafe { MmapOptions::new().map(&file)? };
+        let mut entries = vec![];
+        parse_legacy_mime_glob(region.iter().as_slice(), |mime, ptn| {
+            entries.push(GlobEntry {
+                weight: 50,
+                mime: String::from_utf8_lossy(mime).into_owned(),
+                pattern: String::from_utf8_lossy(ptn).into_owned(),
+                flags: vec![],
+            });
+            true
+        });
+        return Ok(Some(entries));
+    };
     let region = unsafe { MmapOptions::new().map(&file)? };
-    parse_mime_glob(region.iter().as_slice(), |score, mime, ptn| {
-        let Ok(Ok(score)) = str::from_utf8(score).map(|s| s.parse::<usize>()) else {
+
+    Ok(Some(parse_globs2_entries(region.iter().as_slice())))
+}
+
+fn parse_globs2_entries(slice: &[u8]) -> Vec<GlobEntry> {
+    let mut entries = vec![];
+    parse_mime_glob(slice, |weight, mime, ptn, flags| {
+        let Ok(Ok(weight)) = str::from_utf8(weight).map(|s| s.parse::<usize>()) else {
             return true; // Skip.
         };
 
-        for_callback(score,
-                     String::from_utf8(mime.to_vec()).unwrap(),
-                     str::from_utf8(ptn).unwrap())
+        entries.push(GlobEntry {
+            weight,
+            mime: String::from_utf8_lossy(mime).into_owned(),
+            pattern: String::from_utf8_lossy(ptn).into_owned(),
+            flags: flags.split(|ch| *ch == b',')
+                .filter(|flag| !flag.is_empty())
+                .map(|flag| String::from_utf8_lossy(flag).into_owned())
+                .collect(),
+        });
+        true
     });
 
+    entries
+}
+
+pub fn mime_glob_foreach<ForCallback>(
+    mut for_callback: ForCallback) -> Result<()>
+where ForCallback: FnMut(usize, String, &str) -> bool {
+    mime_glob_foreach_with_flags(|score, mime, ptn, _| for_callback(score, mime, ptn))
+}
+
+// The entries of MimeGlobSource::system() through a callback, for older callers.
+pub fn mime_glob_foreach_with_flags<ForCallback>(
+    mut for_callback: ForCallback) -> Result<()>
+where ForCallback: FnMut(usize, String, &str, bool) -> bool {
+    for entry in MimeGlobSource::system()? {
+        let case_sensitive = entry.case_sensitive();
+        if !for_callback(entry.weight, entry.mime, &entry.pattern, case_sensitive) {
+            break;
+        }
+    }
+
     Ok(())
 }
 
 pub struct MIMEGlobIndex {
     glob_patterns: Vec<MIMEGlobItem>,
-    glob_suffix_index: HashMap<String, MIMEGlobItem>,
+    // Finds the literal segments of glob_patterns in a filename, so only patterns whose
+    // segment occurs are run.
+    glob_pattern_filter: AhoCorasick,
+    // Indexes into glob_patterns, per segment of the filter.
+    glob_pattern_segments: Vec<Vec<usize>>,
+    // Patterns without any literal segment, which are always run.
+    glob_pattern_unfiltered: Vec<usize>,
+    // Case-sensitive suffixes, and the others lowercased.
+    glob_suffix_exact: SuffixTrie,
+    glob_suffix_folded: SuffixTrie,
+    // Keyed by the lowercased name.
+    glob_literal_index: HashMap<String, Vec<MIMEGlobItem>>,
+    // "*.ext" patterns per type, best first.
+    glob_extensions: HashMap<MimeType, Vec<String>>,
 }
 
 impl MIMEGlobIndex {
     pub fn new() -> Result<Self> {
+        Self::from_source(MimeGlobSource::system()?)
+    }
+
+    pub fn from_source(source: MimeGlobSource) -> Result<Self> {
         let mut glob_patterns: Vec<MIMEGlobItem> = vec![];
-        let mut glob_suffix_index: HashMap<String, MIMEGlobItem> = HashMap::new();
+        let mut glob_suffix_exact = SuffixTrie::new();
+        let mut glob_suffix_folded = SuffixTrie::new();
+        let mut glob_literal_index: HashMap<String, Vec<MIMEGlobItem>> = HashMap::new();
+        let mut extensions: HashMap<MimeType, Vec<(usize, String)>> = HashMap::new();
 
-        mime_glob_foreach(|score, mime, ptn| {
-            if ptn.chars().nth(0) == Some('*') && ptn[1..].chars().all(|ch| ch != '*' && ch != '?') {
-                glob_suffix_index.insert(ptn[1..].to_string(), MIMEGlobItem {
-                    score, mime, pattern: None,
+        for entry in source {
+            let (score, ptn, case_sensitive) = (entry.weight, entry.pattern.as_str(), entry.case_sensitive());
+            let Some(mime) = MimeType::parse(&entry.mime) else {
+                continue;
+            };
+            if !ptn.contains(['*', '?', '[']) {
+                glob_literal_index.entry(ptn.chars().flat_map(char::to_lowercase).collect()).or_default().push(MIMEGlobItem {
+                    score, mime, pattern: None, pattern_len: ptn.len(), literal: Some(ptn.to_string()), case_sensitive,
                 });
-            } else {
+            } else if ptn.chars().nth(0) == Some('*') && !ptn[1..].contains(['*', '?', '[']) {
+                if let Some(ext) = ptn.strip_prefix("*.") {
+                    extensions.entry(mime.clone()).or_default().push((score, ext.to_string()));
+                }
+                // Earlier entries come from higher precedence dirs.
+                let item = MIMEGlobItem { score, mime, pattern: None, pattern_len: ptn.len(), literal: None, case_sensitive };
+                if case_sensitive {
+                    glob_suffix_exact.insert(ptn[1..].chars(), item);
+                } else {
+                    glob_suffix_folded.insert(ptn[1..].chars().flat_map(char::to_lowercase), item);
+                }
+            } else if let Ok(pattern) = Pattern::new(ptn) {
                 glob_patterns.push(MIMEGlobItem {
                     score,
                     mime,
-                    pattern: Some(Pattern::new(ptn).unwrap()),
+                    pattern: Some(pattern),
+                    pattern_len: ptn.len(),
+                    literal: None,
+                    case_sensitive,
                 });
             }
-
-            true
-        })?;
+        }
+        glob_patterns.sort_by_key(|item| Reverse(item.score));
+        let glob_extensions = extensions.into_iter().map(|(mime, mut exts)| {
+            // Stable, so equal weights keep the order of the glob files.
+            exts.sort_by_key(|(score, _)| Reverse(*score));
+            let mut deduped: Vec<String> = vec![];
+            for (_, ext) in exts {
+                if !deduped.iter().any(|seen| seen.eq_ignore_ascii_case(&ext)) {
+                    deduped.push(ext);
+                }
+            }
+            (mime, deduped)
+        }).collect();
+        let mut segment_ids: HashMap<String, usize> = HashMap::new();
+        let mut glob_pattern_segments: Vec<Vec<usize>> = vec![];
+        let mut glob_pattern_unfiltered = vec![];
+        for (i, item) in glob_patterns.iter().enumerate() {
+            let Some(segment) = item.pattern.as_ref().and_then(|pattern| longest_literal_segment(pattern.as_str())) else {
+                glob_pattern_unfiltered.push(i);
+                continue;
+            };
+            // The filter ignores ASCII case, which covers case-sensitive patterns too.
+            let id = *segment_ids.entry(segment.to_ascii_lowercase()).or_insert_with(|| {
+                glob_pattern_segments.push(vec![]);
+                glob_pattern_segments.len() - 1
+            });
+            glob_pattern_segments[id].push(i);
+        }
+        let mut segments = vec![String::new(); glob_pattern_segments.len()];
+        for (segment, id) in segment_ids {
+            segments[id] = segment;
+        }
+        let glob_pattern_filter = AhoCorasick::builder()
+            .ascii_case_insensitive(true)
+            .build(&segments)
+            .map_err(Error::other)?;
 
         Ok(Self {
-            glob_patterns, glob_suffix_index,
+            glob_patterns, glob_pattern_filter, glob_pattern_segments, glob_pattern_unfiltered,
+            glob_suffix_exact, glob_suffix_folded, glob_literal_index, glob_extensions,
         })
     }
 
-    fn match_filename_suffix(&self, filename: &str) -> Option<&MIMEGlobItem> {
-        if let Some(extpos) = filename.rfind('.') {
-            return self.glob_suffix_index.get(&filename[extpos..]);
-        }
+    // `lower` is scratch space, so batches can reuse one buffer.
+    fn match_filename_literal(&self, filename: &str, lower: &mut String) -> Vec<&MIMEGlobItem> {
+        lower.clear();
+        lower.extend(filename.chars().flat_map(char::to_lowercase));
+        let Some(items) = self.glob_literal_index.get(lower.as_str()) else {
+            return vec![];
+        };
 
-        None
+        items.iter().filter(|item| !item.case_sensitive || item.literal.as_deref() == Some(filename)).collect()
     }
 
-    fn match_filename_pattern(&self, filename: &str, min_score: usize) -> Option<&MIMEGlobItem> {
-        for glob_item in &self.glob_patterns {
-            if glob_item.score < min_score {
-                return None;
-            }
-            if glob_item.pattern.as_ref().unwrap().matches(filename) {
-                return Some(glob_item);
+    // Rebuild from disk, e.g. after a MimeWatcher reported changes.
+    pub fn reload(&mut self) -> Result<()> {
+        *self = Self::new()?;
+
+        Ok(())
+    }
+
+    // Suffix items for every suffix the filename ends with, so "a.tar.gz" finds both
+    // ".gz" and ".tar.gz", and "notes~" finds "~".
+    fn match_filename_suffix(&self, filename: &str) -> Vec<&MIMEGlobItem> {
+        let mut items = vec![];
+        let mut exact = Some(0);
+        let mut folded = Some(0);
+        for ch in filename.chars().rev() {
+            exact = exact.and_then(|node| self.glob_suffix_exact.child(node, ch));
+            folded = folded.and_then(|node| {
+                ch.to_lowercase().rev().try_fold(node, |node, ch| self.glob_suffix_folded.child(node, ch))
+            });
+            // Exact case-sensitive matches beat case-folded ones.
+            match (exact.map(|node| &self.glob_suffix_exact.nodes[node].items), folded) {
+                (Some(exact_items), _) if !exact_items.is_empty() => items.extend(exact_items),
+                (_, Some(node)) => items.extend(&self.glob_suffix_folded.nodes[node].items),
+                (None, None) => break,
+                _ => {}
             }
         }
 
-        None
+        items
     }
 
-    pub fn match_filename(&self, filename: &str) -> Option<&str> {
-        let suffix_match = self.match_filename_suffix(filename);
-        let suffix_score = suffix_match.map(|item| item.score).unwrap_or(0);
+    fn match_filename_pattern(&self, filename: &str) -> Vec<&MIMEGlobItem> {
+        let mut candidates = self.glob_pattern_unfiltered.clone();
+        for found in self.glob_pattern_filter.find_overlapping_iter(filename) {
+            candidates.extend(&self.glob_pattern_segments[found.pattern()]);
+        }
+        candidates.sort();
+        candidates.dedup();
+
+        candidates.into_iter()
+            .map(|i| &self.glob_patterns[i])
+            .filter(|item| item.matches(filename))
+            .collect()
+    }
 
-        let pattern_match = self.match_filename_pattern(filename, suffix_score);
-        let pattern_score = pattern_match.map(|item| item.score).unwrap_or(0);
-        if suffix_score > pattern_score {
-            suffix_match.map(|item| item.mime.as_str())
+    // The best match alone. Literal names outrank every other glob, so the suffix and
+    // pattern lookups are skipped when one matches.
+    fn best_match(&self, filename: &str, lower: &mut String) -> Option<MimeMatch<'_>> {
+        let literal = self.match_filename_literal(filename, lower);
+        let item = if literal.is_empty() {
+            self.match_filename_suffix(filename).into_iter()
+                .chain(self.match_filename_pattern(filename))
+                .min_by_key(|item| Reverse(item.rank()))
         } else {
-            pattern_match.map(|item| item.mime.as_str())
+            literal.into_iter().min_by_key(|item| Reverse(item.rank()))
+        };
+
+        item.map(MIMEGlobItem::to_match)
+    }
+
+    pub fn match_filename(&self, filename: &str) -> Option<MimeMatch<'_>> {
+        self.best_match(filename, &mut String::new())
+    }
+
+    // match_filename for many names at once, e.g. a directory listing.
+    pub fn match_filenames<'a, I>(&self, filenames: I) -> Vec<Option<MimeMatch<'_>>> where I: IntoIterator<Item = &'a str> {
+        let mut lower = String::new();
+        filenames.into_iter().map(|filename| self.best_match(filename, &mut lower)).collect()
+    }
+
+    // match_filenames spread over the available cores. Fails if a matching thread panicked.
+    pub fn match_filenames_parallel<S>(&self, filenames: &[S]) -> Result<Vec<Option<MimeMatch<'_>>>> where S: AsRef<str> + Sync {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk_size = filenames.len().div_ceil(threads).max(1);
+        thread::scope(|scope| {
+            let workers: Vec<_> = filenames.chunks(chunk_size)
+                .map(|chunk| scope.spawn(move || self.match_filenames(chunk.iter().map(AsRef::as_ref))))
+                .collect();
+            let mut matches = Vec::with_capacity(filenames.len());
+            for worker in workers {
+                matches.extend(worker.join().map_err(|_| Error::other("matching thread panicked"))?);
+            }
+
+            Ok(matches)
+        })
+    }
+
+    // Matches the final component of a path; directories in it never count.
+    pub fn match_path(&self, path: &Path) -> Option<MimeMatch<'_>> {
+        self.match_filename(&path.file_name()?.to_string_lossy())
+    }
+
+    // Matching items, best first.
+    fn match_items(&self, filename: &str) -> Vec<&MIMEGlobItem> {
+        let mut items = self.match_filename_literal(filename, &mut String::new());
+        items.extend(self.match_filename_suffix(filename));
+        items.extend(self.match_filename_pattern(filename));
+        // Among equal weights the longest pattern wins, so "*.tar.gz" beats "*.gz".
+        items.sort_by_key(|item| Reverse(item.rank()));
+
+        items
+    }
+
+    // Extensions without the dot, e.g. for suggesting a filename when saving data of
+    // that type. The highest weighted come first.
+    pub fn extensions_for(&self, mime: &str) -> Vec<String> {
+        let Some(mime) = MimeType::parse(mime) else {
+            return vec![];
+        };
+
+        self.glob_extensions.get(&mime).cloned().unwrap_or_default()
+    }
+
+    // Every matching type, best match first.
+    pub fn match_all(&self, filename: &str) -> Vec<MimeMatch<'_>> {
+        let mut matches: Vec<MimeMatch> = vec![];
+        for item in self.match_items(filename) {
+            if !matches.iter().any(|found| item.mime == found.mime) {
+                matches.push(item.to_match());
+            }
         }
+
+        matches
     }
 
+    // Every distinct type ranked equal to the best match.
+    pub(crate) fn best_matches(&self, filename: &str) -> Vec<&str> {
+        let items = self.match_items(filename);
+        let Some(best) = items.first().map(|item| item.rank()) else {
+            return vec![];
+        };
+
+        let mut mimes: Vec<&str> = vec![];
+        for item in items.into_iter().take_while(|item| item.rank() == best) {
+            if !mimes.contains(&item.mime.as_str()) {
+                mimes.push(item.mime.as_str());
+            }
+        }
+
+        mimes
+    }
 }
@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+
+use crate::dirs;
+use crate::mime_cache::MimeCache;
+
+pub struct MIMEIconIndex {
+    icons: HashMap<String, String>,
+    generic_icons: HashMap<String, String>,
+}
+
+fn parse_icon_file(content: &str, index: &mut HashMap<String, String>) {
+    for line in content.lines() {
+        if line.starts_with('#') {
+            continue;
+        }
+        let Some((mime, icon)) = line.split_once(':') else {
+            continue;
+        };
+        index.insert(mime.to_string(), icon.to_string());
+    }
+}
+
+impl MIMEIconIndex {
+    pub fn new() -> Result<Self> {
+        let mut icons = HashMap::new();
+        let mut generic_icons = HashMap::new();
+        let mut found = false;
+
+        // Lowest precedence first, so user entries overwrite system ones.
+        for data_dir in dirs::data_dirs_scan_order() {
+            let mime_dir = data_dir.join("mime");
+            let cache = MimeCache::open_dir(&mime_dir);
+            if let Some(cache) = &cache {
+                if let (Some(cached_icons), Some(cached_generic_icons)) = (cache.icons(), cache.generic_icons()) {
+                    found = true;
+                    icons.extend(cached_icons.into_iter().map(|(mime, icon)| (mime.to_string(), icon.to_string())));
+                    generic_icons.extend(cached_generic_icons.into_iter().map(|(mime, icon)| (mime.to_string(), icon.to_string())));
+                    continue;
+                }
+            }
+            for (name, index) in [("icons", &mut icons), ("generic-icons", &mut generic_icons)] {
+                if let Ok(content) = fs::read_to_string(mime_dir.join(name)) {
+                    found = true;
+                    parse_icon_file(&content, index);
+                }
+            }
+        }
+        if !found {
+            return Err(Error::new(ErrorKind::NotFound, "no mime/icons or mime/generic-icons in any data dir"));
+        }
+
+        Ok(Self { icons, generic_icons })
+    }
+
+    // The icon a package explicitly assigned to the type, if any.
+    pub fn icon(&self, mime: &str) -> Option<&str> {
+        self.icons.get(mime).map(String::as_str)
+    }
+
+    pub fn generic_icon(&self, mime: &str) -> Option<&str> {
+        self.generic_icons.get(mime).map(String::as_str)
+    }
+
+    // Candidate icon names for a MIME type, most specific first.
+    pub fn icon_names(&self, mime: &str) -> Vec<String> {
+        let media = mime.split('/').next().unwrap_or(mime);
+        let mut names: Vec<String> = self.icon(mime).map(str::to_string).into_iter().collect();
+        let candidates = [Some(mime.replace('/', "-")),
+                          self.generic_icon(mime).map(str::to_string),
+                          Some(format!("{}-x-generic", media)),
+                          Some(String::from("application-x-generic"))];
+        for name in candidates.into_iter().flatten() {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+
+        names
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+
+use log::warn;
+use roxmltree::{Document, Node, ParsingOptions};
+
+use crate::dirs;
+use crate::locale;
+use crate::mime_type::MimeType;
+
+const XML_NS: &str = "http://www.w3.org/XML/1998/namespace";
+const SHARED_MIME_INFO_NS: &str = "http://www.freedesktop.org/standards/shared-mime-info";
+
+#[derive(Clone, Debug, Default)]
+pub struct LocalizedString {
+    default: Option<String>,
+    translations: HashMap<String, String>,
+}
+
+impl LocalizedString {
+    pub fn insert(&mut self, lang: Option<&str>, text: String) {
+        match lang {
+            Some(lang) => { self.translations.insert(lang.to_string(), text); }
+            None => self.default = Some(text),
+        }
+    }
+
+    // Looks up the identifiers of locale::fallback_chain(locale), then the untranslated text.
+    pub fn get(&self, locale: Option<&str>) -> Option<&str> {
+        self.get_in(&locale.map(locale::fallback_chain).unwrap_or_default())
+    }
+
+    // The first translation along `chain`, or the untranslated text.
+    pub fn get_in(&self, chain: &[String]) -> Option<&str> {
+        locale::best_match(chain, &self.translations).or(self.default.as_ref()).map(String::as_str)
+    }
+
+    // The text in the locale of the process.
+    pub fn localized(&self) -> Option<&str> {
+        self.get_in(&locale::current_fallback_chain())
+    }
+
+    // (lang, text) pairs, the untranslated text first.
+    fn entries(&self) -> Vec<(Option<&str>, &str)> {
+        let mut entries: Vec<(Option<&str>, &str)> = self.default.iter().map(|text| (None, text.as_str())).collect();
+        let mut translations: Vec<(Option<&str>, &str)> = self.translations.iter()
+            .map(|(lang, text)| (Some(lang.as_str()), text.as_str()))
+            .collect();
+        translations.sort();
+        entries.extend(translations);
+
+        entries
+    }
+
+    fn merge(&mut self, other: LocalizedString) {
+        if other.default.is_some() {
+            self.default = other.default;
+        }
+        self.translations.extend(other.translations);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MIMEInfoGlob {
+    pub pattern: String,
+    pub weight: usize,
+    pub case_sensitive: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MIMEInfo {
+    pub mime: String,
+    pub comment: LocalizedString,
+    pub acronym: LocalizedString,
+    pub expanded_acronym: LocalizedString,
+    pub globs: Vec<MIMEInfoGlob>,
+    pub subclass_of: Vec<String>,
+    pub aliases: Vec<String>,
+    pub icon: Option<String>,
+    pub generic_icon: Option<String>,
+}
+
+pub(crate) fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+pub(crate) fn parse_document(text: &str) -> Result<Document<'_>> {
+    let options = ParsingOptions { allow_dtd: true, ..ParsingOptions::default() };
+    Document::parse_with_options(text, options).map_err(|err| Error::new(ErrorKind::InvalidData, err))
+}
+
+pub(crate) fn parse_mime_type(node: Node) -> Option<MIMEInfo> {
+    let mut info = MIMEInfo { mime: node.attribute("type")?.to_string(), ..MIMEInfo::default() };
+    for child in node.children().filter(Node::is_element) {
+        let text = child.text().unwrap_or("").trim().to_string();
+        let lang = child.attribute((XML_NS, "lang"));
+        match child.tag_name().name() {
+            "comment" => info.comment.insert(lang, text),
+            "acronym" => info.acronym.insert(lang, text),
+            "expanded-acronym" => info.expanded_acronym.insert(lang, text),
+            "glob" => {
+                let Some(pattern) = child.attribute("pattern") else {
+                    continue;
+                };
+                info.globs.push(MIMEInfoGlob {
+                    pattern: pattern.to_string(),
+                    weight: child.attribute("weight").and_then(|w| w.parse().ok()).unwrap_or(50),
+                    case_sensitive: child.attribute("case-sensitive") == Some("true"),
+                });
+            }
+            "sub-class-of" => info.subclass_of.extend(child.attribute("type").map(str::to_string)),
+            "alias" => info.aliases.extend(child.attribute("type").map(str::to_string)),
+            "icon" => info.icon = child.attribute("name").map(str::to_string),
+            "generic-icon" => info.generic_icon = child.attribute("name").map(str::to_string),
+            _ => {}
+        }
+    }
+
+    Some(info)
+}
+
+impl MIMEInfo {
+    // Metadata of a single type, from the per-type file update-mime-database writes in the
+    // highest precedence data dir that has one. Strings that are not a MIME type, which
+    // could name other files, find nothing.
+    pub fn load(mime: &str) -> Option<Self> {
+        let mime = MimeType::parse(mime)?;
+        for data_dir in dirs::data_dirs_in_precedence() {
+            let Ok(text) = fs::read_to_string(data_dir.join("mime").join(format!("{}.xml", mime.as_str()))) else {
+                continue;
+            };
+            let doc = parse_document(&text).ok()?;
+            return parse_mime_type(doc.root_element());
+        }
+
+        None
+    }
+
+    // The child elements of <mime-type>, each on a line indented by `indent`.
+    fn elements_xml(&self, indent: &str) -> String {
+        let mut xml = String::new();
+        for (tag, text) in [("comment", &self.comment), ("acronym", &self.acronym), ("expanded-acronym", &self.expanded_acronym)] {
+            for (lang, text) in text.entries() {
+                match lang {
+                    Some(lang) => xml += &format!("{}<{} xml:lang=\"{}\">{}</{}>\n", indent, tag, escape_xml(lang), escape_xml(text), tag),
+                    None => xml += &format!("{}<{}>{}</{}>\n", indent, tag, escape_xml(text), tag),
+                }
+            }
+        }
+        for glob in &self.globs {
+            xml += &format!("{}<glob pattern=\"{}\" weight=\"{}\"", indent, escape_xml(&glob.pattern), glob.weight);
+            if glob.case_sensitive {
+                xml += " case-sensitive=\"true\"";
+            }
+            xml += "/>\n";
+        }
+        for parent in &self.subclass_of {
+            xml += &format!("{}<sub-class-of type=\"{}\"/>\n", indent, escape_xml(parent));
+        }
+        for alias in &self.aliases {
+            xml += &format!("{}<alias type=\"{}\"/>\n", indent, escape_xml(alias));
+        }
+        if let Some(icon) = &self.icon {
+            xml += &format!("{}<icon name=\"{}\"/>\n", indent, escape_xml(icon));
+        }
+        if let Some(icon) = &self.generic_icon {
+            xml += &format!("{}<generic-icon name=\"{}\"/>\n", indent, escape_xml(icon));
+        }
+
+        xml
+    }
+
+    // A package document defining just this type.
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml += &format!("<mime-info xmlns=\"{}\">\n", SHARED_MIME_INFO_NS);
+        xml += &format!("  <mime-type type=\"{}\">\n", escape_xml(&self.mime));
+        xml += &self.elements_xml("    ");
+        xml += "  </mime-type>\n</mime-info>\n";
+
+        xml
+    }
+
+    // The per-type file update-mime-database writes as <media>/<subtype>.xml, which load()
+    // reads.
+    pub(crate) fn to_type_xml(&self) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml += &format!("<mime-type xmlns=\"{}\" type=\"{}\">\n", SHARED_MIME_INFO_NS, escape_xml(&self.mime));
+        xml += &self.elements_xml("  ");
+        xml += "</mime-type>\n";
+
+        xml
+    }
+
+    pub(crate) fn merge(&mut self, other: MIMEInfo) {
+        self.comment.merge(other.comment);
+        self.acronym.merge(other.acronym);
+        self.expanded_acronym.merge(other.expanded_acronym);
+        self.globs.extend(other.globs);
+        self.subclass_of.extend(other.subclass_of);
+        self.aliases.extend(other.aliases);
+        if other.icon.is_some() {
+            self.icon = other.icon;
+        }
+        if other.generic_icon.is_some() {
+            self.generic_icon = other.generic_icon;
+        }
+    }
+}
+
+// Every type described by the mime/packages/*.xml sources.
+pub struct MIMEInfoIndex {
+    types: HashMap<String, MIMEInfo>,
+}
+
+impl MIMEInfoIndex {
+    pub fn new() -> Result<Self> {
+        let mut types: HashMap<String, MIMEInfo> = HashMap::new();
+        let mut found = false;
+        for data_dir in dirs::data_dirs_scan_order() {
+            let Ok(entries) = fs::read_dir(data_dir.join("mime/packages")) else {
+                continue;
+            };
+            let mut packages = entries.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == "xml"))
+                .collect::<Vec<_>>();
+            packages.sort();
+            for package in packages {
+                let Ok(text) = fs::read_to_string(&package) else {
+                    continue;
+                };
+                let doc = match parse_document(&text) {
+                    Ok(doc) => doc,
+                    Err(err) => {
+                        warn!("Cannot parse {}: {}", package.display(), err);
+                        continue;
+                    }
+                };
+                found = true;
+                for info in doc.root_element().children().filter(|node| node.has_tag_name("mime-type")).filter_map(parse_mime_type) {
+                    // Later data dirs and packages add to what came before.
+                    match types.get_mut(&info.mime) {
+                        Some(existing) => existing.merge(info),
+                        None => { types.insert(info.mime.clone(), info); }
+                    }
+                }
+            }
+        }
+        if !found {
+            return Err(Error::new(ErrorKind::NotFound, "no mime/packages in any data dir"));
+        }
+
+        Ok(Self { types })
+    }
+
+    pub fn get(&self, mime: &str) -> Option<&MIMEInfo> {
+        self.types.get(mime)
+    }
+
+    pub fn types(&self) -> impl Iterator<Item = &MIMEInfo> {
+        self.types.values()
+    }
+}
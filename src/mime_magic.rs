@@ -0,0 +1,256 @@
+use core::str;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Result};
+use std::path::Path;
+
+use memmap::MmapOptions;
+
+use crate::dirs;
+use crate::mime_bundled::bundled_file;
+use crate::mime_cache::MimeCache;
+use crate::mime_type::{MatchSource, MimeMatch, MimeType};
+
+const MAGIC_HEADER: &[u8] = b"MIME-Magic\0\n";
+const NO_MAGIC: &[u8] = b"__NOMAGIC__";
+
+pub(crate) struct MagicRule {
+    pub(crate) indent: usize,
+    pub(crate) start: usize,
+    pub(crate) value: Vec<u8>,
+    pub(crate) mask: Option<Vec<u8>>,
+    pub(crate) range: usize,
+}
+
+pub(crate) struct MagicSection {
+    pub(crate) priority: usize,
+    pub(crate) mime: MimeType,
+    pub(crate) rules: Vec<MagicRule>,
+    // Set by a __NOMAGIC__ line: the rules of the type in lower precedence dirs are dropped.
+    pub(crate) no_magic: bool,
+}
+
+impl MagicSection {
+    fn to_match(&self) -> MimeMatch<'_> {
+        MimeMatch { mime: self.mime.as_str(), weight: self.priority, source: MatchSource::Magic }
+    }
+}
+
+impl MagicRule {
+    fn extent(&self) -> usize {
+        self.start + self.range + self.value.len()
+    }
+
+    fn test(&self, data: &[u8]) -> bool {
+        (self.start..self.start + self.range).any(|offset| {
+            let Some(window) = data.get(offset..offset + self.value.len()) else {
+                return false;
+            };
+            match &self.mask {
+                Some(mask) => window.iter().zip(mask).zip(&self.value).all(|((d, m), v)| d & m == v & m),
+                None => window == self.value.as_slice(),
+            }
+        })
+    }
+}
+
+// A rule matches if its own test passes and, when it has nested rules, one of them matches too.
+fn match_rules(rules: &[MagicRule], data: &[u8], indent: usize) -> bool {
+    let mut i = 0;
+    while i < rules.len() {
+        let end = i + 1 + rules[i + 1..].iter().take_while(|r| r.indent > indent).count();
+        if rules[i].test(data) && (end == i + 1 || match_rules(&rules[i + 1..end], data, indent + 1)) {
+            return true;
+        }
+        i = end;
+    }
+
+    false
+}
+
+fn parse_number(buf: &[u8], pos: &mut usize) -> Option<usize> {
+    let len = buf[*pos..].iter().take_while(|ch| ch.is_ascii_digit()).count();
+    if len == 0 {
+        return None;
+    }
+    let number = str::from_utf8(&buf[*pos..*pos + len]).ok()?.parse().ok()?;
+    *pos += len;
+
+    Some(number)
+}
+
+// Values with a word size (host16, host32) are stored big endian and compared in the
+// byte order of this machine.
+pub(crate) fn to_host_order(value: &mut [u8], mask: Option<&mut [u8]>, word_size: usize) {
+    if cfg!(target_endian = "big") || word_size <= 1 {
+        return;
+    }
+    for chunk in value.chunks_mut(word_size) {
+        chunk.reverse();
+    }
+    for chunk in mask.into_iter().flat_map(|mask| mask.chunks_mut(word_size)) {
+        chunk.reverse();
+    }
+}
+
+fn parse_rule(buf: &[u8], pos: &mut usize) -> Option<MagicRule> {
+    let indent = parse_number(buf, pos).unwrap_or(0);
+    if *buf.get(*pos)? != b'>' {
+        return None;
+    }
+    *pos += 1;
+    let start = parse_number(buf, pos)?;
+    if *buf.get(*pos)? != b'=' {
+        return None;
+    }
+    let len = u16::from_be_bytes(buf.get(*pos + 1..*pos + 3)?.try_into().ok()?) as usize;
+    *pos += 3;
+    let mut value = buf.get(*pos..*pos + len)?.to_vec();
+    *pos += len;
+
+    let mut mask = None;
+    if buf.get(*pos) == Some(&b'&') {
+        mask = Some(buf.get(*pos + 1..*pos + 1 + len)?.to_vec());
+        *pos += 1 + len;
+    }
+    let mut word_size = 1;
+    if buf.get(*pos) == Some(&b'~') {
+        *pos += 1;
+        word_size = parse_number(buf, pos)?;
+    }
+    let mut range = 1;
+    if buf.get(*pos) == Some(&b'+') {
+        *pos += 1;
+        range = parse_number(buf, pos)?;
+    }
+    // Skip extensions we do not understand.
+    *pos += buf[*pos..].iter().position(|ch| *ch == b'\n')? + 1;
+
+    to_host_order(&mut value, mask.as_deref_mut(), word_size);
+
+    Some(MagicRule { indent, start, value, mask, range })
+}
+
+fn parse_magic(buf: &[u8], sections: &mut Vec<MagicSection>) {
+    if !buf.starts_with(MAGIC_HEADER) {
+        return;
+    }
+    let mut pos = MAGIC_HEADER.len();
+    while pos < buf.len() {
+        if buf[pos] == b'[' {
+            let Some(line_size) = buf[pos..].iter().position(|ch| *ch == b'\n') else {
+                return;
+            };
+            let line = String::from_utf8_lossy(&buf[pos + 1..pos + line_size]).into_owned();
+            pos += line_size + 1;
+            let Some((priority, mime)) = line.trim_end_matches(']').split_once(':') else {
+                return;
+            };
+            let Some(mime) = MimeType::parse(mime) else {
+                return;
+            };
+            sections.push(MagicSection {
+                priority: priority.parse().unwrap_or(50),
+                mime,
+                rules: vec![],
+                no_magic: false,
+            });
+        } else if buf[pos..].starts_with(NO_MAGIC) && buf.get(pos + NO_MAGIC.len()) == Some(&b'\n') {
+            let Some(section) = sections.last_mut() else {
+                return;
+            };
+            section.no_magic = true;
+            pos += NO_MAGIC.len() + 1;
+        } else {
+            // A corrupted rule makes the remainder of the file unreliable.
+            let (Some(rule), Some(section)) = (parse_rule(buf, &mut pos), sections.last_mut()) else {
+                return;
+            };
+            section.rules.push(rule);
+        }
+    }
+}
+
+pub struct MIMEMagicIndex {
+    sections: Vec<MagicSection>,
+    max_extent: usize,
+}
+
+impl MIMEMagicIndex {
+    pub fn new() -> Result<Self> {
+        let mut sections: Vec<MagicSection> = vec![];
+        let mut found = false;
+        // Types whose rules a higher precedence dir cleared with __NOMAGIC__.
+        let mut overridden: HashSet<MimeType> = HashSet::new();
+        for data_dir in dirs::data_dirs_in_precedence() {
+            let mime_dir = data_dir.join("mime");
+            let mut dir_sections = vec![];
+            if let Some(cached) = MimeCache::open_dir(&mime_dir).and_then(|cache| cache.magic_sections()) {
+                dir_sections = cached;
+            } else if let Ok(file) = File::open(mime_dir.join("magic")) {
+                let region = unsafe { MmapOptions::new().map(&file)? };
+                parse_magic(region.iter().as_slice(), &mut dir_sections);
+            } else {
+                continue;
+            }
+            found = true;
+            let mut cleared = vec![];
+            for mut section in dir_sections {
+                if overridden.contains(&section.mime) {
+                    continue;
+                }
+                // mime.cache keeps __NOMAGIC__ as a matchlet value. Rules of the same dir
+                // still apply.
+                if section.no_magic || section.rules.iter().any(|rule| rule.value == NO_MAGIC) {
+                    cleared.push(section.mime.clone());
+                    section.rules.retain(|rule| rule.value != NO_MAGIC);
+                }
+                if !section.rules.is_empty() {
+                    sections.push(section);
+                }
+            }
+            overridden.extend(cleared);
+        }
+        if !found {
+            let Some(bundled) = bundled_file("magic") else {
+                return Err(Error::new(ErrorKind::NotFound, "no mime/magic in any data dir"));
+            };
+            parse_magic(bundled, &mut sections);
+        }
+        sections.sort_by_key(|section| Reverse(section.priority));
+        let max_extent = sections.iter().flat_map(|s| s.rules.iter()).map(|r| r.extent()).max().unwrap_or(0);
+
+        Ok(Self { sections, max_extent })
+    }
+
+    // Number of leading bytes the rules may look at.
+    pub fn max_extent(&self) -> usize {
+        self.max_extent
+    }
+
+    // Every matching type, highest priority first.
+    pub fn sniff_all(&self, data: &[u8]) -> Vec<MimeMatch<'_>> {
+        self.sections.iter()
+            .filter(|section| match_rules(&section.rules, data, 0))
+            .map(MagicSection::to_match)
+            .collect()
+    }
+
+    pub fn sniff_bytes(&self, data: &[u8]) -> Option<MimeMatch<'_>> {
+        self.sections.iter().find(|section| match_rules(&section.rules, data, 0)).map(MagicSection::to_match)
+    }
+
+    // Reads only as much as the rules can look at, so pipes and sockets can be sniffed
+    // without draining them.
+    pub fn sniff_reader(&self, reader: impl Read) -> Result<Option<MimeMatch<'_>>> {
+        let mut data = vec![];
+        reader.take(self.max_extent as u64).read_to_end(&mut data)?;
+
+        Ok(self.sniff_bytes(&data))
+    }
+
+    pub fn sniff_file(&self, path: &Path) -> Result<Option<MimeMatch<'_>>> {
+        self.sniff_reader(File::open(path)?)
+    }
+}
@@ -0,0 +1,204 @@
+use std::cmp::Reverse;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use crate::dirs;
+
+const TREEMAGIC_HEADER: &str = "MIME-TreeMagic\0\n";
+
+#[derive(PartialEq, Eq)]
+enum TreeMagicKind {
+    File,
+    Directory,
+    Link,
+    Any,
+}
+
+struct TreeMagicRule {
+    indent: usize,
+    path: String,
+    kind: TreeMagicKind,
+    match_case: bool,
+    executable: bool,
+    non_empty: bool,
+    on_disc: bool,
+    // Files must also be of this type.
+    mime: Option<String>,
+}
+
+struct TreeMagicSection {
+    priority: usize,
+    mime: String,
+    rules: Vec<TreeMagicRule>,
+}
+
+fn parse_rule(line: &str) -> Option<TreeMagicRule> {
+    let (indent, rest) = line.split_once('>')?;
+    let indent = if indent.is_empty() { 0 } else { indent.parse().ok()? };
+    let rest = rest.strip_prefix('"')?;
+    let (path, rest) = rest.split_once("\"=")?;
+    let mut options = rest.split(',');
+    let kind = match options.next()? {
+        "file" => TreeMagicKind::File,
+        "directory" => TreeMagicKind::Directory,
+        "link" => TreeMagicKind::Link,
+        "any" => TreeMagicKind::Any,
+        _ => return None,
+    };
+    let mut rule = TreeMagicRule {
+        indent, path: path.to_string(), kind,
+        match_case: false, executable: false, non_empty: false, on_disc: false, mime: None,
+    };
+    for option in options {
+        match option {
+            "match-case" => rule.match_case = true,
+            "executable" => rule.executable = true,
+            "non-empty" => rule.non_empty = true,
+            "on-disc" => rule.on_disc = true,
+            mime if mime.contains('/') => rule.mime = Some(mime.to_string()),
+            _ => {}
+        }
+    }
+
+    Some(rule)
+}
+
+fn parse_treemagic(content: &str, sections: &mut Vec<TreeMagicSection>) {
+    let Some(content) = content.strip_prefix(TREEMAGIC_HEADER) else {
+        return;
+    };
+    for line in content.lines() {
+        if let Some(header) = line.strip_prefix('[') {
+            let Some((priority, mime)) = header.trim_end_matches(']').split_once(':') else {
+                return;
+            };
+            sections.push(TreeMagicSection {
+                priority: priority.parse().unwrap_or(50),
+                mime: mime.to_string(),
+                rules: vec![],
+            });
+        } else if let (Some(rule), Some(section)) = (parse_rule(line), sections.last_mut()) {
+            section.rules.push(rule);
+        }
+    }
+}
+
+// Resolve a relative path one component at a time, ignoring case unless asked not to.
+fn resolve_path(root: &Path, path: &str, match_case: bool) -> Option<PathBuf> {
+    if match_case {
+        let resolved = root.join(path);
+        return fs::symlink_metadata(&resolved).is_ok().then_some(resolved);
+    }
+    let mut resolved = root.to_path_buf();
+    for component in path.split('/').filter(|component| !component.is_empty()) {
+        let entry = fs::read_dir(&resolved).ok()?
+            .filter_map(|entry| entry.ok())
+            .find(|entry| entry.file_name().to_str().is_some_and(|name| name.eq_ignore_ascii_case(component)))?;
+        resolved = entry.path();
+    }
+
+    Some(resolved)
+}
+
+// Optical media show up as iso9660 or udf mounts.
+fn is_on_disc(root: &Path) -> bool {
+    let Ok(root) = root.canonicalize() else {
+        return false;
+    };
+    let Ok(mounts) = fs::read_to_string("/proc/self/mounts") else {
+        return false;
+    };
+    mounts.lines().any(|line| {
+        let fields: Vec<&str> = line.split(' ').collect();
+        fields.len() > 2 && Path::new(fields[1]) == root && (fields[2] == "iso9660" || fields[2] == "udf")
+    })
+}
+
+impl TreeMagicRule {
+    fn test<F>(&self, root: &Path, mime_of: &F) -> bool where F: Fn(&Path) -> String {
+        if self.on_disc && !is_on_disc(root) {
+            return false;
+        }
+        let Some(path) = resolve_path(root, &self.path, self.match_case) else {
+            return false;
+        };
+        let Ok(link_meta) = fs::symlink_metadata(&path) else {
+            return false;
+        };
+        let meta = fs::metadata(&path).unwrap_or_else(|_| link_meta.clone());
+        let kind_matches = match self.kind {
+            TreeMagicKind::File => meta.is_file(),
+            TreeMagicKind::Directory => meta.is_dir(),
+            TreeMagicKind::Link => link_meta.file_type().is_symlink(),
+            TreeMagicKind::Any => true,
+        };
+        if !kind_matches {
+            return false;
+        }
+        #[cfg(unix)]
+        if self.executable && meta.permissions().mode() & 0o111 == 0 {
+            return false;
+        }
+        if self.non_empty {
+            let empty = if meta.is_dir() {
+                fs::read_dir(&path).map(|mut entries| entries.next().is_none()).unwrap_or(true)
+            } else {
+                meta.len() == 0
+            };
+            if empty {
+                return false;
+            }
+        }
+
+        self.mime.as_ref().is_none_or(|mime| mime_of(&path) == *mime)
+    }
+}
+
+fn match_rules<F>(rules: &[TreeMagicRule], root: &Path, mime_of: &F, indent: usize) -> bool where F: Fn(&Path) -> String {
+    let mut i = 0;
+    while i < rules.len() {
+        let end = i + 1 + rules[i + 1..].iter().take_while(|r| r.indent > indent).count();
+        if rules[i].test(root, mime_of) && (end == i + 1 || match_rules(&rules[i + 1..end], root, mime_of, indent + 1)) {
+            return true;
+        }
+        i = end;
+    }
+
+    false
+}
+
+pub struct MIMETreeMagicIndex {
+    sections: Vec<TreeMagicSection>,
+}
+
+impl MIMETreeMagicIndex {
+    pub fn new() -> Result<Self> {
+        let mut sections = vec![];
+        let mut found = false;
+        for data_dir in dirs::data_dirs_in_precedence() {
+            let Ok(content) = fs::read_to_string(data_dir.join("mime/treemagic")) else {
+                continue;
+            };
+            found = true;
+            parse_treemagic(&content, &mut sections);
+        }
+        if !found {
+            return Err(Error::new(ErrorKind::NotFound, "no mime/treemagic in any data dir"));
+        }
+        sections.sort_by_key(|section| Reverse(section.priority));
+
+        Ok(Self { sections })
+    }
+
+    // The x-content/* types of a directory tree, such as a mounted volume, highest
+    // priority first. `mime_of` classifies files for rules that require a type.
+    pub fn sniff_dir<F>(&self, root: &Path, mime_of: F) -> Vec<&str> where F: Fn(&Path) -> String {
+        self.sections.iter()
+            .filter(|section| match_rules(&section.rules, root, &mime_of, 0))
+            .map(|section| section.mime.as_str())
+            .collect()
+    }
+}
@@ -0,0 +1,112 @@
+use std::borrow::Borrow;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::{Error, ErrorKind};
+use std::str::FromStr;
+
+// A validated "media/subtype" pair. Types compare case-insensitively, so they are kept
+// lowercased, and parameters such as "; charset=utf-8" are dropped.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MimeType {
+    essence: String,
+    slash: usize,
+}
+
+// Which kind of rule produced a match.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MatchSource {
+    // A whole filename, such as "Makefile".
+    Literal,
+    // A "*.ext" style glob.
+    Suffix,
+    // Any other glob.
+    Glob,
+    // A magic rule on the content.
+    Magic,
+}
+
+// A type found by a glob or magic lookup, with the weight (or magic priority) of the rule
+// that matched, so callers can resolve conflicts themselves.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MimeMatch<'a> {
+    pub mime: &'a str,
+    pub weight: usize,
+    pub source: MatchSource,
+}
+
+// RFC 6838 restricted-name.
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|ch| ch.is_ascii_alphanumeric())
+        && name.len() <= 127
+        && chars.all(|ch| ch.is_ascii_alphanumeric() || "!#$&-^_.+".contains(ch))
+}
+
+impl MimeType {
+    pub fn parse(text: &str) -> Option<Self> {
+        let essence = text.split(';').next().unwrap_or(text).trim();
+        let (media, subtype) = essence.split_once('/')?;
+        if !is_valid_name(media) || !is_valid_name(subtype) {
+            return None;
+        }
+
+        Some(Self { essence: essence.to_ascii_lowercase(), slash: media.len() })
+    }
+
+    pub fn media(&self) -> &str {
+        &self.essence[..self.slash]
+    }
+
+    pub fn subtype(&self) -> &str {
+        &self.essence[self.slash + 1..]
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.essence
+    }
+}
+
+// Hashes like the essence string, so maps keyed by MimeType can be queried with &str.
+impl Hash for MimeType {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.essence.hash(state);
+    }
+}
+
+impl Borrow<str> for MimeType {
+    fn borrow(&self) -> &str {
+        &self.essence
+    }
+}
+
+impl AsRef<str> for MimeType {
+    fn as_ref(&self) -> &str {
+        &self.essence
+    }
+}
+
+impl PartialEq<str> for MimeType {
+    fn eq(&self, other: &str) -> bool {
+        self.essence == other
+    }
+}
+
+impl PartialEq<&str> for MimeType {
+    fn eq(&self, other: &&str) -> bool {
+        self.essence == *other
+    }
+}
+
+impl fmt::Display for MimeType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.essence)
+    }
+}
+
+impl FromStr for MimeType {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Self::parse(text).ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("invalid MIME type {}", text)))
+    }
+}
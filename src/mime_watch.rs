@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+use std::io::{ErrorKind, Result};
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::PathBuf;
+
+use inotify::{Inotify, WatchDescriptor, WatchMask};
+
+use crate::dirs;
+
+// update-mime-database replaces files by renaming them into place.
+fn mime_dir_mask() -> WatchMask {
+    WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::MOVED_FROM | WatchMask::CREATE | WatchMask::DELETE
+}
+
+// Reports changes to the mime dirs of every data dir, so long-running processes know when
+// to reload their MIME indexes.
+pub struct MimeWatcher {
+    inotify: Inotify,
+    // Data dirs without a mime dir yet, watched until one is created.
+    pending: HashMap<WatchDescriptor, PathBuf>,
+    buffer: Vec<u8>,
+}
+
+impl MimeWatcher {
+    pub fn new() -> Result<Self> {
+        let inotify = Inotify::init()?;
+        let mut pending = HashMap::new();
+        for data_dir in dirs::data_dirs_scan_order() {
+            let mime_dir = data_dir.join("mime");
+            if mime_dir.is_dir() {
+                inotify.watches().add(&mime_dir, mime_dir_mask())?;
+            } else if let Ok(wd) = inotify.watches().add(&data_dir, WatchMask::CREATE | WatchMask::MOVED_TO) {
+                pending.insert(wd, data_dir);
+            }
+        }
+
+        Ok(Self { inotify, pending, buffer: vec![0; 4096] })
+    }
+
+    fn handle_events(&mut self, blocking: bool) -> Result<bool> {
+        let events = if blocking {
+            self.inotify.read_events_blocking(&mut self.buffer)
+        } else {
+            self.inotify.read_events(&mut self.buffer)
+        };
+        let events = match events {
+            Ok(events) => events,
+            Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(false),
+            Err(err) => return Err(err),
+        };
+
+        let mut changed = false;
+        let mut created = vec![];
+        for event in events {
+            match self.pending.get(&event.wd) {
+                Some(data_dir) if event.name.is_some_and(|name| name == "mime") => created.push(data_dir.join("mime")),
+                Some(_) => {}
+                None => changed = true,
+            }
+        }
+        for mime_dir in created {
+            self.inotify.watches().add(&mime_dir, mime_dir_mask())?;
+            changed = true;
+        }
+
+        Ok(changed)
+    }
+
+    // Whether anything changed since the last call, without blocking.
+    pub fn poll(&mut self) -> Result<bool> {
+        let mut changed = false;
+        while self.handle_events(false)? {
+            changed = true;
+        }
+
+        Ok(changed)
+    }
+
+    // Blocks until a mime dir changes.
+    pub fn wait(&mut self) -> Result<()> {
+        while !self.handle_events(true)? {}
+
+        Ok(())
+    }
+}
+
+// For integration with poll(2) based event loops.
+impl AsRawFd for MimeWatcher {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inotify.as_raw_fd()
+    }
+}
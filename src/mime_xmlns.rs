@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+
+use crate::dirs;
+use crate::mime_cache::MimeCache;
+use crate::mime_type::MimeType;
+
+// Where a "<!DOCTYPE ...>" ends, counting the internal subset in brackets.
+fn doctype_end(text: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    for (pos, ch) in text.char_indices() {
+        match ch {
+            '[' => depth += 1,
+            ']' => depth = depth.saturating_sub(1),
+            '>' if depth == 0 => return Some(pos + 1),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+// The (namespace, local name) of the root element at the start of an XML document. Only
+// the first bytes are needed, so `data` may be cut off anywhere after the start tag.
+pub fn root_element(data: &[u8]) -> Option<(String, String)> {
+    let text = String::from_utf8_lossy(data);
+    let mut rest = text.trim_start_matches('\u{feff}');
+    // Skip the prolog.
+    loop {
+        rest = rest.trim_start();
+        if let Some(pi) = rest.strip_prefix("<?") {
+            rest = &pi[pi.find("?>")? + 2..];
+        } else if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = &comment[comment.find("-->")? + 3..];
+        } else if let Some(doctype) = rest.strip_prefix("<!") {
+            rest = &doctype[doctype_end(doctype)?..];
+        } else {
+            rest = rest.strip_prefix('<')?;
+            break;
+        }
+    }
+
+    let name_end = rest.find(|ch: char| ch.is_whitespace() || ch == '>' || ch == '/')?;
+    let (name, mut attrs) = rest.split_at(name_end);
+    let (prefix, local) = name.split_once(':').unwrap_or(("", name));
+    let xmlns = if prefix.is_empty() { String::from("xmlns") } else { format!("xmlns:{}", prefix) };
+    loop {
+        attrs = attrs.trim_start();
+        if attrs.is_empty() || attrs.starts_with(['>', '/']) {
+            return None;
+        }
+        let (attr, value) = attrs.split_once('=')?;
+        let value = value.trim_start();
+        let quote = value.chars().next().filter(|quote| *quote == '"' || *quote == '\'')?;
+        let (value, after) = value[1..].split_once(quote)?;
+        if attr.trim() == xmlns {
+            return Some((value.to_string(), local.to_string()));
+        }
+        attrs = after;
+    }
+}
+
+// Types of XML documents by the namespace and name of their root element, e.g.
+// image/svg+xml for <svg xmlns="http://www.w3.org/2000/svg">.
+pub struct MIMEXMLNamespaceIndex {
+    namespaces: HashMap<(String, String), MimeType>,
+}
+
+impl MIMEXMLNamespaceIndex {
+    pub fn new() -> Result<Self> {
+        let mut namespaces = HashMap::new();
+        let mut found = false;
+        // Starting with the highest precedence dir, so its entries are kept.
+        let mut add = |namespace: &str, local: &str, mime: &str| {
+            if let Some(mime) = MimeType::parse(mime) {
+                namespaces.entry((namespace.to_string(), local.to_string())).or_insert(mime);
+            }
+        };
+        for data_dir in dirs::data_dirs_in_precedence() {
+            let mime_dir = data_dir.join("mime");
+            let cache = MimeCache::open_dir(&mime_dir);
+            if let Some(cached) = cache.as_ref().and_then(MimeCache::namespaces) {
+                found = true;
+                cached.into_iter().for_each(|(namespace, local, mime)| add(namespace, local, mime));
+                continue;
+            }
+            let Ok(content) = fs::read_to_string(mime_dir.join("XMLnamespaces")) else {
+                continue;
+            };
+            found = true;
+            for line in content.lines() {
+                let fields: Vec<&str> = line.split(' ').collect();
+                if let [namespace, local, mime] = fields[..] {
+                    add(namespace, local, mime);
+                }
+            }
+        }
+        if !found {
+            return Err(Error::new(ErrorKind::NotFound, "no mime/XMLnamespaces in any data dir"));
+        }
+
+        Ok(Self { namespaces })
+    }
+
+    // The type of an XML document from its first bytes.
+    pub fn sniff_bytes(&self, data: &[u8]) -> Option<&str> {
+        self.namespaces.get(&root_element(data)?).map(MimeType::as_str)
+    }
+}
@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+use zbus::zvariant::Value;
+
+use crate::icon::IconCollection;
+use crate::uri::path_to_uri;
+
+const ICON_SIZE: usize = 48;
+
+// The urgency hint of the notification spec.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Urgency {
+    Low,
+    #[default]
+    Normal,
+    Critical,
+}
+
+// The app_icon of a notification: file paths as file: URIs, and icon names as the file
+// the current theme has for them, since not every notification daemon looks names up.
+// Names the theme lacks are passed on for the daemon to try.
+fn app_icon(icon: &str) -> String {
+    if icon.starts_with('/') {
+        return path_to_uri(Path::new(icon));
+    }
+    match IconCollection::scan_current_theme().lookup(icon, ICON_SIZE) {
+        Ok(found) => path_to_uri(&found.path),
+        Err(_) => icon.to_string(),
+    }
+}
+
+// Shows a notification through org.freedesktop.Notifications and returns its id. `body`
+// may be empty, and `icon` an icon name or a file path.
+pub fn send(summary: &str, body: &str, icon: Option<&str>, urgency: Urgency) -> io::Result<u32> {
+    let connection = zbus::blocking::Connection::session().map_err(io::Error::other)?;
+    let icon = icon.map(app_icon).unwrap_or_default();
+    let hints = HashMap::from([("urgency", Value::U8(urgency as u8))]);
+    let actions: Vec<&str> = vec![];
+    let reply = connection.call_method(
+        Some("org.freedesktop.Notifications"), "/org/freedesktop/Notifications",
+        Some("org.freedesktop.Notifications"), "Notify",
+        // App name, id to replace, icon, summary, body, actions, hints and the default timeout.
+        &("xdg-desktop", 0u32, icon.as_str(), summary, body, actions, hints, -1i32),
+    ).map_err(io::Error::other)?;
+
+    reply.body().deserialize::<u32>().map_err(io::Error::other)
+}
@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+use std::io::{self, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::process::Child;
+
+use log::warn;
+
+use crate::menu::{MenuIndex, MenuItemDetailEntry};
+use crate::mime_db::{inode_type, MimeDatabase};
+use crate::mime_type::MimeType;
+use crate::scope;
+use crate::terminal;
+use crate::uri::{uri_to_path, url_scheme};
+use crate::usage;
+
+// One application and the command lines that open the requested files or URL with it.
+#[derive(Clone, Debug)]
+pub struct Launch {
+    // Index into MenuIndex::items.
+    pub item: usize,
+    // e.g. "firefox.desktop".
+    pub desktop_id: String,
+    pub commands: Vec<String>,
+    // The files the commands open, if any.
+    pub paths: Vec<PathBuf>,
+    // PrefersNonDefaultGPU, or KDE's X-KDE-RunOnDiscreteGpu.
+    pub prefers_non_default_gpu: bool,
+}
+
+// How Launch::spawn_with starts the application.
+#[derive(Clone, Debug, Default)]
+pub struct LaunchOptions {
+    // Whether to run on the discrete GPU of a hybrid graphics system. None follows the
+    // desktop file.
+    pub discrete_gpu: Option<bool>,
+    // Count the launch in the usage store, for frecency ranking.
+    pub record_usage: bool,
+}
+
+// The variables that offload rendering to the discrete GPU: NVIDIA's PRIME render offload
+// when its driver is loaded, else Mesa's DRI_PRIME.
+fn discrete_gpu_env() -> Vec<(&'static str, &'static str)> {
+    if Path::new("/proc/driver/nvidia").exists() {
+        vec![("__NV_PRIME_RENDER_OFFLOAD", "1"), ("__GLX_VENDOR_LIBRARY_NAME", "nvidia"), ("__VK_LAYER_NV_optimus", "NVIDIA_only")]
+    } else {
+        vec![("DRI_PRIME", "1")]
+    }
+}
+
+impl Launch {
+    pub fn spawn(&self) -> io::Result<Vec<Child>> {
+        self.spawn_with(&LaunchOptions::default())
+    }
+
+    pub fn spawn_with(&self, options: &LaunchOptions) -> io::Result<Vec<Child>> {
+        let env = match options.discrete_gpu.unwrap_or(self.prefers_non_default_gpu) {
+            true => discrete_gpu_env(),
+            false => vec![],
+        };
+        let children = self.commands.iter()
+            .map(|cmd| scope::shell_command(&self.desktop_id, cmd).envs(env.iter().copied()).spawn())
+            .collect::<io::Result<Vec<Child>>>()?;
+        if options.record_usage {
+            if let Err(err) = usage::record_launch(&self.desktop_id) {
+                warn!("Cannot record the launch of {}: {}", self.desktop_id, err);
+            }
+        }
+
+        Ok(children)
+    }
+}
+
+// Picks applications for files and URLs the way xdg-open does, from a scanned MenuIndex.
+pub struct Opener {
+    pub index: MenuIndex,
+}
+
+impl Default for Opener {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Opener {
+    pub fn new() -> Self {
+        let mut index = MenuIndex::new_default();
+        index.scan();
+
+        Self { index }
+    }
+
+    pub fn with_index(index: MenuIndex) -> Self {
+        Self { index }
+    }
+
+    // The type `path` is opened as. Directories and other special files get their inode/*
+    // type even without a MIME database.
+    pub fn mime_type(path: &Path) -> Option<String> {
+        match MimeDatabase::shared() {
+            Some(db) => Some(db.guess(path).mime),
+            None => inode_type(path).map(str::to_string),
+        }
+    }
+
+    // The default application for `mime`, or else the first one that can open it. Types
+    // no application takes fall back to their parent types, e.g. text/plain for text/x-csrc.
+    pub fn default_app(&self, mime: &str) -> Option<usize> {
+        let mut types = vec![mime];
+        if let Some(db) = MimeDatabase::shared() {
+            types.extend(db.ancestors(mime));
+        }
+
+        types.into_iter().find_map(|mime| {
+            let assoc = self.index.mime_assoc_index.get(&MimeType::parse(mime)?)?;
+            assoc.default.or_else(|| assoc.all.first().copied())
+        })
+    }
+
+    // Opening `paths` with a chosen application, or starting it when there are none.
+    pub fn launch_with(&self, item: usize, paths: &[PathBuf]) -> Option<Launch> {
+        let app = self.index.items.get(item)?;
+        let entry = app.detail_entry()?;
+        let commands = match paths.is_empty() {
+            true => vec![entry.exec_without_args()],
+            false => entry.exec_with_filenames(&paths.iter().collect()),
+        };
+
+        Some(Launch {
+            item,
+            desktop_id: app.basename.clone() + ".desktop",
+            commands: in_terminal(entry, commands),
+            paths: paths.to_vec(),
+            prefers_non_default_gpu: entry.prefers_non_default_gpu,
+        })
+    }
+
+    // The default applications for `paths`, each with the paths it takes. Fails if any path
+    // has no type or no application.
+    pub fn plan(&self, paths: &[PathBuf]) -> io::Result<Vec<Launch>> {
+        let mut by_app: BTreeMap<usize, Vec<PathBuf>> = BTreeMap::new();
+        for path in paths {
+            let mime = Self::mime_type(path)
+                .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("Cannot find MIME type for {}", path.display())))?;
+            let item = self.default_app(&mime)
+                .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("Cannot find any associated app for {}", mime)))?;
+            by_app.entry(item).or_default().push(path.clone());
+        }
+
+        Ok(by_app.iter().filter_map(|(item, paths)| self.launch_with(*item, paths)).collect())
+    }
+
+    // file: URLs open the file, others go to the x-scheme-handler/<scheme> application.
+    pub fn plan_url(&self, url: &str) -> io::Result<Launch> {
+        if let Some(path) = uri_to_path(url) {
+            let launch = self.plan(&[path])?.pop();
+            return launch.ok_or_else(|| Error::new(ErrorKind::NotFound, format!("Cannot open {}", url)));
+        }
+        let scheme = url_scheme(url).ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("{} is not a URL", url)))?;
+        let mime = format!("x-scheme-handler/{}", scheme);
+        let item = self.default_app(&mime)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("Cannot find any associated app for {}", mime)))?;
+        let entry = self.index.items[item].detail_entry()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("Cannot open {}", url)))?;
+        let desktop_id = self.index.items[item].basename.clone() + ".desktop";
+
+        Ok(Launch {
+            item,
+            desktop_id,
+            commands: in_terminal(entry, vec![entry.exec_with_url(url)]),
+            paths: vec![],
+            prefers_non_default_gpu: entry.prefers_non_default_gpu,
+        })
+    }
+}
+
+// Terminal=true applications run inside the default terminal, when one is found.
+fn in_terminal(entry: &MenuItemDetailEntry, commands: Vec<String>) -> Vec<String> {
+    match entry.is_terminal.then(terminal::default).flatten() {
+        Some(terminal) => commands.iter().map(|cmd| terminal.wrap(cmd)).collect(),
+        None => commands,
+    }
+}
+
+pub fn open_paths(paths: &[PathBuf]) -> io::Result<Vec<Child>> {
+    let mut children = vec![];
+    for launch in Opener::new().plan(paths)? {
+        children.extend(launch.spawn()?);
+    }
+
+    Ok(children)
+}
+
+pub fn open_path(path: &Path) -> io::Result<Vec<Child>> {
+    open_paths(&[path.to_path_buf()])
+}
+
+pub fn open_url(url: &str) -> io::Result<Vec<Child>> {
+    Opener::new().plan_url(url)?.spawn()
+}
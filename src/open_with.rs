@@ -0,0 +1,199 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use crate::desktop_parser::{DesktopFile, DesktopParserCallback};
+use crate::menu::MenuIndex;
+use crate::mime_db::MimeDatabase;
+
+// An application an Open With dialog can offer for a type, with why it qualifies.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Candidate {
+    // e.g. "org.gnome.TextEditor.desktop".
+    pub desktop_id: String,
+    // Index into MenuIndex::items.
+    pub item: usize,
+    // In Default Applications of a mimeapps.list in $XDG_CONFIG_HOME.
+    pub user_default: bool,
+    // In Default Applications of a system or desktop-wide mimeapps.list.
+    pub desktop_default: bool,
+    // In Added Associations.
+    pub added: bool,
+    // In a mimeinfo.cache.
+    pub cached: bool,
+    // The desktop file lists the type in MimeType. Not told apart for added ones.
+    pub declared: bool,
+    // Set when it only qualifies through a type this one inherits from, e.g. text/plain for
+    // text/x-csrc.
+    pub parent_type: Option<String>,
+}
+
+// The entries of a mimeapps.list or mimeinfo.cache: (group, type, desktop ids).
+type ListEntries = Vec<(String, String, Vec<String>)>;
+
+#[derive(Default)]
+struct ListParser {
+    group: String,
+    key: String,
+    entries: ListEntries,
+}
+
+impl DesktopParserCallback for ListParser {
+    fn on_section(&mut self, name: &[u8]) -> bool {
+        self.group = String::from_utf8_lossy(name).into_owned();
+
+        true
+    }
+    fn on_key(&mut self, key: &[u8]) -> bool {
+        self.key = String::from_utf8_lossy(key).trim().to_string();
+
+        true
+    }
+    fn on_value(&mut self, value: &[u8]) -> bool {
+        let ids = String::from_utf8_lossy(value).split(';').map(str::trim).filter(|id| !id.is_empty()).map(String::from).collect();
+        self.entries.push((self.group.clone(), self.key.clone(), ids));
+
+        true
+    }
+}
+
+fn read_list(path: &Path) -> ListEntries {
+    let mut parser = ListParser::default();
+    if let Some(file) = File::open(path).ok().and_then(|file| DesktopFile::new(file).ok()) {
+        file.parse(&mut parser);
+    }
+
+    parser.entries
+}
+
+// Why a candidate qualifies, best first.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Reason {
+    UserDefault,
+    DesktopDefault,
+    Added,
+    Declared,
+    Cached,
+}
+
+struct Ranked {
+    candidate: Candidate,
+    // (parent depth, reason, order seen), the best of every way it qualifies.
+    rank: (usize, Reason, usize),
+}
+
+struct Collector<'a> {
+    index: &'a MenuIndex,
+    ranked: Vec<Ranked>,
+    by_id: HashMap<String, usize>,
+    seen: usize,
+}
+
+impl Collector<'_> {
+    fn add(&mut self, desktop_id: &str, depth: usize, mime: &str, reason: Reason) {
+        let Some(item) = self.index.find_desktop_file(desktop_id) else {
+            return;
+        };
+        self.seen += 1;
+        let rank = (depth, reason, self.seen);
+        let pos = *self.by_id.entry(desktop_id.to_string()).or_insert_with(|| {
+            self.ranked.push(Ranked {
+                candidate: Candidate {
+                    desktop_id: desktop_id.to_string(), item, user_default: false, desktop_default: false,
+                    added: false, cached: false, declared: false, parent_type: (depth > 0).then(|| mime.to_string()),
+                },
+                rank,
+            });
+            self.ranked.len() - 1
+        });
+        // The flags tell why it qualifies for the nearest type only.
+        let ranked = &mut self.ranked[pos];
+        if ranked.rank.0 < depth {
+            return;
+        }
+        ranked.rank = ranked.rank.min(rank);
+        let candidate = &mut ranked.candidate;
+        match reason {
+            Reason::UserDefault => candidate.user_default = true,
+            Reason::DesktopDefault => candidate.desktop_default = true,
+            Reason::Added => candidate.added = true,
+            Reason::Declared => candidate.declared = true,
+            Reason::Cached => candidate.cached = true,
+        }
+    }
+}
+
+// The applications that can open `mime`, from the defaults, Added Associations, mimeinfo.cache
+// files and MimeType keys for it and then for the types it inherits from, each once. Defaults
+// come first, then added, declared and cached ones, in the precedence of their files.
+// Removed Associations drop the additions and declarations of lower precedence.
+pub fn candidates_for(index: &MenuIndex, mime: &str) -> Vec<Candidate> {
+    let base_dirs = index.base_dirs();
+    let mut types = vec![mime.to_string()];
+    if let Some(db) = MimeDatabase::shared() {
+        types.extend(db.ancestors(mime).into_iter().map(String::from));
+    }
+
+    // Every mimeapps.list, highest precedence first, and whether it is the user's.
+    let mut names: Vec<String> = index.mimeapps_names().to_vec();
+    names.push(String::from("mimeapps.list"));
+    let mut lists: Vec<(PathBuf, bool)> = vec![];
+    for dir in base_dirs.config_dirs_in_precedence() {
+        let is_user = dir == base_dirs.config_home();
+        lists.extend(names.iter().map(|name| (dir.join(name), is_user)));
+    }
+    for dir in base_dirs.data_dirs_in_precedence() {
+        lists.extend(names.iter().map(|name| (dir.join("applications").join(name), false)));
+    }
+    let lists: Vec<(ListEntries, bool)> = lists.iter().map(|(path, is_user)| (read_list(path), *is_user)).collect();
+    let caches: Vec<_> = base_dirs.data_dirs_in_precedence().iter().map(|dir| read_list(&dir.join("applications/mimeinfo.cache"))).collect();
+
+    let mut collector = Collector { index, ranked: vec![], by_id: HashMap::new(), seen: 0 };
+    for (depth, mime) in types.iter().enumerate() {
+        let mut removed: HashSet<&str> = HashSet::new();
+        for (entries, is_user) in &lists {
+            let for_type = entries.iter().filter(|(_, key, _)| key == mime);
+            for (group, _, ids) in for_type.clone() {
+                let reason = match group.as_str() {
+                    "Default Applications" if *is_user => Reason::UserDefault,
+                    "Default Applications" => Reason::DesktopDefault,
+                    "Added Associations" => Reason::Added,
+                    _ => continue,
+                };
+                for id in ids.iter().filter(|id| reason != Reason::Added || !removed.contains(id.as_str())) {
+                    collector.add(id, depth, mime, reason);
+                }
+            }
+            for (_, _, ids) in for_type.filter(|(group, _, _)| group == "Removed Associations") {
+                removed.extend(ids.iter().map(String::as_str));
+            }
+        }
+
+        // MenuIndex has the added ones along with the declared ones.
+        let all = index.mime_assoc_index.get(mime.as_str()).map(|assoc| assoc.all.clone()).unwrap_or_default();
+        for item in all {
+            let id = index.items[item].basename.clone() + ".desktop";
+            let is_added = collector.by_id.get(&id).is_some_and(|pos| collector.ranked[*pos].candidate.added);
+            if !removed.contains(id.as_str()) && !is_added {
+                collector.add(&id, depth, mime, Reason::Declared);
+            }
+        }
+        for (_, _, ids) in caches.iter().flatten().filter(|(group, key, _)| group == "MIME Cache" && key == mime) {
+            for id in ids.iter().filter(|id| !removed.contains(id.as_str())) {
+                collector.add(id, depth, mime, Reason::Cached);
+            }
+        }
+    }
+
+    let mut ranked = collector.ranked;
+    ranked.sort_by_key(|ranked| ranked.rank);
+
+    ranked.into_iter().map(|ranked| ranked.candidate).collect()
+}
+
+pub fn open_with_candidates(mime: &str) -> Vec<Candidate> {
+    let mut index = MenuIndex::new_default();
+    index.scan();
+
+    candidates_for(&index, mime)
+}
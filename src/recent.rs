@@ -0,0 +1,229 @@
+use std::fs;
+use std::io::{ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use roxmltree::Node;
+
+use crate::dirs;
+use crate::mime_info::{escape_xml, parse_document};
+use crate::uri::{path_to_uri, uri_to_path};
+
+const BOOKMARK_NS: &str = "http://www.freedesktop.org/standards/desktop-bookmarks";
+const MIME_NS: &str = "http://www.freedesktop.org/standards/shared-mime-info";
+
+// How many entries save() keeps, the most recently modified first.
+pub const DEFAULT_MAX_ENTRIES: usize = 500;
+
+// An application that opened a recent file.
+#[derive(Clone, Debug, Default)]
+pub struct RecentApplication {
+    pub name: String,
+    // The command line it was opened with, e.g. "'gedit %u'".
+    pub exec: String,
+    pub modified: String,
+    pub count: usize,
+}
+
+// A <bookmark> of recently-used.xbel. Times are ISO 8601 in UTC.
+#[derive(Clone, Debug, Default)]
+pub struct RecentEntry {
+    pub href: String,
+    pub title: Option<String>,
+    pub added: String,
+    pub modified: String,
+    pub visited: String,
+    pub mime_type: String,
+    pub groups: Vec<String>,
+    pub applications: Vec<RecentApplication>,
+    // Only to be shown to the applications and groups listed.
+    pub private: bool,
+}
+
+impl RecentEntry {
+    // The local file the entry refers to, if any.
+    pub fn path(&self) -> Option<PathBuf> {
+        uri_to_path(&self.href)
+    }
+}
+
+// (year, month, day) of the UTC date `days` after 1970-01-01, by Howard Hinnant's
+// civil_from_days.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    (year, month, day)
+}
+
+// The current time as recently-used.xbel writes it: "2024-01-31T10:00:00.000000Z".
+fn now() -> String {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days(secs / 86400);
+    let time = secs % 86400;
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z", year, month, day,
+            time / 3600, time / 60 % 60, time % 60, since_epoch.subsec_micros())
+}
+
+fn parse_metadata(node: Node, entry: &mut RecentEntry) {
+    for child in node.children().filter(Node::is_element) {
+        let tag = child.tag_name();
+        match (tag.namespace(), tag.name()) {
+            (Some(MIME_NS), "mime-type") => entry.mime_type = child.attribute("type").unwrap_or("").to_string(),
+            (Some(BOOKMARK_NS), "groups") => entry.groups = child.children().filter(Node::is_element)
+                .filter_map(|group| group.text()).map(|text| text.trim().to_string()).collect(),
+            (Some(BOOKMARK_NS), "applications") => entry.applications = child.children().filter(Node::is_element)
+                .map(|app| RecentApplication {
+                    name: app.attribute("name").unwrap_or("").to_string(),
+                    exec: app.attribute("exec").unwrap_or("").to_string(),
+                    modified: app.attribute("modified").unwrap_or("").to_string(),
+                    count: app.attribute("count").and_then(|count| count.parse().ok()).unwrap_or(1),
+                }).collect(),
+            (Some(BOOKMARK_NS), "private") => entry.private = true,
+            _ => {}
+        }
+    }
+}
+
+fn parse_bookmark(node: Node) -> Option<RecentEntry> {
+    let mut entry = RecentEntry {
+        href: node.attribute("href")?.to_string(),
+        added: node.attribute("added").unwrap_or("").to_string(),
+        modified: node.attribute("modified").unwrap_or("").to_string(),
+        visited: node.attribute("visited").unwrap_or("").to_string(),
+        ..RecentEntry::default()
+    };
+    for child in node.children().filter(Node::is_element) {
+        match child.tag_name().name() {
+            "title" => entry.title = child.text().map(str::to_string),
+            "info" => {
+                for metadata in child.children().filter(|node| node.has_tag_name("metadata")) {
+                    parse_metadata(metadata, &mut entry);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Some(entry)
+}
+
+// The recently used files list, $XDG_DATA_HOME/recently-used.xbel.
+pub struct RecentFiles {
+    pub entries: Vec<RecentEntry>,
+    pub max_entries: usize,
+    path: PathBuf,
+}
+
+impl RecentFiles {
+    pub fn load() -> Result<Self> {
+        Self::open(&dirs::BaseDirs::new().data_home().join("recently-used.xbel"))
+    }
+
+    // A missing file reads as an empty list.
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut recent = Self { entries: vec![], max_entries: DEFAULT_MAX_ENTRIES, path: path.to_path_buf() };
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(recent),
+            Err(err) => return Err(err),
+        };
+        let doc = parse_document(&text)?;
+        recent.entries = doc.root_element().children().filter(|node| node.has_tag_name("bookmark")).filter_map(parse_bookmark).collect();
+
+        Ok(recent)
+    }
+
+    // Records that `app` opened `path` by running `exec`, bumping the entry if it is there.
+    pub fn record(&mut self, path: &Path, mime_type: &str, app: &str, exec: &str) {
+        let href = path_to_uri(path);
+        let now = now();
+        let index = match self.entries.iter().position(|entry| entry.href == href) {
+            Some(index) => index,
+            None => {
+                self.entries.push(RecentEntry { href, added: now.clone(), ..RecentEntry::default() });
+                self.entries.len() - 1
+            }
+        };
+        let entry = &mut self.entries[index];
+        entry.modified = now.clone();
+        entry.visited = now.clone();
+        entry.mime_type = mime_type.to_string();
+        match entry.applications.iter_mut().find(|known| known.name == app) {
+            Some(known) => {
+                known.exec = exec.to_string();
+                known.modified = now;
+                known.count += 1;
+            }
+            None => entry.applications.push(RecentApplication { name: app.to_string(), exec: exec.to_string(), modified: now, count: 1 }),
+        }
+    }
+
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml += &format!("<xbel version=\"1.0\"\n      xmlns:bookmark=\"{}\"\n      xmlns:mime=\"{}\"\n>\n", BOOKMARK_NS, MIME_NS);
+        for entry in &self.entries {
+            xml += &format!("  <bookmark href=\"{}\" added=\"{}\" modified=\"{}\" visited=\"{}\">\n",
+                            escape_xml(&entry.href), escape_xml(&entry.added), escape_xml(&entry.modified), escape_xml(&entry.visited));
+            if let Some(title) = &entry.title {
+                xml += &format!("    <title>{}</title>\n", escape_xml(title));
+            }
+            xml += "    <info>\n      <metadata owner=\"http://freedesktop.org\">\n";
+            xml += &format!("        <mime:mime-type type=\"{}\"/>\n", escape_xml(&entry.mime_type));
+            if !entry.groups.is_empty() {
+                xml += "        <bookmark:groups>\n";
+                for group in &entry.groups {
+                    xml += &format!("          <bookmark:group>{}</bookmark:group>\n", escape_xml(group));
+                }
+                xml += "        </bookmark:groups>\n";
+            }
+            xml += "        <bookmark:applications>\n";
+            for app in &entry.applications {
+                xml += &format!("          <bookmark:application name=\"{}\" exec=\"{}\" modified=\"{}\" count=\"{}\"/>\n",
+                                escape_xml(&app.name), escape_xml(&app.exec), escape_xml(&app.modified), app.count);
+            }
+            xml += "        </bookmark:applications>\n";
+            if entry.private {
+                xml += "        <bookmark:private/>\n";
+            }
+            xml += "      </metadata>\n    </info>\n  </bookmark>\n";
+        }
+        xml += "</xbel>\n";
+
+        xml
+    }
+
+    // Keeps the max_entries most recently modified entries, then replaces the file by a
+    // rename so other readers never see a partial list.
+    pub fn save(&mut self) -> Result<()> {
+        self.entries.sort_by(|a, b| b.modified.cmp(&a.modified));
+        self.entries.truncate(self.max_entries);
+
+        if let Some(parent) = self.path.parent() {
+            dirs::create_private_dir(parent)?;
+        }
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(format!(".{}.tmp", std::process::id()));
+        fs::write(&tmp, self.to_xml())?;
+        fs::rename(&tmp, &self.path).inspect_err(|_| {
+            let _ = fs::remove_file(&tmp);
+        })
+    }
+}
+
+// Loads the list, records `path` and saves it again.
+pub fn add(path: &Path, mime_type: &str, app: &str, exec: &str) -> Result<()> {
+    let mut recent = RecentFiles::load()?;
+    recent.record(path, mime_type, app, exec);
+
+    recent.save()
+}
@@ -0,0 +1,141 @@
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use crate::menu::MenuItem;
+
+fn basename(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+// Programs that run a script named by their first other argument, e.g. "python3 foo.py".
+const INTERPRETERS: [&str; 10] = ["python", "perl", "ruby", "node", "java", "mono", "sh", "bash", "lua", "gjs"];
+
+// Versioned names such as python3.12 count too.
+fn is_interpreter(name: &str) -> bool {
+    INTERPRETERS.contains(&name.trim_end_matches(|ch: char| ch.is_ascii_digit() || ch == '.'))
+}
+
+// The file name of the program in `words`, a command line past any "env NAME=VALUE"
+// wrappers, or of the script it runs when that is an interpreter. Options of the
+// interpreter are skipped.
+fn program_of<'a>(mut words: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let program = basename(words.next()?);
+    if !is_interpreter(program) {
+        return Some(program);
+    }
+
+    Some(words.find(|word| !word.starts_with('-')).map_or(program, basename))
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn window_id(id: &str) -> Option<u64> {
+    u64::from_str_radix(id.strip_prefix("0x")?, 16).ok()
+}
+
+// The WM_CLASS instance and class names of the windows in the EWMH client list, in lower
+// case. Empty outside of X11 or when xprop or xwininfo is missing.
+fn client_wm_classes() -> HashSet<String> {
+    let mut classes = HashSet::new();
+    if env::var_os("DISPLAY").is_none() {
+        return classes;
+    }
+    // "_NET_CLIENT_LIST(WINDOW): window id # 0x1a00003, 0x2c00007"
+    let Some(list) = command_output("xprop", &["-root", "_NET_CLIENT_LIST"]) else {
+        return classes;
+    };
+    let clients: HashSet<u64> = list.split_once('#').map_or("", |(_, ids)| ids).split(',').filter_map(|id| window_id(id.trim())).collect();
+    // One call for the classes of every window:
+    // "     0x1a00003 "Title": ("navigator" "firefox")  1280x720+0+0  +0+0"
+    let Some(tree) = command_output("xwininfo", &["-root", "-tree"]) else {
+        return classes;
+    };
+    for line in tree.lines().map(str::trim_start) {
+        if !line.split_whitespace().next().and_then(window_id).is_some_and(|id| clients.contains(&id)) {
+            continue;
+        }
+        // The title comes first and may hold anything, so the class list is the last one.
+        let Some(names) = line.rsplit_once("(\"").and_then(|(_, rest)| rest.split_once(')')).map(|(names, _)| names) else {
+            continue;
+        };
+        classes.extend(names.split('"').step_by(2).map(|name| name.trim().to_lowercase()).filter(|name| !name.is_empty()));
+    }
+
+    classes
+}
+
+// The names the running processes go by: the file names of their executables and of the
+// programs of their command lines, which for interpreters are the scripts they run.
+fn process_names() -> HashSet<String> {
+    let mut names = HashSet::new();
+    let Ok(read_dir) = Path::new("/proc").read_dir() else {
+        return names;
+    };
+    let pids = read_dir.flatten().map(|entry| entry.path()).filter(|path| {
+        path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.bytes().all(|ch| ch.is_ascii_digit()))
+    });
+    for pid in pids {
+        if let Some(exe) = fs::read_link(pid.join("exe")).ok().and_then(|exe| exe.file_name().map(|name| name.to_string_lossy().into_owned())) {
+            names.insert(exe);
+        }
+        let Ok(cmdline) = fs::read(pid.join("cmdline")) else {
+            continue;
+        };
+        let cmdline = String::from_utf8_lossy(&cmdline);
+        if let Some(program) = program_of(cmdline.split('\0').filter(|word| !word.is_empty())) {
+            names.insert(program.to_string());
+        }
+    }
+
+    names
+}
+
+// The file name of the program an Exec value runs, past "env NAME=VALUE" wrappers, or of
+// the script for interpreters.
+fn exec_program(exec: &str) -> Option<&str> {
+    let words = exec.split_whitespace().skip_while(|word| *word == "env" || word.contains('='));
+
+    program_of(words.map(|word| word.trim_matches(['"', '\''])))
+}
+
+// The windows and processes at one point in time, to tell which menu items are running.
+// Taking one is not cheap, so docks should reuse it across items.
+#[derive(Clone, Debug, Default)]
+pub struct RunningApps {
+    // Lower case.
+    pub wm_classes: HashSet<String>,
+    pub process_names: HashSet<String>,
+}
+
+impl RunningApps {
+    pub fn snapshot() -> Self {
+        RunningApps { wm_classes: client_wm_classes(), process_names: process_names() }
+    }
+
+    // Whether a window has the StartupWMClass (or guessed class) or the Flatpak app id of
+    // the item, or else a process runs the program of its Exec.
+    pub fn is_running(&self, item: &MenuItem) -> bool {
+        let Some(entry) = item.detail_entry() else {
+            return false;
+        };
+        let classes = [Some(entry.wmclass.as_str()), item.flatpak_id.as_deref()];
+        if classes.into_iter().flatten().any(|class| !class.is_empty() && self.wm_classes.contains(&class.to_lowercase())) {
+            return true;
+        }
+        // Flatpak and snap commands are launchers; the app runs under another name.
+        let program = exec_program(&entry.exec).filter(|program| !["flatpak", "snap"].contains(program) && !program.ends_with(".AppImage"));
+
+        program.is_some_and(|program| self.process_names.contains(program))
+    }
+}
+
+pub fn is_running(item: &MenuItem) -> bool {
+    RunningApps::snapshot().is_running(item)
+}
@@ -0,0 +1,52 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::path::Path;
+use std::process::Command;
+
+use crate::autostart::can_exec;
+
+// Escapes `text` for use in a unit name as systemd-escape does. '-' is escaped too, as it
+// separates the parts of the name.
+fn escape_unit_part(text: &str) -> String {
+    let mut escaped = String::new();
+    for (i, byte) in text.bytes().enumerate() {
+        if byte.is_ascii_alphanumeric() || byte == b'_' || byte == b':' || (byte == b'.' && i > 0) {
+            escaped.push(byte as char);
+        } else {
+            escaped += &format!("\\x{:02x}", byte);
+        }
+    }
+
+    escaped
+}
+
+// "app-org.gnome.Terminal-1a2b3c4d5e6f7a8b.scope" for "org.gnome.Terminal.desktop", the
+// name the systemd desktop application guidelines give a launched app.
+pub fn unit_name(desktop_id: &str) -> String {
+    let app_id = desktop_id.strip_suffix(".desktop").unwrap_or(desktop_id);
+    let random = RandomState::new().build_hasher().finish();
+
+    format!("app-{}-{:016x}.scope", escape_unit_part(app_id), random)
+}
+
+// Whether the session runs a systemd user manager to put scopes in.
+pub fn available() -> bool {
+    Path::new("/run/systemd/system").is_dir() && can_exec("systemd-run")
+}
+
+// A command that runs the shell command line `command_line` for the app `desktop_id`. With
+// the systemd feature, and systemd running, it runs in its own transient scope under
+// app.slice, so it gets its own cgroup instead of sharing the launcher's.
+pub fn shell_command(desktop_id: &str, command_line: &str) -> Command {
+    if cfg!(feature = "systemd") && available() {
+        let mut cmd = Command::new("systemd-run");
+        cmd.args(["--user", "--scope", "--quiet", "--collect", "--slice=app.slice"])
+            .arg(format!("--unit={}", unit_name(desktop_id)))
+            .args(["--", "/bin/sh", "-c", command_line]);
+        return cmd;
+    }
+    let mut cmd = Command::new("/bin/sh");
+    cmd.arg("-c").arg(command_line);
+
+    cmd
+}
@@ -0,0 +1,78 @@
+use crate::dirs::{EnvProvider, StdEnv};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SessionType {
+    X11,
+    Wayland,
+    Tty,
+    // Values such as "mir" or "unspecified".
+    Other(String),
+}
+
+// The desktop session this process runs in, as described by the XDG_* session variables.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Session {
+    // $XDG_CURRENT_DESKTOP, e.g. ["ubuntu", "GNOME"], most specific first.
+    pub current_desktops: Vec<String>,
+    pub session_type: Option<SessionType>,
+    // $XDG_SESSION_DESKTOP, the name of the session, e.g. "plasma".
+    pub session_desktop: Option<String>,
+}
+
+impl Session {
+    pub fn from_env(env: impl EnvProvider) -> Self {
+        let current_desktops = env.var("XDG_CURRENT_DESKTOP").unwrap_or_default()
+            .split(':').filter(|s| !s.is_empty()).map(str::to_string).collect();
+        // Without XDG_SESSION_TYPE, e.g. outside of a logind session, guess from the display
+        // variables.
+        let session_type = match env.var("XDG_SESSION_TYPE").filter(|s| !s.is_empty()) {
+            Some(name) => Some(match name.to_ascii_lowercase().as_str() {
+                "x11" => SessionType::X11,
+                "wayland" => SessionType::Wayland,
+                "tty" => SessionType::Tty,
+                _ => SessionType::Other(name),
+            }),
+            None if env.var("WAYLAND_DISPLAY").is_some_and(|s| !s.is_empty()) => Some(SessionType::Wayland),
+            None if env.var("DISPLAY").is_some_and(|s| !s.is_empty()) => Some(SessionType::X11),
+            None => None,
+        };
+        let session_desktop = env.var("XDG_SESSION_DESKTOP").filter(|s| !s.is_empty());
+
+        Self { current_desktops, session_type, session_desktop }
+    }
+
+    // Whether `name` is one of the current desktops, as OnlyShowIn and NotShowIn match them.
+    pub fn is_desktop(&self, name: &str) -> bool {
+        self.current_desktops.iter().any(|desktop| desktop == name)
+    }
+
+    pub fn is_gnome(&self) -> bool {
+        self.is_desktop("GNOME")
+    }
+
+    pub fn is_kde(&self) -> bool {
+        self.is_desktop("KDE")
+    }
+
+    pub fn is_xfce(&self) -> bool {
+        self.is_desktop("XFCE")
+    }
+
+    pub fn is_wayland(&self) -> bool {
+        self.session_type == Some(SessionType::Wayland)
+    }
+
+    pub fn is_x11(&self) -> bool {
+        self.session_type == Some(SessionType::X11)
+    }
+
+    // The desktop-specific mimeapps.list names, e.g. "gnome-mimeapps.list", highest
+    // precedence first.
+    pub fn mimeapps_names(&self) -> Vec<String> {
+        self.current_desktops.iter().map(|desktop| format!("{}-mimeapps.list", desktop.to_lowercase())).collect()
+    }
+}
+
+pub fn current() -> Session {
+    Session::from_env(StdEnv)
+}
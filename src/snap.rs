@@ -0,0 +1,65 @@
+use std::path::{Path, PathBuf};
+
+// Where snapd puts the wrappers that start snap apps.
+pub const SNAP_BIN_DIR: &str = "/snap/bin";
+
+// What the Exec line of a desktop file exported by snapd runs, e.g. "env
+// BAMF_DESKTOP_FILE_HINT=/var/lib/snapd/desktop/applications/firefox_firefox.desktop
+// /snap/bin/firefox %u".
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SnapExport {
+    // From X-SnapInstanceName, else the wrapper name up to the first '.', e.g. "firefox" or
+    // "firefox_beta" for a parallel install.
+    pub instance_name: String,
+    // The wrapper in SNAP_BIN_DIR, e.g. "firefox" or "snapcraft.app" for the app of another name.
+    pub command: String,
+    // The NAME=VALUE pairs set through env.
+    pub env: Vec<String>,
+    // What follows the wrapper, field codes included.
+    pub args: Vec<String>,
+}
+
+impl SnapExport {
+    // None unless `exec` runs a wrapper in SNAP_BIN_DIR.
+    pub fn from_exec(instance_name: Option<&str>, exec: &str) -> Option<Self> {
+        let mut words = exec.split_whitespace();
+        let mut env = vec![];
+        let command = loop {
+            let word = words.next()?;
+            if let Some(command) = Path::new(word).strip_prefix(SNAP_BIN_DIR).ok().and_then(Path::to_str).filter(|command| !command.is_empty()) {
+                break command;
+            } else if word.contains('=') {
+                env.push(word.to_string());
+            } else if word != "env" {
+                return None;
+            }
+        };
+        let instance_name = instance_name.unwrap_or_else(|| command.split('.').next().unwrap_or(command));
+
+        Some(SnapExport {
+            instance_name: instance_name.to_string(),
+            command: command.to_string(),
+            env,
+            args: words.map(String::from).collect(),
+        })
+    }
+
+    pub fn wrapper(&self) -> PathBuf {
+        Path::new(SNAP_BIN_DIR).join(&self.command)
+    }
+
+    // An Exec value running the wrapper. BAMF_DESKTOP_FILE_HINT, which snapd sets for its
+    // own launches so the app is matched to its desktop file, points at `desktop_file` if
+    // the Exec line did not set it.
+    pub fn exec(&self, desktop_file: &Path) -> String {
+        let mut words = vec![String::from("env")];
+        words.extend(self.env.iter().cloned());
+        if !self.env.iter().any(|pair| pair.starts_with("BAMF_DESKTOP_FILE_HINT=")) {
+            words.push(format!("BAMF_DESKTOP_FILE_HINT={}", desktop_file.display()));
+        }
+        words.push(self.wrapper().display().to_string());
+        words.extend(self.args.iter().cloned());
+
+        words.join(" ")
+    }
+}
@@ -0,0 +1,167 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use crate::autostart::{can_exec, strip_field_codes};
+use crate::desktop_parser::{DesktopFile, DesktopParserCallback};
+use crate::dirs::BaseDirs;
+use crate::session::{self, Session};
+
+// The argument most terminals take before the command to run.
+const DEFAULT_EXEC_ARG: &str = "-e";
+
+// A terminal emulator picked as xdg-terminal-exec does.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Terminal {
+    // e.g. "foot.desktop".
+    pub desktop_id: String,
+    pub path: PathBuf,
+    // Exec without field codes.
+    pub exec: String,
+    // What precedes the command, from X-ExecArg or ExecArg. Empty for terminals that take
+    // the command as their remaining arguments.
+    pub exec_arg: String,
+}
+
+impl Terminal {
+    // A shell command line that runs `command`, itself a shell command line, in the
+    // terminal.
+    pub fn wrap(&self, command: &str) -> String {
+        let quoted = format!("'{}'", command.replace('\'', "'\\''"));
+        [self.exec.as_str(), self.exec_arg.as_str(), "sh", "-c", &quoted].iter()
+            .filter(|part| !part.is_empty())
+            .copied()
+            .collect::<Vec<&str>>()
+            .join(" ")
+    }
+}
+
+#[derive(Default)]
+struct TerminalParser {
+    in_entry: bool,
+    key: String,
+    app_type: String,
+    exec: String,
+    try_exec: String,
+    exec_arg: Option<String>,
+    hidden: bool,
+    is_terminal_emulator: bool,
+}
+
+impl DesktopParserCallback for TerminalParser {
+    fn on_section(&mut self, name: &[u8]) -> bool {
+        self.in_entry = name == b"Desktop Entry";
+
+        true
+    }
+    fn on_key(&mut self, key: &[u8]) -> bool {
+        self.key = String::from_utf8_lossy(key).trim().to_string();
+
+        true
+    }
+    fn on_value(&mut self, value: &[u8]) -> bool {
+        if !self.in_entry {
+            return true;
+        }
+        let value = String::from_utf8_lossy(value.trim_ascii()).into_owned();
+        match self.key.as_str() {
+            "Type" => self.app_type = value,
+            "Exec" => self.exec = value,
+            "TryExec" => self.try_exec = value,
+            // X-ExecArg is the name used until the spec is accepted, and wins over ExecArg.
+            "X-ExecArg" => self.exec_arg = Some(value),
+            "ExecArg" => {
+                self.exec_arg.get_or_insert(value);
+            }
+            "Hidden" => self.hidden = value.eq_ignore_ascii_case("true"),
+            "Categories" => self.is_terminal_emulator = value.split(';').any(|category| category == "TerminalEmulator"),
+            _ => {}
+        }
+
+        true
+    }
+}
+
+// A usable terminal from the desktop file at `path`. With `emulators_only`, the entry must
+// also be in the TerminalEmulator category.
+fn read_terminal(desktop_id: &str, path: &Path, emulators_only: bool) -> Option<Terminal> {
+    let mut parser = TerminalParser::default();
+    DesktopFile::new(File::open(path).ok()?).ok()?.parse(&mut parser);
+    if emulators_only && !parser.is_terminal_emulator {
+        return None;
+    }
+    if parser.hidden || parser.app_type != "Application" || parser.exec.is_empty() {
+        return None;
+    }
+    if !parser.try_exec.is_empty() && !can_exec(&parser.try_exec) {
+        return None;
+    }
+
+    Some(Terminal {
+        desktop_id: desktop_id.to_string(),
+        path: path.to_path_buf(),
+        exec: strip_field_codes(&parser.exec),
+        exec_arg: parser.exec_arg.unwrap_or_else(|| String::from(DEFAULT_EXEC_ARG)),
+    })
+}
+
+// The desktop ids listed in the xdg-terminals.list files, most preferred first. Each config
+// dir has a list per current desktop, e.g. gnome-xdg-terminals.list, ahead of the generic
+// one. Entries may name an action after a ':', which is ignored here.
+fn listed_terminals(base_dirs: &BaseDirs, session: &Session) -> Vec<String> {
+    let mut names: Vec<String> = session.current_desktops.iter()
+        .map(|desktop| format!("{}-xdg-terminals.list", desktop.to_lowercase()))
+        .collect();
+    names.push(String::from("xdg-terminals.list"));
+
+    let mut ids = vec![];
+    for dir in base_dirs.config_dirs_in_precedence() {
+        for name in &names {
+            let Ok(content) = fs::read_to_string(dir.join(name)) else {
+                continue;
+            };
+            for line in content.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+                let id = line.split(':').next().unwrap_or(line);
+                if id.ends_with(".desktop") && !ids.iter().any(|known| known == id) {
+                    ids.push(id.to_string());
+                }
+            }
+        }
+    }
+
+    ids
+}
+
+// The preferred terminal: the first usable entry of the xdg-terminals.list files, else the
+// first TerminalEmulator application by desktop id.
+pub fn default_for(base_dirs: &BaseDirs, session: &Session) -> Option<Terminal> {
+    let listed = listed_terminals(base_dirs, session).into_iter().find_map(|id| {
+        let path = base_dirs.find_data_file(Path::new("applications").join(&id))?;
+        read_terminal(&id, &path, false)
+    });
+    if listed.is_some() {
+        return listed;
+    }
+
+    let mut candidates: Vec<(String, PathBuf)> = vec![];
+    for dir in base_dirs.data_dirs_in_precedence() {
+        let Ok(read_dir) = dir.join("applications").read_dir() else {
+            continue;
+        };
+        for entry in read_dir.flatten() {
+            let Ok(id) = entry.file_name().into_string() else {
+                continue;
+            };
+            // Files in higher precedence dirs shadow those of the same id.
+            if id.ends_with(".desktop") && !candidates.iter().any(|(known, _)| *known == id) {
+                candidates.push((id, entry.path()));
+            }
+        }
+    }
+    candidates.sort();
+
+    candidates.iter().find_map(|(id, path)| read_terminal(id, path, true))
+}
+
+pub fn default() -> Option<Terminal> {
+    default_for(&BaseDirs::new(), &session::current())
+}
@@ -0,0 +1,241 @@
+use std::fs::{self, Metadata, OpenOptions};
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+#[cfg(not(unix))]
+use std::time::UNIX_EPOCH;
+
+use crate::dirs;
+use crate::uri::path_to_uri;
+
+// The sizes of the shared thumbnail cache, each a directory under
+// $XDG_CACHE_HOME/thumbnails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ThumbnailSize {
+    Normal,
+    Large,
+    XLarge,
+    XXLarge,
+}
+
+impl ThumbnailSize {
+    pub const ALL: [ThumbnailSize; 4] = [Self::Normal, Self::Large, Self::XLarge, Self::XXLarge];
+
+    // The longest side in pixels.
+    pub fn pixels(self) -> u32 {
+        match self {
+            Self::Normal => 128,
+            Self::Large => 256,
+            Self::XLarge => 512,
+            Self::XXLarge => 1024,
+        }
+    }
+
+    fn dir_name(self) -> &'static str {
+        match self {
+            Self::Normal => "normal",
+            Self::Large => "large",
+            Self::XLarge => "x-large",
+            Self::XXLarge => "xx-large",
+        }
+    }
+}
+
+// A fully transparent 1x1 PNG, the body of fail files.
+const EMPTY_PNG: &[u8] = &[
+    0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52,
+    0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x06, 0x00, 0x00, 0x00, 0x1f, 0x15, 0xc4,
+    0x89, 0x00, 0x00, 0x00, 0x0b, 0x49, 0x44, 0x41, 0x54, 0x78, 0xda, 0x63, 0x60, 0x00, 0x02, 0x00,
+    0x00, 0x05, 0x00, 0x01, 0xe9, 0xfa, 0xdc, 0xd8, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44,
+    0xae, 0x42, 0x60, 0x82,
+];
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+pub fn cache_dir() -> PathBuf {
+    dirs::cache_home().join("thumbnails")
+}
+
+// The file name of every thumbnail of `uri`: the MD5 of the URI in hex.
+pub fn thumbnail_name(uri: &str) -> String {
+    format!("{:x}.png", md5::compute(uri.as_bytes()))
+}
+
+pub fn thumbnail_path(uri: &str, size: ThumbnailSize) -> PathBuf {
+    cache_dir().join(size.dir_name()).join(thumbnail_name(uri))
+}
+
+fn fail_path(uri: &str, app: &str) -> PathBuf {
+    cache_dir().join("fail").join(app).join(thumbnail_name(uri))
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+// The tEXt chunks of a PNG, as (keyword, text) pairs.
+fn png_text(data: &[u8]) -> Vec<(String, String)> {
+    let mut result = vec![];
+    let Some(mut rest) = data.strip_prefix(PNG_SIGNATURE) else {
+        return result;
+    };
+    while rest.len() >= 12 {
+        let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let Some(body) = rest.get(8..8 + len) else {
+            break;
+        };
+        match &rest[4..8] {
+            b"tEXt" => {
+                if let Some(pos) = body.iter().position(|&byte| byte == 0) {
+                    // tEXt is Latin-1.
+                    let decode = |bytes: &[u8]| bytes.iter().map(|&byte| byte as char).collect::<String>();
+                    result.push((decode(&body[..pos]), decode(&body[pos + 1..])));
+                }
+            }
+            b"IDAT" | b"IEND" => break,
+            _ => {}
+        }
+        rest = rest.get(12 + len..).unwrap_or_default();
+    }
+
+    result
+}
+
+// A copy of `png` with tEXt chunks for `text` right after the header.
+fn with_png_text(png: &[u8], text: &[(&str, String)]) -> Option<Vec<u8>> {
+    let header_end = PNG_SIGNATURE.len() + 25;
+    if !png.starts_with(PNG_SIGNATURE) || png.get(12..16) != Some(b"IHDR") || png.len() < header_end {
+        return None;
+    }
+    let mut result = png[..header_end].to_vec();
+    for (key, value) in text {
+        let mut chunk = b"tEXt".to_vec();
+        chunk.extend(key.as_bytes());
+        chunk.push(0);
+        // Non Latin-1 characters cannot be stored; the URI is ASCII by construction.
+        chunk.extend(value.chars().map(|ch| if (ch as u32) < 256 { ch as u8 } else { b'?' }));
+        result.extend(((chunk.len() - 4) as u32).to_be_bytes());
+        result.extend(&chunk);
+        result.extend(crc32(&chunk).to_be_bytes());
+    }
+    result.extend(&png[header_end..]);
+
+    Some(result)
+}
+
+// The modification time Thumb::MTime holds, in seconds since the epoch.
+#[cfg(unix)]
+fn mtime(meta: &Metadata) -> i64 {
+    MetadataExt::mtime(meta)
+}
+
+#[cfg(not(unix))]
+fn mtime(meta: &Metadata) -> i64 {
+    meta.modified().ok().and_then(|time| time.duration_since(UNIX_EPOCH).ok()).map_or(0, |since| since.as_secs() as i64)
+}
+
+// Writes a thumbnail or fail file readable only by the user, replacing any old one by a
+// rename so readers never see a partial file.
+fn write_thumbnail(dest: &Path, png: &[u8]) -> io::Result<()> {
+    dirs::create_private_dir(dest.parent().unwrap_or(Path::new(".")))?;
+    let mut tmp = dest.to_path_buf().into_os_string();
+    tmp.push(format!(".{}.tmp", std::process::id()));
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let written = options.open(&tmp)
+        .and_then(|mut file| file.write_all(png))
+        .and_then(|_| fs::rename(&tmp, dest));
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+
+    written
+}
+
+// Whether the thumbnail at `thumb` was made from `uri` as last modified at `mtime`.
+pub fn is_valid(thumb: &Path, uri: &str, mtime: i64) -> bool {
+    let Ok(data) = fs::read(thumb) else {
+        return false;
+    };
+    let text = png_text(&data);
+    let value = |key: &str| text.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+
+    value("Thumb::URI") == Some(uri) && value("Thumb::MTime").and_then(|m| m.parse::<i64>().ok()) == Some(mtime)
+}
+
+// An up to date thumbnail of `path`, of `size` or else the nearest larger one.
+pub fn find_thumbnail(path: &Path, size: ThumbnailSize) -> Option<PathBuf> {
+    let path = path.canonicalize().ok()?;
+    let mtime = mtime(&fs::metadata(&path).ok()?);
+    let uri = path_to_uri(&path);
+    ThumbnailSize::ALL.into_iter().filter(|candidate| *candidate >= size)
+        .map(|candidate| thumbnail_path(&uri, candidate))
+        .find(|thumb| is_valid(thumb, &uri, mtime))
+}
+
+// Whether `app` (a name and version, e.g. "xdg-desktop-0.1") already failed on this version
+// of `path`.
+pub fn has_failed(path: &Path, app: &str) -> bool {
+    let Ok(path) = path.canonicalize() else {
+        return false;
+    };
+    let Ok(meta) = fs::metadata(&path) else {
+        return false;
+    };
+    let uri = path_to_uri(&path);
+
+    is_valid(&fail_path(&uri, app), &uri, mtime(&meta))
+}
+
+// Records that `app` cannot thumbnail `path`, so others need not try again until it changes.
+pub fn record_failure(path: &Path, app: &str) -> io::Result<()> {
+    let path = path.canonicalize()?;
+    let mtime = mtime(&fs::metadata(&path)?);
+    let uri = path_to_uri(&path);
+    let png = with_png_text(EMPTY_PNG, &[("Thumb::URI", uri.clone()), ("Thumb::MTime", mtime.to_string())]).unwrap();
+
+    write_thumbnail(&fail_path(&uri, app), &png)
+}
+
+// Scales the image at `path` into the cache, unless a valid thumbnail is there already.
+// Images smaller than `size` are kept at their own size, as the spec asks.
+#[cfg(feature = "raster")]
+pub fn generate(path: &Path, size: ThumbnailSize) -> io::Result<PathBuf> {
+    let to_io_error = |e: image::ImageError| io::Error::new(io::ErrorKind::InvalidData, e);
+    let path = path.canonicalize()?;
+    let meta = fs::metadata(&path)?;
+    let uri = path_to_uri(&path);
+    let dest = thumbnail_path(&uri, size);
+    if is_valid(&dest, &uri, mtime(&meta)) {
+        return Ok(dest);
+    }
+
+    let img = image::open(&path).map_err(to_io_error)?;
+    let (width, height) = (img.width(), img.height());
+    let img = if width.max(height) > size.pixels() { img.thumbnail(size.pixels(), size.pixels()) } else { img };
+    let mut png = vec![];
+    img.write_to(&mut io::Cursor::new(&mut png), image::ImageFormat::Png).map_err(to_io_error)?;
+    let text = [
+        ("Thumb::URI", uri),
+        ("Thumb::MTime", mtime(&meta).to_string()),
+        ("Thumb::Size", meta.len().to_string()),
+        ("Thumb::Image::Width", width.to_string()),
+        ("Thumb::Image::Height", height.to_string()),
+        ("Software", "xdg-desktop".to_string()),
+    ];
+    let png = with_png_text(&png, &text).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad PNG from encoder"))?;
+    write_thumbnail(&dest, &png)?;
+
+    Ok(dest)
+}
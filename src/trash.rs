@@ -0,0 +1,314 @@
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use crate::dirs;
+
+// A trash can: the home one under $XDG_DATA_HOME, or one at the top of another mount.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrashDir {
+    pub path: PathBuf,
+    // For per-mount trash cans, the mount point that Path= in .trashinfo is relative to.
+    topdir: Option<PathBuf>,
+}
+
+// A file in the trash.
+#[derive(Clone, Debug)]
+pub struct TrashItem {
+    // The name under files/ and, with .trashinfo appended, under info/.
+    pub id: String,
+    pub original_path: PathBuf,
+    // Local time, as written: "2004-08-31T22:32:08".
+    pub deletion_date: String,
+    pub trash_dir: TrashDir,
+}
+
+impl TrashItem {
+    // The trashed file itself.
+    pub fn path(&self) -> PathBuf {
+        self.trash_dir.files().join(&self.id)
+    }
+
+    fn info_path(&self) -> PathBuf {
+        self.trash_dir.info().join(self.id.clone() + ".trashinfo")
+    }
+}
+
+impl TrashDir {
+    pub fn home() -> Self {
+        Self { path: dirs::BaseDirs::new().data_home().join("Trash"), topdir: None }
+    }
+
+    pub fn files(&self) -> PathBuf {
+        self.path.join("files")
+    }
+
+    pub fn info(&self) -> PathBuf {
+        self.path.join("info")
+    }
+
+    // $topdir/.Trash/$uid if the admin made $topdir/.Trash a sticky, non-symlink dir, else
+    // $topdir/.Trash-$uid. The latter is only created when `create` is set. Either must be
+    // a real dir of the current user, so others cannot plant one, and is skipped otherwise.
+    fn for_topdir(topdir: &Path, create: bool) -> Option<Self> {
+        let uid = unsafe { libc::getuid() };
+        let usable = |path: &Path| {
+            is_own_dir(path, uid) || (create && dirs::create_private_dir(path).is_ok() && is_own_dir(path, uid))
+        };
+        let shared = topdir.join(".Trash");
+        let shared_ok = fs::symlink_metadata(&shared).is_ok_and(|meta| meta.is_dir() && meta.permissions().mode() & 0o1000 != 0);
+        if shared_ok {
+            let path = shared.join(uid.to_string());
+            if usable(&path) {
+                return Some(Self { path, topdir: Some(topdir.to_path_buf()) });
+            }
+        }
+        let path = topdir.join(format!(".Trash-{}", uid));
+        if usable(&path) {
+            return Some(Self { path, topdir: Some(topdir.to_path_buf()) });
+        }
+
+        None
+    }
+
+    fn items(&self) -> Vec<TrashItem> {
+        let Ok(read_dir) = self.info().read_dir() else {
+            return vec![];
+        };
+        read_dir.flatten().filter_map(|ent| {
+            let name = ent.file_name().into_string().ok()?;
+            let id = name.strip_suffix(".trashinfo")?.to_string();
+            let (path, deletion_date) = parse_trash_info(&fs::read_to_string(ent.path()).ok()?)?;
+            let original_path = match &self.topdir {
+                Some(topdir) => topdir.join(path),
+                None => path,
+            };
+
+            Some(TrashItem { id, original_path, deletion_date, trash_dir: self.clone() })
+        }).collect()
+    }
+}
+
+// Whether `path` itself, not a symlink to it, is a dir owned by `uid`.
+fn is_own_dir(path: &Path, uid: libc::uid_t) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|meta| meta.is_dir() && meta.uid() == uid)
+}
+
+// Path= is percent-encoded, like the path part of a URL.
+fn encode_path(path: &Path) -> String {
+    let mut result = String::new();
+    for &byte in path.as_os_str().as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            result.push(byte as char);
+        } else {
+            result += &format!("%{:02X}", byte);
+        }
+    }
+
+    result
+}
+
+fn decode_path(value: &str) -> PathBuf {
+    let bytes = value.as_bytes();
+    let mut result = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match hex {
+            Some(byte) if bytes[i] == b'%' => {
+                result.push(byte);
+                i += 3;
+            }
+            _ => {
+                result.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    PathBuf::from(OsString::from_vec(result))
+}
+
+fn parse_trash_info(content: &str) -> Option<(PathBuf, String)> {
+    let mut in_info = false;
+    let mut path = None;
+    let mut deletion_date = String::new();
+    for line in content.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_info = line == "[Trash Info]";
+        } else if let (true, Some((key, value))) = (in_info, line.split_once('=')) {
+            match key.trim() {
+                "Path" => path = Some(decode_path(value.trim())),
+                "DeletionDate" => deletion_date = value.trim().to_string(),
+                _ => {}
+            }
+        }
+    }
+
+    Some((path?, deletion_date))
+}
+
+fn deletion_date() -> String {
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        libc::localtime_r(&now, &mut tm);
+    }
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}", tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday, tm.tm_hour, tm.tm_min, tm.tm_sec)
+}
+
+// Mount points, from /proc/self/mounts with its octal escapes undone.
+fn mount_points() -> Vec<PathBuf> {
+    let Ok(content) = fs::read_to_string("/proc/self/mounts") else {
+        return vec![];
+    };
+    content.lines().filter_map(|line| line.split(' ').nth(1)).map(|field| {
+        let mut bytes = vec![];
+        let mut rest = field.as_bytes();
+        while let Some((&byte, tail)) = rest.split_first() {
+            let octal = tail.get(..3).and_then(|oct| u8::from_str_radix(std::str::from_utf8(oct).ok()?, 8).ok());
+            match octal {
+                Some(value) if byte == b'\\' => {
+                    bytes.push(value);
+                    rest = &tail[3..];
+                }
+                _ => {
+                    bytes.push(byte);
+                    rest = tail;
+                }
+            }
+        }
+        PathBuf::from(OsString::from_vec(bytes))
+    }).collect()
+}
+
+// The top of the mount holding `path`: the last ancestor on the same device.
+fn topdir(path: &Path, dev: u64) -> PathBuf {
+    let mut top = path.to_path_buf();
+    for dir in path.ancestors().skip(1) {
+        if fs::metadata(dir).ok().map(|meta| meta.dev()) != Some(dev) {
+            break;
+        }
+        top = dir.to_path_buf();
+    }
+
+    top
+}
+
+// The trash cans that exist: the home one and those of mounted filesystems.
+pub fn trash_dirs() -> Vec<TrashDir> {
+    let mut result = vec![TrashDir::home()];
+    for mount in mount_points() {
+        if let Some(trash) = TrashDir::for_topdir(&mount, false) {
+            if !result.contains(&trash) {
+                result.push(trash);
+            }
+        }
+    }
+
+    result
+}
+
+// Moves `path` to the trash can of its filesystem: the home one if it is on the same device
+// as $XDG_DATA_HOME, else one at the top of its mount.
+pub fn trash(path: &Path) -> io::Result<TrashItem> {
+    let meta = fs::symlink_metadata(path)?;
+    let name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Cannot trash {}", path.display())))?;
+    // Only the parent is resolved, so a symlink is trashed rather than its target.
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let path = parent.canonicalize()?.join(name);
+
+    let home = TrashDir::home();
+    let home_dev = home.path.ancestors().find_map(|dir| fs::metadata(dir).ok()).map(|meta| meta.dev());
+    let trash_dir = if home_dev == Some(meta.dev()) {
+        home
+    } else {
+        let topdir = topdir(&path, meta.dev());
+        TrashDir::for_topdir(&topdir, true)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::PermissionDenied, format!("No trash can on {}", topdir.display())))?
+    };
+    dirs::create_private_dir(&trash_dir.files())?;
+    dirs::create_private_dir(&trash_dir.info())?;
+
+    let original_path = match &trash_dir.topdir {
+        Some(topdir) => path.strip_prefix(topdir).unwrap_or(&path).to_path_buf(),
+        None => path.clone(),
+    };
+    let deletion_date = deletion_date();
+    let name = name.to_string_lossy();
+    // Claiming the info file first makes the name ours, even with other trashers running.
+    let mut n = 0;
+    loop {
+        n += 1;
+        let id = if n == 1 { name.to_string() } else { format!("{}.{}", name, n) };
+        let info_path = trash_dir.info().join(id.clone() + ".trashinfo");
+        let mut info = match OpenOptions::new().write(true).create_new(true).open(&info_path) {
+            Ok(info) => info,
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        };
+        if trash_dir.files().join(&id).symlink_metadata().is_ok() {
+            fs::remove_file(&info_path)?;
+            continue;
+        }
+        let written = write!(info, "[Trash Info]\nPath={}\nDeletionDate={}\n", encode_path(&original_path), deletion_date)
+            .and_then(|_| fs::rename(&path, trash_dir.files().join(&id)));
+        if let Err(err) = written {
+            let _ = fs::remove_file(&info_path);
+            return Err(err);
+        }
+
+        return Ok(TrashItem { id, original_path: path, deletion_date, trash_dir });
+    }
+}
+
+// Everything in every trash can.
+pub fn list() -> Vec<TrashItem> {
+    trash_dirs().iter().flat_map(TrashDir::items).collect()
+}
+
+// Moves an item back where it came from, failing if something else is there now.
+pub fn restore(item: &TrashItem) -> io::Result<()> {
+    if item.original_path.symlink_metadata().is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", item.original_path.display())));
+    }
+    if let Some(parent) = item.original_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(item.path(), &item.original_path)?;
+
+    fs::remove_file(item.info_path())
+}
+
+// Deletes an item for good.
+pub fn erase(item: &TrashItem) -> io::Result<()> {
+    let path = item.path();
+    match fs::symlink_metadata(&path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(&path)?,
+        Ok(_) => fs::remove_file(&path)?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+
+    fs::remove_file(item.info_path())
+}
+
+// Erases everything in every trash can.
+pub fn empty() -> io::Result<()> {
+    for item in list() {
+        erase(&item)?;
+    }
+    for trash_dir in trash_dirs() {
+        // The size cache describes files/, which is now empty.
+        match fs::remove_file(trash_dir.path.join("directorysizes")) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
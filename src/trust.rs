@@ -0,0 +1,102 @@
+use std::ffi::CString;
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Child;
+
+use crate::dirs::BaseDirs;
+use crate::menu::MenuIndex;
+use crate::open::Opener;
+
+// Set to "true" on a desktop file to trust it without the executable bit, e.g. on file
+// systems without permissions.
+pub const TRUSTED_XATTR: &str = "user.xdg.trusted";
+
+// What to do with a desktop file that is not trusted, as asked of the user.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrustDecision {
+    Refuse,
+    // Run it this time, leaving it untrusted.
+    RunOnce,
+    // Mark it trusted, then run it.
+    Trust,
+}
+
+// The dirs whose desktop files are installed rather than downloaded or dropped somewhere:
+// the applications dirs and the autostart dirs.
+fn standard_dirs(base_dirs: &BaseDirs) -> Vec<PathBuf> {
+    let applications = base_dirs.data_dirs_in_precedence().into_iter().map(|dir| dir.join("applications"));
+    let autostart = base_dirs.config_dirs_in_precedence().into_iter().map(|dir| dir.join("autostart"));
+
+    applications.chain(autostart).filter_map(|dir| dir.canonicalize().ok()).collect()
+}
+
+fn has_trusted_xattr(path: &Path) -> bool {
+    let (Ok(path), Ok(name)) = (CString::new(path.as_os_str().as_bytes()), CString::new(TRUSTED_XATTR)) else {
+        return false;
+    };
+    let mut value = [0u8; 16];
+    let len = unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), value.as_mut_ptr().cast(), value.len()) };
+
+    len > 0 && &value[..len as usize] == b"true"
+}
+
+// Whether `path` may run without asking, as GNOME and KDE decide: it is in a standard dir,
+// or it is executable and not writable by others, or it has the trusted xattr. Symlinks
+// are judged by their targets.
+pub fn is_trusted_for(base_dirs: &BaseDirs, path: &Path) -> bool {
+    let Ok(path) = path.canonicalize() else {
+        return false;
+    };
+    if standard_dirs(base_dirs).iter().any(|dir| path.starts_with(dir)) {
+        return true;
+    }
+    let Ok(meta) = fs::metadata(&path) else {
+        return false;
+    };
+    let mode = meta.permissions().mode();
+    if mode & 0o002 != 0 {
+        return false;
+    }
+
+    mode & 0o100 != 0 || has_trusted_xattr(&path)
+}
+
+pub fn is_trusted(path: &Path) -> bool {
+    is_trusted_for(&BaseDirs::new(), path)
+}
+
+// Trusts the desktop file by making it executable for its owner.
+pub fn mark_trusted(path: &Path) -> io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_mode(permissions.mode() | 0o100);
+
+    fs::set_permissions(path, permissions)
+}
+
+// Starts the application of the desktop file at `path`. Untrusted files run only if
+// `confirm` says so; otherwise this fails with PermissionDenied.
+pub fn launch_desktop_file_for(base_dirs: &BaseDirs, path: &Path, confirm: impl FnOnce(&Path) -> TrustDecision) -> io::Result<Vec<Child>> {
+    if !is_trusted_for(base_dirs, path) {
+        match confirm(path) {
+            TrustDecision::Refuse => {
+                return Err(Error::new(ErrorKind::PermissionDenied, format!("{} is not trusted", path.display())));
+            }
+            TrustDecision::RunOnce => {}
+            TrustDecision::Trust => mark_trusted(path)?,
+        }
+    }
+
+    let mut index = MenuIndex::with_base_dirs(None, base_dirs.clone());
+    let launch = index.add_desktop_file(path)
+        .and_then(|item| Opener::with_index(index).launch_with(item, &[]))
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("{} has no application to start", path.display())))?;
+
+    launch.spawn()
+}
+
+pub fn launch_desktop_file(path: &Path, confirm: impl FnOnce(&Path) -> TrustDecision) -> io::Result<Vec<Child>> {
+    launch_desktop_file_for(&BaseDirs::new(), path, confirm)
+}
@@ -0,0 +1,144 @@
+#[cfg(unix)]
+use std::ffi::OsString;
+#[cfg(unix)]
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+// Path characters left as they are in file URIs, as GLib's g_filename_to_uri does. Every
+// other byte, including spaces, '#', '%' and the bytes of non-ASCII names, is escaped.
+const PATH_SAFE: &[u8] = b"-._~/!$&'()*+,;=:@";
+
+// %XX-escapes every byte of `bytes` that is neither alphanumeric nor in `safe`.
+pub fn percent_encode(bytes: &[u8], safe: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len());
+    for &byte in bytes {
+        if byte.is_ascii_alphanumeric() || safe.contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded += &format!("%{:02X}", byte);
+        }
+    }
+
+    encoded
+}
+
+// Undoes %XX escapes. A '%' without two hex digits after it is kept as it is.
+pub fn percent_decode(text: &str) -> Vec<u8> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok()).and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    decoded
+}
+
+// The scheme of an RFC 3986 URL, lowercased.
+pub fn url_scheme(url: &str) -> Option<String> {
+    let (scheme, _) = url.split_once(':')?;
+    let mut chars = scheme.chars();
+    if !chars.next()?.is_ascii_alphabetic() || !chars.all(|ch| ch.is_ascii_alphanumeric() || "+-.".contains(ch)) {
+        return None;
+    }
+
+    Some(scheme.to_ascii_lowercase())
+}
+
+#[cfg(unix)]
+fn local_hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|byte| *byte == 0).unwrap_or(buf.len());
+
+    String::from_utf8(buf[..len].to_vec()).ok()
+}
+
+#[cfg(not(unix))]
+fn local_hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok()
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    path.as_os_str().as_bytes().to_vec()
+}
+
+// Drive paths such as C:\Users become /C:/Users, as in file:///C:/Users.
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    let path = path.to_string_lossy().replace('\\', "/");
+    match path.starts_with('/') {
+        true => path.into_bytes(),
+        false => format!("/{}", path).into_bytes(),
+    }
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+    Some(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+    let path = String::from_utf8(bytes).ok()?;
+    let is_drive = path.as_bytes().get(1).is_some_and(u8::is_ascii_alphabetic) && path.as_bytes().get(2) == Some(&b':');
+
+    Some(PathBuf::from(if is_drive { &path[1..] } else { path.as_str() }))
+}
+
+// Whether the host part of a file URI names this machine.
+fn is_local_host(host: &str) -> bool {
+    host.is_empty() || host.eq_ignore_ascii_case("localhost")
+        || local_hostname().is_some_and(|name| host.eq_ignore_ascii_case(&name))
+}
+
+// "file:///home/user/My%20File.txt" for an absolute path. Relative paths are resolved
+// against the current directory first.
+pub fn path_to_uri(path: &Path) -> String {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+
+    String::from("file://") + &percent_encode(&path_bytes(&path), PATH_SAFE)
+}
+
+// The local path of a file: URI, in either the file:///path or file:/path form. None for
+// other schemes, files on other hosts, and paths with an escaped '/' or NUL. A query or
+// fragment is ignored.
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    if url_scheme(uri)? != "file" {
+        return None;
+    }
+    let rest = &uri["file:".len()..];
+    let rest = rest.split(['?', '#']).next().unwrap_or(rest);
+    let path = match rest.strip_prefix("//") {
+        Some(authority) => {
+            let (host, path) = authority.split_at(authority.find('/').unwrap_or(authority.len()));
+            if !is_local_host(host) {
+                return None;
+            }
+            path
+        }
+        None => rest,
+    };
+    if !path.starts_with('/') {
+        return None;
+    }
+    let segments: Option<Vec<Vec<u8>>> = path.split('/').map(|segment| {
+        let decoded = percent_decode(segment);
+        (!decoded.contains(&b'/') && !decoded.contains(&0)).then_some(decoded)
+    }).collect();
+
+    path_from_bytes(segments?.join(&b'/'))
+}
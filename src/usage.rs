@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{ErrorKind, Result};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::dirs::BaseDirs;
+
+const DAY: u64 = 24 * 60 * 60;
+
+// Weights by days since the last launch, as Firefox ranks its history.
+const AGE_WEIGHTS: [(u64, f64); 4] = [(4, 100.0), (14, 70.0), (31, 50.0), (90, 30.0)];
+const OLD_WEIGHT: f64 = 10.0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub count: u64,
+    // Seconds since the epoch.
+    pub last_used: u64,
+}
+
+impl Usage {
+    // How often and how lately it was used, at `now` in seconds since the epoch.
+    pub fn frecency(&self, now: u64) -> f64 {
+        let days = now.saturating_sub(self.last_used) / DAY;
+        let weight = AGE_WEIGHTS.iter().find(|(max_days, _)| days < *max_days).map_or(OLD_WEIGHT, |(_, weight)| *weight);
+
+        self.count as f64 * weight
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs())
+}
+
+// Launch counts and times by desktop id, kept in $XDG_STATE_HOME/xdg-desktop/usage as
+// "<desktop id>\t<count>\t<last used>" lines. Nothing is recorded unless asked, see
+// LaunchOptions::record_usage.
+#[derive(Clone, Debug, Default)]
+pub struct UsageStore {
+    path: PathBuf,
+    pub entries: BTreeMap<String, Usage>,
+}
+
+impl UsageStore {
+    // A missing file is an empty store; malformed lines are skipped.
+    pub fn load_for(base_dirs: &BaseDirs) -> Result<Self> {
+        let path = base_dirs.app_state_dir("xdg-desktop").join("usage");
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+        let mut entries = BTreeMap::new();
+        for line in content.lines() {
+            let mut fields = line.split('\t');
+            let (Some(id), Some(Ok(count)), Some(Ok(last_used))) = (fields.next(), fields.next().map(str::parse), fields.next().map(str::parse)) else {
+                continue;
+            };
+            entries.insert(id.to_string(), Usage { count, last_used });
+        }
+
+        Ok(UsageStore { path, entries })
+    }
+
+    pub fn load() -> Result<Self> {
+        Self::load_for(&BaseDirs::new())
+    }
+
+    // Replaces the file by a rename.
+    pub fn save(&self) -> Result<()> {
+        let dir = self.path.parent().unwrap();
+        fs::create_dir_all(dir)?;
+        let content: String = self.entries.iter()
+            .map(|(id, usage)| format!("{}\t{}\t{}\n", id, usage.count, usage.last_used))
+            .collect();
+        let tmp = dir.join(format!(".usage.{}", std::process::id()));
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, &self.path).inspect_err(|_| {
+            let _ = fs::remove_file(&tmp);
+        })
+    }
+
+    // Counts a launch of `desktop_id` now. Call save() to keep it.
+    pub fn record(&mut self, desktop_id: &str) {
+        let usage = self.entries.entry(desktop_id.to_string()).or_default();
+        usage.count += 1;
+        usage.last_used = now();
+    }
+
+    pub fn forget(&mut self, desktop_id: &str) {
+        self.entries.remove(desktop_id);
+    }
+
+    // 0 for apps never launched.
+    pub fn frecency(&self, desktop_id: &str) -> f64 {
+        self.entries.get(desktop_id).map_or(0.0, |usage| usage.frecency(now()))
+    }
+
+    // Sorts `items` by the frecency of their desktop ids, highest first. Items of equal
+    // frecency, such as ones never launched, keep their order.
+    pub fn rank<T>(&self, items: &mut [T], desktop_id: impl Fn(&T) -> &str) {
+        let now = now();
+        let score = |item: &T| self.entries.get(desktop_id(item)).map_or(0.0, |usage| usage.frecency(now));
+        items.sort_by(|a, b| score(b).total_cmp(&score(a)));
+    }
+}
+
+// Records a launch of `desktop_id` in the user's store.
+pub fn record_launch(desktop_id: &str) -> Result<()> {
+    let mut store = UsageStore::load()?;
+    store.record(desktop_id);
+
+    store.save()
+}
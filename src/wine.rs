@@ -0,0 +1,206 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::Result;
+use std::path::{Path, PathBuf};
+
+use crate::desktop_parser::{DesktopFile, DesktopParserCallback};
+use crate::dirs::BaseDirs;
+
+// The programs that run Windows programs, as packaged by Wine, its distributions and Proton.
+// Native tools such as winetricks or winecfg do not count.
+const WINE_PROGRAMS: [&str; 9] = [
+    "wine", "wine64", "wine-stable", "wine64-stable", "wine-staging", "wine64-staging", "wine-development", "wine64-development",
+    "proton",
+];
+
+// Whether a desktop file was made by Wine or Proton for a Windows program: it runs wine or
+// proton, sets WINEPREFIX, is in the Wine category, or is in a wine dir such as
+// applications/wine/Programs, where Wine puts its start menu.
+pub fn is_wine_entry(exec: &str, categories: &str, path: &Path) -> bool {
+    let runs_wine = exec.split_whitespace().any(|word| {
+        let program = word.rsplit('/').next().unwrap_or(word);
+        WINE_PROGRAMS.contains(&program) || word.starts_with("WINEPREFIX=")
+    });
+    let in_category = categories.split(';').any(|category| category == "Wine" || category == "X-Wine");
+    let in_wine_dir = path.components().any(|component| component.as_os_str() == "wine" || component.as_os_str() == ".wine");
+
+    runs_wine || in_category || in_wine_dir
+}
+
+// The words of an Exec value as read from the file: string escapes are undone first, then
+// quoting and backslashes as the shell would.
+fn exec_words(exec: &str) -> Vec<String> {
+    let unescaped = exec.replace("\\s", " ").replace("\\\\", "\\");
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut in_quotes = false;
+    let mut chars = unescaped.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' => {
+                in_quotes = !in_quotes;
+                word.get_or_insert_default();
+            }
+            '\\' => word.get_or_insert_default().extend(chars.next()),
+            ' ' | '\t' if !in_quotes => words.extend(word.take()),
+            _ => word.get_or_insert_default().push(ch),
+        }
+    }
+    words.extend(word);
+
+    words
+}
+
+// The Windows program an entry made by Wine starts: the shortcut after "start.exe /Unix", or
+// the .exe it runs, with C:\ paths found in the dosdevices of WINEPREFIX or ~/.wine.
+pub fn target(exec: &str, home: &Path) -> Option<PathBuf> {
+    let words = exec_words(exec);
+    if let Some(at) = words.iter().position(|word| word == "/Unix") {
+        return words.get(at + 1).map(PathBuf::from);
+    }
+    let exe = words.iter().find(|word| word.to_ascii_lowercase().ends_with(".exe"))?;
+    if exe.starts_with('/') {
+        return Some(PathBuf::from(exe));
+    }
+    let (drive, rest) = exe.split_once(":\\").filter(|(drive, _)| drive.len() == 1)?;
+    let prefix = words.iter()
+        .find_map(|word| word.strip_prefix("WINEPREFIX="))
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home.join(".wine"));
+
+    Some(prefix.join("dosdevices").join(format!("{}:", drive.to_ascii_lowercase())).join(rest.replace('\\', "/")))
+}
+
+#[derive(Default)]
+struct WineEntryParser {
+    in_entry: bool,
+    key: String,
+    exec: String,
+    categories: String,
+}
+
+impl DesktopParserCallback for WineEntryParser {
+    fn on_section(&mut self, name: &[u8]) -> bool {
+        self.in_entry = name == b"Desktop Entry";
+
+        true
+    }
+    fn on_key(&mut self, key: &[u8]) -> bool {
+        self.key = String::from_utf8_lossy(key).trim().to_string();
+
+        true
+    }
+    fn on_value(&mut self, value: &[u8]) -> bool {
+        if !self.in_entry {
+            return true;
+        }
+        let value = String::from_utf8_lossy(value.trim_ascii()).into_owned();
+        match self.key.as_str() {
+            "Exec" => self.exec = value,
+            "Categories" => self.categories = value,
+            _ => {}
+        }
+
+        true
+    }
+}
+
+// The desktop files under `dir` and its subdirs. Dirs already in `visited`, by their
+// canonical paths, are skipped, so symlink loops end.
+fn desktop_files(dir: &Path, visited: &mut HashSet<PathBuf>, files: &mut Vec<PathBuf>) {
+    let Some(canonical) = dir.canonicalize().ok().filter(|canonical| visited.insert(canonical.clone())) else {
+        return;
+    };
+    let Ok(read_dir) = canonical.read_dir() else {
+        return;
+    };
+    for path in read_dir.flatten().map(|entry| dir.join(entry.file_name())) {
+        if path.is_dir() {
+            desktop_files(&path, visited, files);
+        } else if path.extension().is_some_and(|ext| ext == "desktop") {
+            files.push(path);
+        }
+    }
+}
+
+// The user's Wine entries whose program is gone, e.g. after uninstalling it or deleting the
+// prefix. Entries whose target cannot be told, such as Steam's, are kept.
+pub fn stale_entries_for(base_dirs: &BaseDirs) -> Vec<PathBuf> {
+    let mut files = vec![];
+    desktop_files(&base_dirs.data_home().join("applications"), &mut HashSet::new(), &mut files);
+    files.sort();
+
+    files.into_iter().filter(|path| {
+        let mut parser = WineEntryParser::default();
+        let Some(desktop_file) = File::open(path).ok().and_then(|file| DesktopFile::new(file).ok()) else {
+            return false;
+        };
+        desktop_file.parse(&mut parser);
+        is_wine_entry(&parser.exec, &parser.categories, path)
+            && target(&parser.exec, base_dirs.home()).is_some_and(|target| !target.exists())
+    }).collect()
+}
+
+pub fn stale_entries() -> Vec<PathBuf> {
+    stale_entries_for(&BaseDirs::new())
+}
+
+// Sets NoDisplay=true in the desktop file, replacing it by a rename.
+pub fn hide_entry(path: &Path) -> Result<()> {
+    let content = fs::read_to_string(path)?;
+    let mut lines = vec![];
+    let mut in_entry = false;
+    let mut hidden = false;
+    for line in content.lines() {
+        if line.starts_with('[') {
+            if in_entry && !hidden {
+                lines.push(String::from("NoDisplay=true"));
+                hidden = true;
+            }
+            in_entry = line.trim() == "[Desktop Entry]";
+        } else if in_entry && line.split('=').next().is_some_and(|key| key.trim() == "NoDisplay") {
+            lines.push(String::from("NoDisplay=true"));
+            hidden = true;
+            continue;
+        }
+        lines.push(line.to_string());
+    }
+    if in_entry && !hidden {
+        lines.push(String::from("NoDisplay=true"));
+    }
+
+    let filename = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = path.with_file_name(format!(".{}.{}", filename, std::process::id()));
+    fs::write(&tmp, lines.join("\n") + "\n")?;
+    fs::rename(&tmp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
+}
+
+// Hides the stale Wine entries and returns them.
+pub fn hide_stale_for(base_dirs: &BaseDirs) -> Result<Vec<PathBuf>> {
+    let stale = stale_entries_for(base_dirs);
+    for path in &stale {
+        hide_entry(path)?;
+    }
+
+    Ok(stale)
+}
+
+pub fn hide_stale() -> Result<Vec<PathBuf>> {
+    hide_stale_for(&BaseDirs::new())
+}
+
+// Deletes the stale Wine entries and returns them.
+pub fn purge_stale_for(base_dirs: &BaseDirs) -> Result<Vec<PathBuf>> {
+    let stale = stale_entries_for(base_dirs);
+    for path in &stale {
+        fs::remove_file(path)?;
+    }
+
+    Ok(stale)
+}
+
+pub fn purge_stale() -> Result<Vec<PathBuf>> {
+    purge_stale_for(&BaseDirs::new())
+}